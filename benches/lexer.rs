@@ -0,0 +1,25 @@
+// Timings of the lexer on generated netlists, run with "cargo bench"
+// Lexing time should grow linearly with the length of the source, so each doubling should take about twice as long
+
+use std::hint::black_box;
+use std::time::Instant;
+use osd::lang::Lexer;
+
+/// Returns a netlist of the given number of gate lines, each chaining on the previous one
+fn netlist(lines: usize) -> String {
+    let mut source = String::from("INPUTS a, b\nOUTPUTS w0\n");
+    for n in 1..lines {
+        source.push_str(&format!("AND gate_{} IN(w{}, a) OUT(w{}) # Gate number {}\n", n, n, n - 1, n));
+    }
+    source
+}
+
+fn main() {
+    for lines in [25_000, 50_000, 100_000] {
+        let source = netlist(lines);
+        let start = Instant::now();
+        black_box(Lexer::new(source).tokenize().unwrap());
+        let elapsed = start.elapsed();
+        println!("{:>7} lines {:>10.2?} ({:.0} ns per line)", lines, elapsed, elapsed.as_nanos() as f64 / lines as f64);
+    }
+}
@@ -0,0 +1,115 @@
+// Timings of the simulator on generated circuits, run with "cargo bench"
+// Each benchmark prints the time it took next to the time of the approach it improves on
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use osd::lang::Program;
+use osd::sim::Simulator;
+
+/// Runs a closure the given number of times and returns the total time taken
+fn time(runs: usize, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed()
+}
+
+/// Prints the time of an approach next to the time of its baseline
+fn report(name: &str, baseline: Duration, improved: Duration) {
+    println!("{:<40} {:>10.2?} -> {:>10.2?} ({:.1}x)", name, baseline, improved, baseline.as_secs_f64() / improved.as_secs_f64());
+}
+
+/// Returns the input values numbered by the bits of the given value, the first input being the lowest bit
+fn vector(program: &Program, value: u64) -> HashMap<String, bool> {
+    program.inputs.iter().enumerate()
+        .map(|(i, input)| (input.clone(), value >> i & 1 == 1))
+        .collect()
+}
+
+/// Returns a datapath of the given number of 8-bit ripple-carry adders, each adding the same two inputs
+fn adder_datapath(copies: usize) -> Program {
+    let mut source = String::from("SUBCIRCUIT fa\nINPUTS a, b, c\nOUTPUTS s, co\nXOR x1 IN(a, b) OUT(t)\nXOR x2 IN(t, c) OUT(s)\n\
+        AND a1 IN(t, c) OUT(u)\nAND a2 IN(a, b) OUT(w)\nOR o1 IN(u, w) OUT(co)\nEND\n");
+    source.push_str("SUBCIRCUIT add8\nINPUTS a[8], b[8], c\nOUTPUTS s[8], co\n");
+    for i in 0..8 {
+        let carry_in = if i == 0 { "c".to_string() } else { format!("k{}", i - 1) };
+        let carry_out = if i == 7 { "co".to_string() } else { format!("k{}", i) };
+        source.push_str(&format!("fa IN(a[{i}], b[{i}], {carry_in}) OUT(s[{i}], {carry_out})\n"));
+    }
+    source.push_str("END\nINPUTS x[8], y[8]\nOUTPUTS");
+    source.push_str(&(0..copies).map(|n| format!(" s{n}[8], c{n}")).collect::<Vec<_>>().join(","));
+    source.push('\n');
+    for n in 0..copies {
+        source.push_str(&format!("add8 IN(x[0:7], y[0:7], 0) OUT(s{n}[0:7], c{n})\n"));
+    }
+    source.parse().expect("generated source should parse")
+}
+
+/// Evaluates many copies of one adder with and without the subcircuit cache, reporting both the time and the number of subcircuits evaluated
+fn subcircuit_cache() {
+    let program = adder_datapath(64);
+    let vectors: Vec<HashMap<String, bool>> = (0..64).map(|value| vector(&program, value * 1021)).collect();
+    let uncached = Simulator::new(&program);
+    let cached = Simulator::new(&program).with_subcircuit_cache();
+    let baseline = time(4, || for inputs in &vectors {
+        black_box(uncached.evaluate(inputs).unwrap());
+    });
+    let improved = time(4, || for inputs in &vectors {
+        black_box(cached.evaluate(inputs).unwrap());
+    });
+    report("64 adders, subcircuit cache", baseline, improved);
+    println!("{:<40} {:>10} -> {:>10} subcircuit evaluations", "", uncached.subcircuit_evaluations(), cached.subcircuit_evaluations());
+}
+
+/// Returns a circuit of the given number of independent 16-input gate trees, which is as wide as it is shallow
+fn wide_circuit(trees: usize) -> Program {
+    let mut source = String::from("INPUTS a[16]\nOUTPUTS");
+    source.push_str(&(0..trees).map(|t| format!(" y{}", t)).collect::<Vec<_>>().join(","));
+    source.push('\n');
+    for t in 0..trees {
+        for g in 0..8 {
+            source.push_str(&format!("XOR t{t}_{g} IN(a[{}], a[{}]) OUT(t{t}_{g})\n", (2 * g + t) % 16, (2 * g + 1) % 16));
+        }
+        for g in 0..4 {
+            source.push_str(&format!("AND u{t}_{g} IN(t{t}_{}, t{t}_{}) OUT(u{t}_{g})\n", 2 * g, 2 * g + 1));
+        }
+        source.push_str(&format!("OR v{t}_0 IN(u{t}_0, u{t}_1) OUT(v{t}_0)\nOR v{t}_1 IN(u{t}_2, u{t}_3) OUT(v{t}_1)\n"));
+        source.push_str(&format!("NAND r{t} IN(v{t}_0, v{t}_1) OUT(y{t})\n"));
+    }
+    source.parse().expect("generated source should parse")
+}
+
+/// Evaluates a wide circuit, level by level in parallel with the parallel feature and in order without it
+/// Compare "cargo bench" with "cargo bench --features parallel"
+fn wide_evaluation() {
+    let program = wide_circuit(2000);
+    let simulator = Simulator::new(&program);
+    let inputs = vector(&program, 0xA5C3);
+    let elapsed = time(5, || {
+        black_box(simulator.evaluate(&inputs).unwrap());
+    });
+    let mode = if cfg!(feature = "parallel") { "parallel" } else { "serial" };
+    println!("{:<40} {:>10.2?}", format!("2000 gate trees, {}", mode), elapsed);
+}
+
+/// Evaluates every combination of the inputs of an adder datapath one at a time and as a batch
+fn batch_evaluation() {
+    let program = adder_datapath(4);
+    let simulator = Simulator::new(&program);
+    let vectors: Vec<HashMap<String, bool>> = (0..1 << 16).step_by(64).map(|value| vector(&program, value)).collect();
+    let baseline = time(1, || for inputs in &vectors {
+        black_box(simulator.evaluate(inputs).unwrap());
+    });
+    let improved = time(1, || {
+        black_box(simulator.evaluate_batch(&vectors).unwrap());
+    });
+    report("1024 vectors, evaluate_batch", baseline, improved);
+}
+
+fn main() {
+    subcircuit_cache();
+    wide_evaluation();
+    batch_evaluation();
+}
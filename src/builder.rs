@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use crate::lang::*;
+use crate::validate::SemanticError;
+
+/// Builds a Program in code instead of parsing it from source text
+/// Methods consume and return the builder so calls can be chained
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    subcircuits: HashMap<String, Subcircuit>, // Subcircuits defined so far, by name
+    inputs: Vec<String>,                      // Inputs of the circuit being built
+    outputs: Vec<String>,                     // Outputs of the circuit being built
+    constants: HashMap<String, bool>,         // Named constants of the circuit being built
+    components: Vec<Component>,               // Components of the circuit being built
+    aliases: Vec<Alias>,                      // Aliases of the circuit being built
+}
+
+impl ProgramBuilder {
+    /// Creates an empty builder
+    pub fn new() -> Self {
+        ProgramBuilder::default()
+    }
+
+    /// Declares an input of the circuit
+    pub fn input(mut self, name: &str) -> Self {
+        self.inputs.push(name.to_string());
+        self
+    }
+
+    /// Declares an output of the circuit
+    pub fn output(mut self, name: &str) -> Self {
+        self.outputs.push(name.to_string());
+        self
+    }
+
+    /// Declares a named constant signal, as a CONST line does in source text
+    /// Constants only exist in the main circuit, so they are ignored on a subcircuit body
+    pub fn constant(mut self, name: &str, value: bool) -> Self {
+        self.constants.insert(name.to_string(), value);
+        self
+    }
+
+    /// Adds a component reading the given inputs and driving the given outputs
+    /// As in source text, the input names "0" and "1" are constant low and high signals
+    pub fn gate(mut self, gate_type: GateType, identifier: &str, inputs: &[&str], outputs: &[&str]) -> Self {
+        let inputs = inputs.iter()
+            .map(|&input| match input {
+                "0" => Signal::Constant(false),
+                "1" => Signal::Constant(true),
+                wire => Signal::Wire(wire.to_string()),
+            })
+            .collect();
+        self.components.push(Component {
+            gate_type,
+            identifier: identifier.to_string(),
+            inputs,
+            outputs: outputs.iter().map(|output| output.to_string()).collect(),
+            location: BUILT_LOCATION,
+            comments: vec![],
+        });
+        self
+    }
+
+    /// Declares another name for a signal, as an ALIAS line does in source text
+    pub fn alias(mut self, name: &str, target: &str) -> Self {
+        self.aliases.push(Alias {
+            name: name.to_string(),
+            target: target.to_string(),
+            location: BUILT_LOCATION,
+        });
+        self
+    }
+
+    /// Defines a subcircuit whose ports and components are those of the given builder
+    /// Subcircuits defined on the body builder are added to the program as well
+    pub fn subcircuit(mut self, name: &str, body: ProgramBuilder) -> Self {
+        self.subcircuits.extend(body.subcircuits);
+        self.subcircuits.insert(name.to_string(), Subcircuit {
+            name: name.to_string(),
+            components: body.components,
+            output_locations: body.outputs.iter().map(|output| (output.clone(), BUILT_LOCATION)).collect(),
+            inputs: body.inputs,
+            outputs: body.outputs,
+            aliases: body.aliases,
+            location: BUILT_LOCATION,
+            comments: vec![],
+        });
+        self
+    }
+
+    /// Returns the program after running the semantic validation pass on it
+    pub fn build(self) -> Result<Program, Vec<SemanticError>> {
+        let program = Program {
+            subcircuits: self.subcircuits,
+            inputs: self.inputs,
+            constants: self.constants,
+            components: self.components,
+            output_locations: self.outputs.iter().map(|output| (output.clone(), BUILT_LOCATION)).collect(),
+            outputs: self.outputs,
+            aliases: self.aliases,
+            comments: vec![],
+        };
+        program.validate()?;
+        Ok(program)
+    }
+}
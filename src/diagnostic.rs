@@ -0,0 +1,172 @@
+use crate::lang::*;
+use crate::validate::SemanticError;
+
+/// Returns the text of a 1-based source line, splitting lines the same way the lexer does
+fn source_line(source: &str, line: usize) -> Option<&str> {
+    let mut start = 0;
+    let mut current = 1;
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\n' && c != '\r' {
+            continue;
+        }
+        if current == line {
+            return Some(&source[start..i]);
+        }
+        if c == '\r' && chars.peek().is_some_and(|&(_, next)| next == '\n') {
+            chars.next();
+        }
+        start = chars.peek().map_or(source.len(), |&(next, _)| next);
+        current += 1;
+    }
+    (current == line).then(|| &source[start..])
+}
+
+/// Replaces the tabs of a line with spaces up to the next tab stop, matching how the lexer counts columns
+fn expand_tabs(line: &str, tab_width: usize) -> String {
+    let mut expanded = String::new();
+    let mut column = 1;
+    for c in line.chars() {
+        if c == '\t' {
+            let next = next_tab_stop(column, tab_width);
+            expanded.push_str(&" ".repeat(next - column));
+            column = next;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+/// Formats an error message followed by the offending source line and a caret under the column
+pub fn render_error(source: &str, location: Location, message: &str) -> String {
+    let end = Location { line: location.line, column: location.column + 1 };
+    render_span(source, Span { start: location, end }, message)
+}
+
+/// Formats an error message followed by the first source line of the span, underlined with carets
+/// A span that continues onto later lines is underlined up to the end of its first line.
+/// Tabs are expanded to DEFAULT_TAB_WIDTH, the lexer's default, so the carets line up with the columns
+pub fn render_span(source: &str, span: Span, message: &str) -> String {
+    render_span_with_tab_width(source, span, message, DEFAULT_TAB_WIDTH)
+}
+
+/// Formats an error like render_span, for source lexed with LexerConfig::tab_width set to the given width
+pub fn render_span_with_tab_width(source: &str, span: Span, message: &str, tab_width: usize) -> String {
+    let start = span.start;
+    let mut rendered = format!("Error: {}\n --> {}\n", message, start);
+    let Some(line) = source_line(source, start.line) else {
+        return rendered;
+    };
+
+    let line = expand_tabs(line, tab_width);
+    let line_length = line.chars().count();
+    let end_column = if span.end.line == start.line { span.end.column } else { line_length + 1 };
+    let width = end_column.saturating_sub(start.column).max(1);
+
+    let gutter = start.line.to_string();
+    let padding = " ".repeat(gutter.len());
+    let indent = " ".repeat(start.column.saturating_sub(1));
+    rendered.push_str(&format!("{} |\n{} | {}\n{} | {}{}\n", padding, gutter, line, padding, indent, "^".repeat(width)));
+    rendered
+}
+
+impl LexerError {
+    /// Returns the part of the source the error covers
+    pub fn span(&self) -> Span {
+        match self {
+            LexerError::UnexpectedCharacter(_, span)
+            | LexerError::InvalidIdentifier(_, span)
+            | LexerError::UnterminatedComment(span)
+            | LexerError::InvalidNumber(_, span)
+            | LexerError::NonAsciiIdentifier(_, span) => *span,
+        }
+    }
+
+    /// Returns where in the source the error occurred
+    pub fn location(&self) -> Location {
+        self.span().start
+    }
+
+    /// Formats the error together with the source line it points at, underlining the offending text
+    pub fn render(&self, source: &str) -> String {
+        render_span(source, self.span(), &self.to_string())
+    }
+}
+
+impl ParseError {
+    /// Returns where in the source the error occurred
+    pub fn location(&self) -> Location {
+        match self {
+            ParseError::DuplicateComponent { second_location: location, .. }
+            | ParseError::DuplicateSubcircuit { second_location: location, .. }
+            | ParseError::UnexpectedEof { location, .. }
+            | ParseError::UnexpectedToken { location, .. }
+            | ParseError::ExpectedIdentifier { location, .. }
+            | ParseError::ExpectedSubcircuitName { location, .. }
+            | ParseError::InvalidToken { location, .. }
+            | ParseError::Expected { location, .. }
+            | ParseError::UnknownSubcircuit { location, .. }
+            | ParseError::PortCountMismatch { location, .. }
+            | ParseError::InvalidBusWidth { location, .. }
+            | ParseError::MissingSection { location, .. }
+            | ParseError::EmptySection { location, .. }
+            | ParseError::KeywordAsIdentifier { location, .. }
+            | ParseError::UnexpectedComma { location }
+            | ParseError::DuplicateConstant { location, .. }
+            | ParseError::ExpectedSingleSignal { location }
+            | ParseError::NestingTooDeep { location, .. }
+            | ParseError::LiteralWidthMismatch { location, .. }
+            | ParseError::TrailingTokens { location } => *location,
+        }
+    }
+
+    /// Formats the error together with the source line it points at
+    pub fn render(&self, source: &str) -> String {
+        render_error(source, self.location(), &self.to_string())
+    }
+}
+
+impl SemanticError {
+    /// Returns where in the source the error occurred, or None for errors that span several definitions
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            SemanticError::RecursiveSubcircuit { .. }
+            | SemanticError::InputOutputCollision { .. }
+            | SemanticError::UnknownOutput { .. }
+            | SemanticError::UnconnectedPort { .. } => None,
+            SemanticError::MultipleDrivers { locations, .. } => locations.get(1).copied(),
+            SemanticError::UndefinedSignal { location, .. }
+            | SemanticError::BadArity { location, .. }
+            | SemanticError::BadOutputCount { location, .. }
+            | SemanticError::DrivenInput { location, .. }
+            | SemanticError::DrivenConstant { location, .. }
+            | SemanticError::UnknownSubcircuit { location, .. }
+            | SemanticError::UndrivenOutput { location, .. }
+            | SemanticError::DuplicateSubcircuit { location, .. }
+            | SemanticError::AliasCollision { location, .. }
+            | SemanticError::UndefinedAliasTarget { location, .. }
+            | SemanticError::AliasCycle { location, .. } => Some(*location),
+        }
+    }
+
+    /// Formats the error together with the source line it points at, when it has a location
+    pub fn render(&self, source: &str) -> String {
+        match self.location() {
+            Some(location) => render_error(source, location, &self.to_string()),
+            None => format!("Error: {}\n", self),
+        }
+    }
+}
+
+impl CompileError {
+    /// Formats the error together with the source line it points at, or every semantic error in turn
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            CompileError::Lexer(err) => err.render(source),
+            CompileError::Parse(err) => err.render(source),
+            CompileError::Semantic(errors) => errors.iter().map(|err| err.render(source)).collect(),
+        }
+    }
+}
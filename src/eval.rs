@@ -1,130 +1,206 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::lang::*;
+use crate::sim::{SimError, Simulator};
 
-// Evaluator
+// Truth table
 
-fn evaluate_circuit(program: &Program, subcircuit_name: Option<&str>, input_values: HashMap<String, bool>) -> HashMap<String, bool> {
-    let mut values = input_values.clone();
-   
-    let components = if let Some(name) = subcircuit_name {
-        &program.subcircuits[name].components
-    } else {
-        &program.components
-    };
+/// Largest number of inputs a truth table is generated for (2^20 rows)
+pub const MAX_TRUTH_TABLE_INPUTS: usize = 20;
 
-    for component in components {
-        let input_values: Vec<bool> = component.inputs.iter()
-            .map(|input| *values.get(input).expect("Input not found"))
-            .collect();
+/// The outputs of a program for every combination of its inputs
+#[derive(Debug)]
+pub struct TruthTable {
+    pub inputs: Vec<String>,  // Input names in declaration order
+    pub outputs: Vec<String>, // Output names in declaration order
+    pub rows: Vec<TruthRow>,  // One row per input combination, counting up from all false
+}
 
-        let results = match &component.gate_type {
-            GateType::And => vec![eval_and(input_values)],
-            GateType::Or => vec![eval_or(input_values)],
-            GateType::Not => vec![eval_not(input_values[0])],
-            GateType::Nand => vec![eval_nand(input_values)],
-            GateType::Nor => vec![eval_nor(input_values)],
-            GateType::Xor => vec![eval_xor(input_values)],
-            GateType::Xnor => vec![eval_xnor(input_values)],
-            GateType::Subcircuit(subcircuit_name) => {
-                let subcircuit = &program.subcircuits[subcircuit_name];
-               
-                let mut subcircuit_inputs = HashMap::new();
-                for (i, input_name) in subcircuit.inputs.iter().enumerate() {
-                    subcircuit_inputs.insert(
-                        input_name.clone(),
-                        input_values[i]
-                    );
-                }
-               
-                let subcircuit_results = evaluate_circuit(
-                    program,
-                    Some(subcircuit_name),
-                    subcircuit_inputs
-                );
-               
-                subcircuit.outputs.iter()
-                    .map(|output_name| *subcircuit_results.get(output_name)
-                        .expect("Subcircuit output not found"))
-                    .collect()
-            }
-        };
+/// A single row of a truth table, with values in the same order as the table's names
+#[derive(Debug)]
+pub struct TruthRow {
+    pub inputs: Vec<bool>,
+    pub outputs: Vec<bool>,
+}
 
-        for (output_name, result) in component.outputs.iter().zip(results.iter()) {
-            values.insert(output_name.clone(), *result);
+impl Program {
+    /// Simulates the program for all 2^n input combinations
+    /// Returns SimError::TooManyInputs for programs with more than MAX_TRUTH_TABLE_INPUTS inputs
+    pub fn truth_table(&self) -> Result<TruthTable, SimError> {
+        if self.inputs.len() > MAX_TRUTH_TABLE_INPUTS {
+            return Err(SimError::TooManyInputs { count: self.inputs.len(), limit: MAX_TRUTH_TABLE_INPUTS });
         }
+
+        let rows = self.truth_table_iter().collect::<Result<Vec<TruthRow>, SimError>>()?;
+        Ok(TruthTable { inputs: self.inputs.clone(), outputs: self.outputs.clone(), rows })
     }
-    values
-}
 
-fn eval_and(inputs: Vec<bool>) -> bool {
-    inputs.iter().all(|&x| x)
+    /// Yields the rows of the truth table one at a time, simulating each combination only when it is requested
+    /// The rows come in the same order as in truth_table, but are never all held in memory, so there is no
+    /// MAX_TRUTH_TABLE_INPUTS limit. The iterator stops after the first error, which is SimError::TooManyInputs
+    /// when the number of rows does not fit in a usize
+    pub fn truth_table_iter(&self) -> impl Iterator<Item = Result<TruthRow, SimError>> + '_ {
+        let count = self.inputs.len();
+        let limit = usize::BITS as usize - 1;
+        let mut error = (count > limit).then_some(SimError::TooManyInputs { count, limit });
+        let rows = if error.is_some() { 0 } else { 1 << count };
+        let simulator = Simulator::new(self);
+        let mut next = 0;
+
+        std::iter::from_fn(move || {
+            if let Some(err) = error.take() {
+                next = rows;
+                return Some(Err(err));
+            }
+            if next == rows {
+                return None;
+            }
+            let combination = input_combination(next, count);
+            next += 1;
+
+            let input_values: HashMap<String, bool> = self.inputs.iter().cloned()
+                .zip(combination.iter().copied())
+                .collect();
+            let row = simulator.evaluate(&input_values).map(|results| TruthRow {
+                inputs: combination,
+                outputs: self.outputs.iter().map(|o| results[o]).collect(),
+            });
+            if row.is_err() {
+                next = rows;
+            }
+            Some(row)
+        })
+    }
 }
 
-fn eval_or(inputs: Vec<bool>) -> bool {
-    inputs.iter().any(|&x| x)
-}
+// Equivalence
 
-fn eval_not(input: bool) -> bool {
-    !input
-}
+impl Program {
+    /// Returns true if both programs declare the same inputs and outputs and drive the same outputs
+    /// for every combination of inputs. Ports are matched by name, so their declaration order may differ
+    /// Returns false without simulating if the port names differ, and SimError::TooManyInputs
+    /// for programs with more than MAX_TRUTH_TABLE_INPUTS inputs
+    pub fn equivalent(&self, other: &Program) -> Result<bool, SimError> {
+        let same_ports = |mine: &[String], theirs: &[String]| {
+            mine.iter().collect::<HashSet<_>>() == theirs.iter().collect::<HashSet<_>>()
+        };
+        if !same_ports(&self.inputs, &other.inputs) || !same_ports(&self.outputs, &other.outputs) {
+            return Ok(false);
+        }
+        if self.inputs.len() > MAX_TRUTH_TABLE_INPUTS {
+            return Err(SimError::TooManyInputs { count: self.inputs.len(), limit: MAX_TRUTH_TABLE_INPUTS });
+        }
 
-fn eval_nand(inputs: Vec<bool>) -> bool {
-    !eval_and(inputs)
+        let simulator = Simulator::new(self);
+        let other_simulator = Simulator::new(other);
+        for combination in generate_input_combinations(self.inputs.len()) {
+            let input_values: HashMap<String, bool> = self.inputs.iter().cloned()
+                .zip(combination)
+                .collect();
+            let results = simulator.evaluate(&input_values)?;
+            let other_results = other_simulator.evaluate(&input_values)?;
+            if self.outputs.iter().any(|output| results[output] != other_results[output]) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
 }
 
-fn eval_nor(inputs: Vec<bool>) -> bool {
-    !eval_or(inputs)
-}
+// Sampling
 
-fn eval_xor(inputs: Vec<bool>) -> bool {
-    inputs.iter().fold(false, |acc, &x| acc ^ x)
+/// A small reproducible random number generator (SplitMix64), so samples only depend on their seed
+struct SampleRng {
+    state: u64,
 }
 
-fn eval_xnor(inputs: Vec<bool>) -> bool {
-    !eval_xor(inputs)
+impl SampleRng {
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
 }
 
-// Printer
+/// An assignment of input values together with the outputs the program drives for it
+pub type Sample = (HashMap<String, bool>, HashMap<String, bool>);
+
+impl Simulator<'_> {
+    /// Evaluates the program for n random input assignments and returns each assignment with its outputs
+    /// The assignments only depend on the seed and the declared inputs, so the same seed always gives the same
+    /// samples. Unlike truth_table this works for any number of inputs, and assignments may repeat
+    pub fn sample(&self, n: usize, seed: u64) -> Result<Vec<Sample>, SimError> {
+        let mut rng = SampleRng { state: seed };
+        let mut samples = Vec::with_capacity(n);
+        for _ in 0..n {
+            let inputs: HashMap<String, bool> = self.program().inputs.iter()
+                .map(|input| (input.clone(), rng.next() >> 63 == 1))
+                .collect();
+            let outputs = self.evaluate(&inputs)?;
+            samples.push((inputs, outputs));
+        }
+        Ok(samples)
+    }
+}
 
-pub fn print_truth_table(program: &Program) {
-    let input_combinations = generate_input_combinations(program.inputs.len());
+// CSV
 
-    let header: Vec<String> = program.inputs.iter().cloned().collect();
-    let header_str = header.join(", ");
-    println!("{}, {}", header_str, program.outputs.join(", "));
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
 
-    for combination in input_combinations {
-        let mut input_values = HashMap::new();
-        for (i, input) in program.inputs.iter().enumerate() {
-            input_values.insert(input.clone(), combination[i]);
+impl TruthTable {
+    /// Renders the table as CSV, with a header of input then output names and one row of 0/1 values per combination
+    /// Columns follow the declared input and output order, and rows keep the table's order
+    pub fn to_csv(&self) -> String {
+        let header: Vec<String> = self.inputs.iter().chain(self.outputs.iter()).map(|name| csv_field(name)).collect();
+        let mut csv = header.join(",");
+        csv.push('\n');
+
+        for row in &self.rows {
+            let values: Vec<String> = row.inputs.iter()
+                .chain(row.outputs.iter())
+                .map(|&value| (value as u8).to_string())
+                .collect();
+            csv.push_str(&values.join(","));
+            csv.push('\n');
         }
+        csv
+    }
+}
 
-        let results = evaluate_circuit(program, None, input_values);
+// Printer
 
-        let input_str: Vec<String> = program.inputs.iter()
-            .map(|i| results[i].to_string())
-            .collect();
-        let input_str = input_str.join(", ");
+pub fn print_truth_table(program: &Program) -> Result<(), SimError> {
+    let table = program.truth_table()?;
+    println!("{}, {}", table.inputs.join(", "), table.outputs.join(", "));
 
-        let output_str: Vec<String> = program.outputs.iter()
-            .map(|o| results[o].to_string())
+    for row in &table.rows {
+        let values: Vec<String> = row.inputs.iter()
+            .chain(row.outputs.iter())
+            .map(|value| value.to_string())
             .collect();
-        let output_str = output_str.join(", ");
-
-        println!("{}, {}", input_str, output_str);
+        println!("{}", values.join(", "));
     }
+    Ok(())
 }
-
 fn generate_input_combinations(n: usize) -> Vec<Vec<bool>> {
     let mut combinations = Vec::new();
     let num_combinations = 2_usize.pow(n as u32);
 
     for i in 0..num_combinations {
-        let mut combination = Vec::new();
-        for j in 0..n {
-            combination.push((i >> (n - 1 - j)) & 1 == 1);
-        }
-        combinations.push(combination);
+        combinations.push(input_combination(i, n));
     }
     combinations
 }
+
+/// Returns the i-th of the 2^n input combinations, with the first input as the most significant bit
+fn input_combination(i: usize, n: usize) -> Vec<bool> {
+    (0..n).map(|j| (i >> (n - 1 - j)) & 1 == 1).collect()
+}
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::lang::*;
+use crate::logic::Logic;
+use crate::sim::{SimError, Simulator};
+use crate::transform::HIERARCHY_SEPARATOR;
+
+// Graphviz
+
+/// Escapes the characters that end or escape a quoted Graphviz string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes a string for use as a Graphviz ID or label
+fn dot_quote(text: &str) -> String {
+    format!("\"{}\"", dot_escape(text))
+}
+
+/// Escapes the characters that have a meaning inside Graphviz record labels
+fn dot_record_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>' | '"' | '\\' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Program {
+    /// Renders the main circuit as a Graphviz digraph
+    /// Inputs, outputs and components appear in declaration order so the output is deterministic.
+    /// Aliases are resolved first, so readers of an alias are connected to the signal it names
+    pub fn to_dot(&self) -> String {
+        if self.has_aliases() {
+            return self.resolve_aliases().to_dot();
+        }
+        let mut dot = String::from("digraph circuit {\n    rankdir=LR;\n");
+
+        // Where each signal comes from, as a Graphviz node or node:port reference
+        let mut drivers: HashMap<&str, String> = HashMap::new();
+
+        for input in &self.inputs {
+            let id = dot_quote(&format!("input:{}", input));
+            dot.push_str(&format!("    {} [shape=circle, label={}];\n", id, dot_quote(input)));
+            drivers.insert(input, id);
+        }
+
+        let mut constants: Vec<&String> = self.constants.keys().collect();
+        constants.sort();
+        for name in constants {
+            let id = dot_quote(&format!("const:{}", name));
+            let label = format!("{} = {}", name, self.constants[name] as u8);
+            dot.push_str(&format!("    {} [shape=plaintext, label={}];\n", id, dot_quote(&label)));
+            drivers.insert(name, id);
+        }
+
+        for output in &self.outputs {
+            let id = dot_quote(&format!("output:{}", output));
+            dot.push_str(&format!("    {} [shape=doublecircle, label={}];\n", id, dot_quote(output)));
+        }
+
+        for component in &self.components {
+            let id = dot_quote(&component.identifier);
+            match &component.gate_type {
+                GateType::Subcircuit(name) => {
+                    let subcircuit = self.subcircuits.get(name);
+                    let port_names = |ports: Option<&Vec<String>>, prefix: char, count: usize| -> String {
+                        (0..count)
+                            .map(|i| {
+                                let label = ports.and_then(|p| p.get(i)).map_or(String::new(), |p| dot_record_escape(p));
+                                format!("<{}{}> {}", prefix, i, label)
+                            })
+                            .collect::<Vec<_>>()
+                            .join("|")
+                    };
+                    let label = format!(
+                        "{{{{{}}}|{}\\n{}|{{{}}}}}",
+                        port_names(subcircuit.map(|s| &s.inputs), 'i', component.inputs.len()),
+                        dot_record_escape(name),
+                        dot_record_escape(&component.identifier),
+                        port_names(subcircuit.map(|s| &s.outputs), 'o', component.outputs.len()),
+                    );
+                    dot.push_str(&format!("    {} [shape=record, label=\"{}\"];\n", id, label));
+                    for (i, output) in component.outputs.iter().enumerate() {
+                        drivers.insert(output, format!("{}:o{}", id, i));
+                    }
+                }
+                gate_type => {
+                    let label = format!("{}\\n{}", dot_escape(gate_type.keyword()), dot_escape(&component.identifier));
+                    dot.push_str(&format!("    {} [shape=box, label=\"{}\"];\n", id, label));
+                    for output in &component.outputs {
+                        drivers.insert(output, id.clone());
+                    }
+                }
+            }
+        }
+
+        let uses_constant = |value: bool| self.components.iter()
+            .flat_map(|c| c.inputs.iter())
+            .any(|input| matches!(input, Signal::Constant(v) if *v == value));
+        for value in [false, true] {
+            if uses_constant(value) {
+                let text = (value as u8).to_string();
+                dot.push_str(&format!("    {} [shape=plaintext, label={}];\n", dot_quote(&format!("constant:{}", text)), dot_quote(&text)));
+            }
+        }
+
+        for component in &self.components {
+            let id = dot_quote(&component.identifier);
+            let is_subcircuit = matches!(component.gate_type, GateType::Subcircuit(_));
+            for (i, input) in component.inputs.iter().enumerate() {
+                let target = if is_subcircuit { format!("{}:i{}", id, i) } else { id.clone() };
+                match input {
+                    Signal::Wire(name) => {
+                        if let Some(source) = drivers.get(name.as_str()) {
+                            dot.push_str(&format!("    {} -> {} [label={}];\n", source, target, dot_quote(name)));
+                        }
+                    }
+                    Signal::Constant(value) => {
+                        let source = dot_quote(&format!("constant:{}", *value as u8));
+                        dot.push_str(&format!("    {} -> {};\n", source, target));
+                    }
+                }
+            }
+        }
+
+        for output in &self.outputs {
+            if let Some(source) = drivers.get(output.as_str()) {
+                let target = dot_quote(&format!("output:{}", output));
+                dot.push_str(&format!("    {} -> {};\n", source, target));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Mermaid
+
+/// Turns a name into a Mermaid node ID made of ASCII letters, digits and '_', behind a prefix for its kind of node
+/// '_' is doubled and other characters become their code point in hex between underscores, so distinct names never clash
+/// and no ID can be a Mermaid keyword such as "end"
+fn mermaid_id(prefix: &str, name: &str) -> String {
+    let mut id = format!("{}_", prefix);
+    for c in name.chars() {
+        match c {
+            '_' => id.push_str("__"),
+            c if c.is_ascii_alphanumeric() => id.push(c),
+            c => id.push_str(&format!("_{:x}_", c as u32)),
+        }
+    }
+    id
+}
+
+/// Quotes a Mermaid label, writing quotes and the characters that start an entity as entity codes
+fn mermaid_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('#', "#35;").replace('"', "#quot;"))
+}
+
+impl Program {
+    /// Renders the main circuit as a Mermaid flowchart, which Markdown viewers such as GitHub draw directly
+    /// Inputs and outputs are circles, components are boxes labelled with their type and identifier, and every
+    /// connection is an edge labelled with its signal. Nodes and edges appear in declaration order,
+    /// so the output is deterministic. Aliases are resolved first, as for to_dot
+    pub fn to_mermaid(&self) -> String {
+        if self.has_aliases() {
+            return self.resolve_aliases().to_mermaid();
+        }
+        let mut mermaid = String::from("flowchart LR\n");
+
+        // Node ID of the input, constant or component driving each signal
+        let mut drivers: HashMap<&str, String> = HashMap::new();
+
+        for input in &self.inputs {
+            let id = mermaid_id("input", input);
+            mermaid.push_str(&format!("    {}(({}))\n", id, mermaid_quote(input)));
+            drivers.insert(input, id);
+        }
+
+        let mut constants: Vec<&String> = self.constants.keys().collect();
+        constants.sort();
+        for name in constants {
+            let id = mermaid_id("const", name);
+            let label = format!("{} = {}", name, self.constants[name] as u8);
+            mermaid.push_str(&format!("    {}[{}]\n", id, mermaid_quote(&label)));
+            drivers.insert(name, id);
+        }
+
+        for output in &self.outputs {
+            mermaid.push_str(&format!("    {}((({})))\n", mermaid_id("output", output), mermaid_quote(output)));
+        }
+
+        for component in &self.components {
+            let id = mermaid_id("gate", &component.identifier);
+            let (open, close) = match &component.gate_type {
+                GateType::Subcircuit(_) => ("[[", "]]"),
+                _ => ("[", "]"),
+            };
+            let label = format!("{}<br/>{}", component.gate_type.keyword(), component.identifier);
+            mermaid.push_str(&format!("    {}{}{}{}\n", id, open, mermaid_quote(&label), close));
+            for output in &component.outputs {
+                drivers.insert(output, id.clone());
+            }
+        }
+
+        for value in [false, true] {
+            let used = self.components.iter()
+                .flat_map(|c| c.inputs.iter())
+                .any(|input| matches!(input, Signal::Constant(v) if *v == value));
+            if used {
+                let text = (value as u8).to_string();
+                mermaid.push_str(&format!("    {}[{}]\n", mermaid_id("constant", &text), mermaid_quote(&text)));
+            }
+        }
+
+        for component in &self.components {
+            let target = mermaid_id("gate", &component.identifier);
+            for input in &component.inputs {
+                match input {
+                    Signal::Wire(name) => {
+                        if let Some(source) = drivers.get(name.as_str()) {
+                            mermaid.push_str(&format!("    {} -->|{}| {}\n", source, mermaid_quote(name), target));
+                        }
+                    }
+                    Signal::Constant(value) => {
+                        let source = mermaid_id("constant", &(*value as u8).to_string());
+                        mermaid.push_str(&format!("    {} --> {}\n", source, target));
+                    }
+                }
+            }
+        }
+
+        for output in &self.outputs {
+            if let Some(source) = drivers.get(output.as_str()) {
+                mermaid.push_str(&format!("    {} --> {}\n", source, mermaid_id("output", output)));
+            }
+        }
+        mermaid
+    }
+}
+
+// Verilog
+
+/// Name of the Verilog module generated for the main circuit
+pub const VERILOG_TOP_MODULE: &str = "top";
+
+/// Represents possible errors that can occur while exporting a program
+#[derive(Debug)]
+pub enum ExportError {
+    UnknownSubcircuit(String),
+    ModuleNameCollision(String),
+    BadArity { component: String, found: usize },
+    BadOutputCount { component: String, found: usize },
+}
+
+impl std::error::Error for ExportError {}
+
+/// Implements Display trait for ExportError to provide human-readable error messages
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::UnknownSubcircuit(name) =>
+                write!(f, "Unknown subcircuit '{}'", name),
+            ExportError::ModuleNameCollision(name) =>
+                write!(f, "Subcircuit '{}' collides with the generated top-level module name", name),
+            ExportError::BadArity { component, found } =>
+                write!(f, "Component '{}' cannot be exported with {} input(s)", component, found),
+            ExportError::BadOutputCount { component, found } =>
+                write!(f, "Component '{}' cannot be exported with {} output(s)", component, found),
+        }
+    }
+}
+
+const VERILOG_KEYWORDS: &[&str] = &[
+    "always", "and", "assign", "begin", "buf", "case", "default", "else", "end", "endcase",
+    "endmodule", "for", "function", "if", "initial", "inout", "input", "integer", "module",
+    "nand", "nor", "not", "or", "output", "parameter", "reg", "supply0", "supply1", "wire",
+    "xnor", "xor",
+];
+
+/// Returns a Verilog identifier for a signal or module name
+/// Names that are not plain Verilog identifiers become escaped identifiers
+fn verilog_ident(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !VERILOG_KEYWORDS.contains(&name);
+    if plain {
+        name.to_string()
+    } else {
+        format!("\\{} ", name)
+    }
+}
+
+/// Returns the Verilog expression for a component input
+fn verilog_signal(signal: &Signal) -> String {
+    match signal {
+        Signal::Wire(name) => verilog_ident(name),
+        Signal::Constant(value) => format!("1'b{}", *value as u8),
+    }
+}
+
+/// Writes one Verilog module with the given ports, named constants and components
+fn write_verilog_module(
+    program: &Program,
+    name: &str,
+    inputs: &[String],
+    outputs: &[String],
+    constants: &HashMap<String, bool>,
+    components: &[Component],
+) -> Result<String, ExportError> {
+    let ports: Vec<String> = inputs.iter().chain(outputs.iter()).map(|p| verilog_ident(p)).collect();
+    let mut verilog = format!("module {}({});\n", verilog_ident(name), ports.join(", "));
+
+    if !inputs.is_empty() {
+        let names: Vec<String> = inputs.iter().map(|p| verilog_ident(p)).collect();
+        verilog.push_str(&format!("    input {};\n", names.join(", ")));
+    }
+    if !outputs.is_empty() {
+        let names: Vec<String> = outputs.iter().map(|p| verilog_ident(p)).collect();
+        verilog.push_str(&format!("    output {};\n", names.join(", ")));
+    }
+
+    // Flip-flop and latch outputs become regs, every other signal becomes a wire, with constants in name order
+    // followed by the remaining signals in order of first appearance
+    let regs: Vec<&str> = components.iter()
+        .filter(|c| matches!(c.gate_type, GateType::Dff | GateType::SrLatch | GateType::DLatch))
+        .flat_map(|c| c.outputs.iter().map(String::as_str))
+        .collect();
+    let mut constant_names: Vec<&str> = constants.keys().map(String::as_str).collect();
+    constant_names.sort();
+    let mut wires: Vec<&str> = constant_names.iter().copied()
+        .filter(|constant| !outputs.iter().any(|p| p == constant))
+        .collect();
+    for component in components {
+        let signals = component.inputs.iter().filter_map(Signal::wire)
+            .chain(component.outputs.iter().map(String::as_str));
+        for signal in signals {
+            let is_port = inputs.iter().chain(outputs.iter()).any(|p| p == signal);
+            if !is_port && !regs.contains(&signal) && !wires.contains(&signal) {
+                wires.push(signal);
+            }
+        }
+    }
+    if !regs.is_empty() {
+        let names: Vec<String> = regs.iter().map(|r| verilog_ident(r)).collect();
+        verilog.push_str(&format!("    reg {};\n", names.join(", ")));
+    }
+    if !wires.is_empty() {
+        let names: Vec<String> = wires.iter().map(|w| verilog_ident(w)).collect();
+        verilog.push_str(&format!("    wire {};\n", names.join(", ")));
+    }
+    verilog.push('\n');
+
+    for constant in constant_names {
+        verilog.push_str(&format!("    assign {} = 1'b{};\n", verilog_ident(constant), constants[constant] as u8));
+    }
+    for component in components {
+        let operands: Vec<String> = component.inputs.iter().map(verilog_signal).collect();
+        let expression = match &component.gate_type {
+            gate_type @ (GateType::And | GateType::Or | GateType::Xor) =>
+                operands.join(&format!(" {} ", gate_type.symbol())),
+            gate_type @ (GateType::Nand | GateType::Nor | GateType::Xnor) =>
+                format!("~({})", operands.join(&format!(" {} ", gate_type.symbol().trim_start_matches('~')))),
+            gate_type @ (GateType::Not | GateType::Buf) => match operands.as_slice() {
+                [operand] => format!("{}{}", gate_type.symbol(), operand),
+                _ => return Err(ExportError::BadArity {
+                    component: component.identifier.clone(),
+                    found: operands.len(),
+                }),
+            },
+            GateType::Tri => match operands.as_slice() {
+                [data, enable] => format!("{} ? {} : 1'bz", enable, data),
+                _ => return Err(ExportError::BadArity {
+                    component: component.identifier.clone(),
+                    found: operands.len(),
+                }),
+            },
+            GateType::Dff => {
+                let [data, clock] = operands.as_slice() else {
+                    return Err(ExportError::BadArity {
+                        component: component.identifier.clone(),
+                        found: operands.len(),
+                    });
+                };
+                for output in &component.outputs {
+                    verilog.push_str(&format!("    always @(posedge {}) {} <= {};\n", clock, verilog_ident(output), data));
+                }
+                continue;
+            }
+            gate_type @ (GateType::SrLatch | GateType::DLatch) => {
+                let [first, second] = operands.as_slice() else {
+                    return Err(ExportError::BadArity {
+                        component: component.identifier.clone(),
+                        found: operands.len(),
+                    });
+                };
+                for output in &component.outputs {
+                    let output = verilog_ident(output);
+                    let body = match gate_type {
+                        GateType::SrLatch => format!("if ({}) {} <= 1'b1; else if ({}) {} <= 1'b0;", first, output, second, output),
+                        _ => format!("if ({}) {} <= {};", second, output, first),
+                    };
+                    verilog.push_str(&format!("    always @(*) {}\n", body));
+                }
+                continue;
+            }
+            gate_type @ (GateType::HalfAdder | GateType::FullAdder) => {
+                if operands.len() != gate_type.min_inputs() {
+                    return Err(ExportError::BadArity {
+                        component: component.identifier.clone(),
+                        found: operands.len(),
+                    });
+                }
+                let [sum, carry] = component.outputs.as_slice() else {
+                    return Err(ExportError::BadOutputCount {
+                        component: component.identifier.clone(),
+                        found: component.outputs.len(),
+                    });
+                };
+                verilog.push_str(&format!(
+                    "    assign {{{}, {}}} = {};\n",
+                    verilog_ident(carry),
+                    verilog_ident(sum),
+                    operands.join(" + "),
+                ));
+                continue;
+            }
+            GateType::Subcircuit(subcircuit_name) => {
+                let subcircuit = program.subcircuits.get(subcircuit_name)
+                    .ok_or_else(|| ExportError::UnknownSubcircuit(subcircuit_name.clone()))?;
+                let connections: Vec<String> = subcircuit.inputs.iter().zip(operands.iter())
+                    .map(|(port, operand)| format!(".{}({})", verilog_ident(port), operand))
+                    .chain(subcircuit.outputs.iter().zip(component.outputs.iter())
+                        .map(|(port, wire)| format!(".{}({})", verilog_ident(port), verilog_ident(wire))))
+                    .collect();
+                verilog.push_str(&format!(
+                    "    {} {} ({});\n",
+                    verilog_ident(subcircuit_name),
+                    verilog_ident(&component.identifier),
+                    connections.join(", "),
+                ));
+                continue;
+            }
+        };
+
+        for output in &component.outputs {
+            verilog.push_str(&format!("    assign {} = {};\n", verilog_ident(output), expression));
+        }
+    }
+
+    verilog.push_str("endmodule\n");
+    Ok(verilog)
+}
+
+impl Program {
+    /// Renders the program as Verilog, with one module per subcircuit followed by the top module
+    /// Subcircuit modules appear in name order and the main circuit becomes VERILOG_TOP_MODULE.
+    /// Aliases are resolved first, so an alias that is an output becomes an assign from the signal it names
+    pub fn to_verilog(&self) -> Result<String, ExportError> {
+        if self.has_aliases() {
+            return self.resolve_aliases().to_verilog();
+        }
+        let mut names: Vec<&String> = self.subcircuits.keys().collect();
+        names.sort();
+
+        let mut modules = vec![];
+        for name in names {
+            if name == VERILOG_TOP_MODULE {
+                return Err(ExportError::ModuleNameCollision(name.clone()));
+            }
+            let subcircuit = &self.subcircuits[name];
+            modules.push(write_verilog_module(self, name, &subcircuit.inputs, &subcircuit.outputs, &HashMap::new(), &subcircuit.components)?);
+        }
+        modules.push(write_verilog_module(self, VERILOG_TOP_MODULE, &self.inputs, &self.outputs, &self.constants, &self.components)?);
+
+        Ok(modules.join("\n"))
+    }
+}
+
+// Netlist
+
+/// Writes a name as an S-expression atom, quoting it if it contains whitespace, parentheses, quotes or ';'
+fn netlist_atom(name: &str) -> String {
+    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';' | '\\')) {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        name.to_string()
+    }
+}
+
+/// Writes a list form such as "(in a b)" with the given head and atoms
+fn netlist_list<'a>(head: &str, atoms: impl IntoIterator<Item = &'a str>) -> String {
+    let mut list = format!("({}", head);
+    for atom in atoms {
+        list.push(' ');
+        list.push_str(&netlist_atom(atom));
+    }
+    list.push(')');
+    list
+}
+
+/// Writes the ports, cells and nets of one scope, indented by the given prefix
+fn write_netlist_scope(
+    netlist: &mut String,
+    indent: &str,
+    inputs: &[String],
+    outputs: &[String],
+    signals: &std::collections::BTreeSet<String>,
+    components: &[Component],
+) {
+    netlist.push_str(&format!("{}{}\n", indent, netlist_list("inputs", inputs.iter().map(String::as_str))));
+    netlist.push_str(&format!("{}{}\n", indent, netlist_list("outputs", outputs.iter().map(String::as_str))));
+
+    let labels: Vec<String> = components.iter().map(crate::graph::component_label).collect();
+    for (component, label) in components.iter().zip(&labels) {
+        let inputs: Vec<String> = component.inputs.iter().map(Signal::to_string).collect();
+        netlist.push_str(&format!(
+            "{}(cell {} {} {} {})\n",
+            indent,
+            netlist_atom(label),
+            netlist_atom(component.gate_type.keyword()),
+            netlist_list("in", inputs.iter().map(String::as_str)),
+            netlist_list("out", component.outputs.iter().map(String::as_str)),
+        ));
+    }
+
+    netlist.push_str(&format!("{}(nets\n", indent));
+    for signal in signals {
+        let cells = |is_connected: &dyn Fn(&Component) -> bool| -> Vec<&str> {
+            components.iter().zip(&labels)
+                .filter(|(component, _)| is_connected(component))
+                .map(|(_, label)| label.as_str())
+                .collect()
+        };
+        let drivers = cells(&|component| component.outputs.contains(signal));
+        let loads = cells(&|component| component.inputs.iter().any(|input| input.wire() == Some(signal.as_str())));
+        netlist.push_str(&format!(
+            "{}  (net {} {} {})\n",
+            indent,
+            netlist_atom(signal),
+            netlist_list("drivers", drivers),
+            netlist_list("loads", loads),
+        ));
+    }
+    netlist.push_str(&format!("{})\n", indent));
+}
+
+impl Program {
+    /// Renders the program as an S-expression netlist of cells and nets
+    /// Each component becomes "(cell g1 AND (in a b) (out y))", where subcircuit instances name their subcircuit
+    /// as the cell type, and each net lists the cells driving and loading it. Subcircuits come first in name
+    /// order, cells keep source order and nets are sorted by name, so the output is deterministic.
+    /// Aliases are resolved first, so an alias that is an output becomes a BUF cell
+    pub fn to_netlist(&self) -> String {
+        if self.has_aliases() {
+            return self.resolve_aliases().to_netlist();
+        }
+        let mut netlist = String::from("(netlist\n");
+
+        let mut names: Vec<&String> = self.subcircuits.keys().collect();
+        names.sort();
+        for name in names {
+            let subcircuit = &self.subcircuits[name];
+            netlist.push_str(&format!("  (subcircuit {}\n", netlist_atom(name)));
+            write_netlist_scope(&mut netlist, "    ", &subcircuit.inputs, &subcircuit.outputs, &subcircuit.signals(), &subcircuit.components);
+            netlist.push_str("  )\n");
+        }
+
+        let mut constants: Vec<&String> = self.constants.keys().collect();
+        constants.sort();
+        if !constants.is_empty() {
+            let entries: Vec<String> = constants.iter()
+                .map(|name| format!("({} {})", netlist_atom(name), self.constants[*name] as u8))
+                .collect();
+            netlist.push_str(&format!("  (constants {})\n", entries.join(" ")));
+        }
+        write_netlist_scope(&mut netlist, "  ", &self.inputs, &self.outputs, &self.signals(), &self.components);
+
+        netlist.push_str(")\n");
+        netlist
+    }
+}
+
+// Value change dump
+
+/// Timescale of the VCD files written by Simulator::to_vcd, where one time unit is half a clock period
+pub const VCD_TIMESCALE: &str = "1ns";
+
+/// Name of the VCD scope holding the signals of the main circuit
+pub const VCD_TOP_SCOPE: &str = "top";
+
+/// Returns the VCD identifier code of the variable with the given index, made of the printable characters '!' to '~'
+fn vcd_code(mut index: usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return code;
+        }
+        index -= 1;
+    }
+}
+
+/// Formats a signal value as a VCD scalar, where a signal that has not been computed yet is unknown
+fn vcd_value(value: Option<Logic>) -> char {
+    match value {
+        Some(Logic::Low) => '0',
+        Some(Logic::High) => '1',
+        Some(Logic::Z) => 'z',
+        Some(Logic::X) | None => 'x',
+    }
+}
+
+/// Writes the variable declarations of the given signals, placing flattened names such as "full_adder_0.temp1"
+/// in a nested scope per subcircuit instance. A bus bit such as "a[0]" is declared as the reference "a [0]"
+fn write_vcd_vars(vcd: &mut String, signals: &[String]) {
+    let mut order: Vec<usize> = (0..signals.len()).collect();
+    order.sort_by_key(|&index| signals[index].split(HIERARCHY_SEPARATOR).collect::<Vec<_>>());
+
+    let mut current: Vec<&str> = vec![];
+    for index in order {
+        let mut path: Vec<&str> = signals[index].split(HIERARCHY_SEPARATOR).collect();
+        let leaf = path.pop().unwrap_or_default();
+        let common = current.iter().zip(&path).take_while(|(a, b)| a == b).count();
+        for _ in common..current.len() {
+            vcd.push_str("$upscope $end\n");
+        }
+        for scope in &path[common..] {
+            vcd.push_str(&format!("$scope module {} $end\n", scope));
+        }
+        current = path;
+
+        let reference = match leaf.find('[') {
+            Some(bracket) => format!("{} {}", &leaf[..bracket], &leaf[bracket..]),
+            None => leaf.to_string(),
+        };
+        vcd.push_str(&format!("$var wire 1 {} {} $end\n", vcd_code(index), reference));
+    }
+    for _ in 0..current.len() {
+        vcd.push_str("$upscope $end\n");
+    }
+}
+
+impl Simulator<'_> {
+    /// Runs the circuit for the given number of clock cycles and returns every signal's values as a Value Change Dump
+    /// Each program input wired to a flip-flop clock rises at odd times and falls at even times, and the circuit
+    /// is ticked after every change. Other inputs keep the values set with set_input. The dump starts from the
+    /// current state at time 0, covers every signal of the flattened program and can be opened in GTKWave.
+    /// The ticks run on a copy of the simulator, so its own state and watches are left as they were
+    pub fn to_vcd(&self, ticks: usize) -> Result<String, SimError> {
+        let mut simulator = self.fork();
+        let flattened = self.program().flatten()?;
+        let signals: Vec<String> = flattened.signals().into_iter().collect();
+        let mut clocks: Vec<&str> = vec![];
+        for component in flattened.components.iter().filter(|c| matches!(c.gate_type, GateType::Dff)) {
+            let clock = component.inputs.get(1).and_then(Signal::wire);
+            if let Some(clock) = clock.filter(|clock| flattened.inputs.iter().any(|input| input == clock)) {
+                if !clocks.contains(&clock) {
+                    clocks.push(clock);
+                }
+            }
+        }
+
+        let mut vcd = format!("$timescale {} $end\n$scope module {} $end\n", VCD_TIMESCALE, VCD_TOP_SCOPE);
+        write_vcd_vars(&mut vcd, &signals);
+        vcd.push_str("$upscope $end\n$enddefinitions $end\n");
+
+        simulator.step()?;
+        let mut last: Vec<char> = signals.iter().map(|signal| vcd_value(simulator.get_logic(signal))).collect();
+        vcd.push_str("#0\n$dumpvars\n");
+        for (index, value) in last.iter().enumerate() {
+            vcd.push_str(&format!("{}{}\n", value, vcd_code(index)));
+        }
+        vcd.push_str("$end\n");
+
+        for time in 1..=2 * ticks {
+            for clock in &clocks {
+                simulator.set_input(clock, time % 2 == 1)?;
+            }
+            simulator.tick()?;
+
+            vcd.push_str(&format!("#{}\n", time));
+            for (index, signal) in signals.iter().enumerate() {
+                let value = vcd_value(simulator.get_logic(signal));
+                if last[index] != value {
+                    last[index] = value;
+                    vcd.push_str(&format!("{}{}\n", value, vcd_code(index)));
+                }
+            }
+        }
+        Ok(vcd)
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::lang::*;
+
+/// Writes comments kept by the parser, each on its own line
+fn write_comments(f: &mut fmt::Formatter<'_>, comments: &[String]) -> fmt::Result {
+    for comment in comments {
+        writeln!(f, "{}", comment)?;
+    }
+    Ok(())
+}
+
+/// Splits a bus bit name such as "a[2]" into its bus name and index
+fn split_bus_bit(name: &str) -> Option<(&str, usize)> {
+    let (bus, rest) = name.split_once('[')?;
+    let index = rest.strip_suffix(']')?.parse().ok()?;
+    Some((bus, index))
+}
+
+/// Writes a port declaration list, folding the bits "a[0]" through "a[n-1]" back into "a[n]"
+fn write_declarations(f: &mut fmt::Formatter<'_>, names: &[String]) -> fmt::Result {
+    let mut declarations = vec![];
+    let mut i = 0;
+    while i < names.len() {
+        if let Some((bus, 0)) = split_bus_bit(&names[i]) {
+            let width = names[i..].iter()
+                .enumerate()
+                .take_while(|(bit, name)| split_bus_bit(name) == Some((bus, *bit)))
+                .count();
+            declarations.push(format!("{}[{}]", bus, width));
+            i += width;
+        } else {
+            declarations.push(names[i].clone());
+            i += 1;
+        }
+    }
+    write!(f, "{}", declarations.join(", "))
+}
+
+/// Writes the INPUTS and OUTPUTS sections, the CONST lines in name order, the component list
+/// and the ALIAS lines of one scope
+fn write_scope(
+    f: &mut fmt::Formatter<'_>,
+    inputs: &[String],
+    outputs: &[String],
+    constants: &HashMap<String, bool>,
+    components: &[Component],
+    aliases: &[Alias],
+) -> fmt::Result {
+    write!(f, "INPUTS ")?;
+    write_declarations(f, inputs)?;
+    write!(f, "\nOUTPUTS ")?;
+    write_declarations(f, outputs)?;
+    writeln!(f)?;
+    let mut names: Vec<&String> = constants.keys().collect();
+    names.sort();
+    for name in names {
+        writeln!(f, "CONST {} = {}", name, constants[name] as u8)?;
+    }
+    for component in components {
+        write_comments(f, &component.comments)?;
+        writeln!(f, "{}", component)?;
+    }
+    for alias in aliases {
+        writeln!(f, "ALIAS {} = {}", alias.name, alias.target)?;
+    }
+    Ok(())
+}
+
+/// Formats a component as one line of source, e.g. "AND g1 IN(a, b) OUT(y)"
+/// Subcircuit instances are written without their generated identifier
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inputs: Vec<String> = self.inputs.iter().map(Signal::to_string).collect();
+        match &self.gate_type {
+            GateType::Subcircuit(name) => write!(f, "{}", name)?,
+            gate_type => write!(f, "{} {}", gate_type.keyword(), self.identifier)?,
+        }
+        write!(f, " IN({}) OUT({})", inputs.join(", "), self.outputs.join(", "))
+    }
+}
+
+/// Formats a subcircuit as a SUBCIRCUIT ... END block, preceded by its comments
+impl fmt::Display for Subcircuit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_comments(f, &self.comments)?;
+        writeln!(f, "SUBCIRCUIT {}", self.name)?;
+        write_scope(f, &self.inputs, &self.outputs, &HashMap::new(), &self.components, &self.aliases)?;
+        writeln!(f, "END")
+    }
+}
+
+/// Formats a program as source text that parses back to an equivalent program
+/// Subcircuits are written first in name order, followed by the main circuit.
+/// Comments kept by the parser are written before the definition they were attached to
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.subcircuits.keys().collect();
+        names.sort();
+        for name in names {
+            writeln!(f, "{}", self.subcircuits[name])?;
+        }
+        write_comments(f, &self.comments)?;
+        write_scope(f, &self.inputs, &self.outputs, &self.constants, &self.components, &self.aliases)
+    }
+}
@@ -0,0 +1,546 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::lang::*;
+use crate::sim::{check_acyclic, SimError};
+use crate::validate::SemanticError;
+
+// Dependency graph
+
+/// Builds the wire dependency edges of a component list
+/// Each entry lists the consuming components of a component and the wire connecting them
+/// Flip-flops only change on a clock tick, so wires into a DFF are not combinational dependencies
+pub(crate) fn dependency_edges(components: &[Component]) -> Vec<Vec<(usize, &str)>> {
+    let mut consumers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, component) in components.iter().enumerate() {
+        if matches!(component.gate_type, GateType::Dff) {
+            continue;
+        }
+        for wire in component.inputs.iter().filter_map(Signal::wire) {
+            consumers.entry(wire).or_default().push(i);
+        }
+    }
+
+    components.iter()
+        .map(|component| {
+            component.outputs.iter()
+                .flat_map(|output| {
+                    consumers.get(output.as_str())
+                        .into_iter()
+                        .flatten()
+                        .map(move |&consumer| (consumer, output.as_str()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Orders a component list so every component comes after the components driving its inputs
+/// Returns the components that could not be ordered when the list contains a loop
+pub(crate) fn topological_order(components: &[Component]) -> Result<Vec<usize>, Vec<usize>> {
+    let (order, unordered) = partial_order(components);
+    if unordered.is_empty() {
+        Ok(order)
+    } else {
+        Err(unordered)
+    }
+}
+
+/// Orders a component list like topological_order, appending the components that loops keep from being ordered
+/// in source order, so every component appears once even when the list feeds back into itself
+pub(crate) fn feedback_order(components: &[Component]) -> Vec<usize> {
+    let (mut order, unordered) = partial_order(components);
+    order.extend(unordered);
+    order
+}
+
+/// Orders the components that do not depend on a loop, and returns the others separately in source order
+fn partial_order(components: &[Component]) -> (Vec<usize>, Vec<usize>) {
+    let edges = dependency_edges(components);
+    let mut in_degree = vec![0; components.len()];
+    for &(consumer, _) in edges.iter().flatten() {
+        in_degree[consumer] += 1;
+    }
+
+    let mut ready: Vec<usize> = (0..components.len()).rev().filter(|&i| in_degree[i] == 0).collect();
+    let mut order = Vec::with_capacity(components.len());
+    while let Some(node) = ready.pop() {
+        order.push(node);
+        for &(consumer, _) in edges[node].iter().rev() {
+            in_degree[consumer] -= 1;
+            if in_degree[consumer] == 0 {
+                ready.push(consumer);
+            }
+        }
+    }
+
+    let unordered = (0..components.len()).filter(|&i| in_degree[i] > 0).collect();
+    (order, unordered)
+}
+
+/// Groups an ordered component list into levels by the longest path from a component without drivers
+/// No component reads a signal driven within its own level, so the components of a level can be evaluated independently
+#[cfg(feature = "parallel")]
+pub(crate) fn levels(components: &[Component], order: &[usize]) -> Vec<Vec<usize>> {
+    let edges = dependency_edges(components);
+    let mut depth = vec![0; components.len()];
+    let mut levels: Vec<Vec<usize>> = vec![];
+    for &node in order {
+        for &(consumer, _) in &edges[node] {
+            depth[consumer] = depth[consumer].max(depth[node] + 1);
+        }
+        if levels.len() <= depth[node] {
+            levels.resize_with(depth[node] + 1, Vec::new);
+        }
+        levels[depth[node]].push(node);
+    }
+    levels
+}
+
+/// Searches a component list for a combinational loop
+/// Returns the components on the loop in order, each paired with the wire leading to the next one
+pub(crate) fn find_cycle(components: &[Component]) -> Option<Vec<(usize, String)>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        OnPath,
+        Done,
+    }
+
+    let edges = dependency_edges(components);
+    let mut marks = vec![Mark::Unvisited; components.len()];
+
+    for start in 0..components.len() {
+        if marks[start] != Mark::Unvisited {
+            continue;
+        }
+
+        // Depth first search with an explicit stack of (component, next edge to follow)
+        let mut stack = vec![(start, 0)];
+        marks[start] = Mark::OnPath;
+
+        while let Some(top) = stack.last_mut() {
+            let node = top.0;
+            if let Some(&(next, _)) = edges[node].get(top.1) {
+                top.1 += 1;
+                match marks[next] {
+                    Mark::OnPath => {
+                        let first = stack.iter().position(|&(n, _)| n == next)?;
+                        return Some(stack[first..].iter()
+                            .map(|&(n, taken)| (n, edges[n][taken - 1].1.to_string()))
+                            .collect());
+                    }
+                    Mark::Unvisited => {
+                        marks[next] = Mark::OnPath;
+                        stack.push((next, 0));
+                    }
+                    Mark::Done => {}
+                }
+            } else {
+                marks[node] = Mark::Done;
+                stack.pop();
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds subcircuits that instantiate themselves, directly or through other subcircuits
+/// Each chain starts and ends with the same subcircuit, e.g. ["a", "b", "a"]
+/// Subcircuits are explored in name order so the result is deterministic
+pub(crate) fn recursive_subcircuits(program: &Program) -> Vec<Vec<String>> {
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+    let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, n)| (n.as_str(), i)).collect();
+
+    // Subcircuits instantiated by each subcircuit, in component order
+    let calls: Vec<Vec<usize>> = names.iter()
+        .map(|name| {
+            program.subcircuits[*name].components.iter()
+                .filter_map(|c| match &c.gate_type {
+                    GateType::Subcircuit(callee) => index.get(callee.as_str()).copied(),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut chains = vec![];
+    let mut done = vec![false; names.len()];
+    for start in 0..names.len() {
+        if done[start] {
+            continue;
+        }
+
+        let mut stack: Vec<(usize, usize)> = vec![(start, 0)];
+        while let Some(top) = stack.last_mut() {
+            let node = top.0;
+            if let Some(&callee) = calls[node].get(top.1) {
+                top.1 += 1;
+                if let Some(first) = stack.iter().position(|&(n, _)| n == callee) {
+                    let mut chain: Vec<String> = stack[first..].iter().map(|&(n, _)| names[n].clone()).collect();
+                    chain.push(names[callee].clone());
+                    chains.push(chain);
+                } else if !done[callee] {
+                    stack.push((callee, 0));
+                }
+            } else {
+                done[node] = true;
+                stack.pop();
+            }
+        }
+    }
+
+    chains
+}
+
+/// Returns the number of subcircuit levels in each subcircuit, counting the subcircuit itself
+/// A subcircuit made only of primitive gates has depth 1, and one instantiating it has depth 2.
+/// Instances of undefined subcircuits and instances closing a recursive loop are not counted
+pub(crate) fn subcircuit_depths(program: &Program) -> HashMap<&str, usize> {
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    for start in names {
+        if depths.contains_key(start.as_str()) {
+            continue;
+        }
+
+        // Depth first search with an explicit stack of (subcircuit, next component to look at, deepest callee so far)
+        let mut stack: Vec<(&str, usize, usize)> = vec![(start, 0, 0)];
+        while let Some(top) = stack.last_mut() {
+            let (name, next, deepest) = *top;
+            let components = &program.subcircuits[name].components;
+            let Some(component) = components.get(next) else {
+                depths.insert(name, deepest + 1);
+                stack.pop();
+                if let Some(caller) = stack.last_mut() {
+                    caller.2 = caller.2.max(deepest + 1);
+                }
+                continue;
+            };
+            top.1 += 1;
+
+            let GateType::Subcircuit(callee) = &component.gate_type else {
+                continue;
+            };
+            let Some((callee, _)) = program.subcircuits.get_key_value(callee) else {
+                continue;
+            };
+            if let Some(&depth) = depths.get(callee.as_str()) {
+                top.2 = top.2.max(depth);
+            } else if !stack.iter().any(|&(n, _, _)| n == callee) {
+                stack.push((callee, 0, 0));
+            }
+        }
+    }
+    depths
+}
+
+/// Returns the declared outputs of a scope that are neither inputs nor driven by a component
+pub(crate) fn undriven_outputs<'a>(inputs: &[String], outputs: &'a [String], components: &[Component]) -> Vec<&'a String> {
+    outputs.iter()
+        .filter(|output| !inputs.contains(output))
+        .filter(|output| !components.iter().any(|c| c.outputs.contains(output)))
+        .collect()
+}
+
+/// Returns a readable name for a component, falling back to its gate type when it has no identifier
+pub(crate) fn component_label(component: &Component) -> String {
+    if !component.identifier.is_empty() {
+        return component.identifier.clone();
+    }
+    match &component.gate_type {
+        GateType::Subcircuit(name) => name.clone(),
+        gate_type => format!("{:?}", gate_type).to_uppercase(),
+    }
+}
+
+/// Formats a loop found by find_cycle as "g1 -> (wire x) -> g2 -> (wire y) -> g1"
+fn format_cycle(components: &[Component], cycle: &[(usize, String)]) -> String {
+    let mut trace = String::new();
+    for (index, wire) in cycle {
+        trace.push_str(&format!("{} -> (wire {}) -> ", component_label(&components[*index]), wire));
+    }
+    trace.push_str(&component_label(&components[cycle[0].0]));
+    trace
+}
+
+// Diagnostics
+
+impl Program {
+    /// Describes the first combinational loop in the program, including the wires that close it
+    /// The main circuit is checked first, then subcircuits in name order, with their aliases resolved
+    pub fn describe_cycle(&self) -> Option<String> {
+        if self.has_aliases() {
+            return self.resolve_aliases().describe_cycle();
+        }
+        if let Some(cycle) = find_cycle(&self.components) {
+            return Some(format_cycle(&self.components, &cycle));
+        }
+
+        let mut names: Vec<&String> = self.subcircuits.keys().collect();
+        names.sort();
+        for name in names {
+            let components = &self.subcircuits[name].components;
+            if let Some(cycle) = find_cycle(components) {
+                return Some(format!("{} (in subcircuit {})", format_cycle(components, &cycle), name));
+            }
+        }
+
+        None
+    }
+
+    /// Panics with a readable trace if the program contains a combinational loop
+    pub fn assert_acyclic(&self) {
+        if let Some(trace) = self.describe_cycle() {
+            panic!("Combinational loop detected: {}", trace);
+        }
+    }
+}
+
+// Signal analysis
+
+/// Maps each signal of the main circuit to the components that drive and read it
+struct SignalIndex<'a> {
+    drivers: HashMap<&'a str, Vec<&'a Component>>, // Signal -> components listing it as an output
+    readers: HashMap<&'a str, Vec<&'a Component>>, // Signal -> components listing it as an input
+    signals: Vec<&'a str>,                         // Every signal, in order of first appearance
+    seen: HashSet<&'a str>,                        // The signals already in signals, to keep them distinct
+}
+
+impl<'a> SignalIndex<'a> {
+    fn new(program: &'a Program) -> Self {
+        let mut index = SignalIndex { drivers: HashMap::new(), readers: HashMap::new(), signals: vec![], seen: HashSet::new() };
+        for input in &program.inputs {
+            index.note(input);
+        }
+        let mut constants: Vec<&str> = program.constants.keys().map(String::as_str).collect();
+        constants.sort_unstable();
+        for constant in constants {
+            index.note(constant);
+        }
+        for component in &program.components {
+            for wire in component.inputs.iter().filter_map(Signal::wire) {
+                index.note(wire);
+                add_once(index.readers.entry(wire).or_default(), component);
+            }
+            for output in &component.outputs {
+                index.note(output);
+                add_once(index.drivers.entry(output).or_default(), component);
+            }
+        }
+        for alias in &program.aliases {
+            index.note(&alias.name);
+            index.note(&alias.target);
+        }
+        for output in &program.outputs {
+            index.note(output);
+        }
+        index
+    }
+
+    /// Records a signal the first time it is seen
+    fn note(&mut self, signal: &'a str) {
+        if self.seen.insert(signal) {
+            self.signals.push(signal);
+        }
+    }
+}
+
+/// Appends a component unless it was the last one added, so a component using a signal twice is listed once
+fn add_once<'a>(components: &mut Vec<&'a Component>, component: &'a Component) {
+    if !components.last().is_some_and(|last| std::ptr::eq(*last, component)) {
+        components.push(component);
+    }
+}
+
+/// Collects the names of the ports of a scope, of every wire its components read or drive and of its aliases
+fn scope_signals(inputs: &[String], outputs: &[String], components: &[Component], aliases: &[Alias]) -> BTreeSet<String> {
+    let wires = components.iter().flat_map(|component| {
+        component.inputs.iter().filter_map(Signal::wire).chain(component.outputs.iter().map(String::as_str))
+    });
+    let aliases = aliases.iter().flat_map(|alias| [alias.name.as_str(), alias.target.as_str()]);
+    inputs.iter().chain(outputs).map(String::as_str)
+        .chain(wires)
+        .chain(aliases)
+        .map(str::to_string)
+        .collect()
+}
+
+impl Subcircuit {
+    /// Returns every distinct signal name of the subcircuit in sorted order: its ports, the wires of its components
+    /// and its aliases
+    pub fn signals(&self) -> BTreeSet<String> {
+        scope_signals(&self.inputs, &self.outputs, &self.components, &self.aliases)
+    }
+}
+
+/// Signals of the main circuit that are missing a source or a destination
+#[derive(Debug, Default)]
+pub struct DanglingSignals {
+    pub undriven: Vec<String>, // Read by a component or declared as an output, but neither driven nor an input
+    pub unused: Vec<String>,   // An input or driven by a component, but neither read nor declared as an output
+}
+
+impl Program {
+    /// Returns every distinct signal name of the main circuit in sorted order: its inputs, outputs and constants
+    /// the wires its components read or drive and its aliases. Signals inside subcircuits are not included
+    pub fn signals(&self) -> BTreeSet<String> {
+        let mut signals = scope_signals(&self.inputs, &self.outputs, &self.components, &self.aliases);
+        signals.extend(self.constants.keys().cloned());
+        signals
+    }
+
+    /// Returns the components of the main circuit that read the given signal, in source order
+    /// Use fanout_map to look up many signals without scanning the components each time
+    pub fn fanout(&self, signal: &str) -> Vec<&Component> {
+        self.components.iter()
+            .filter(|component| component.inputs.iter().filter_map(Signal::wire).any(|wire| wire == signal))
+            .collect()
+    }
+
+    /// Returns the components of the main circuit that drive the given signal, in source order
+    /// Use drivers_map to look up many signals without scanning the components each time
+    pub fn drivers(&self, signal: &str) -> Vec<&Component> {
+        self.components.iter()
+            .filter(|component| component.outputs.iter().any(|output| output == signal))
+            .collect()
+    }
+
+    /// Returns the fanout of every signal of the main circuit that some component reads
+    pub fn fanout_map(&self) -> HashMap<&str, Vec<&Component>> {
+        SignalIndex::new(self).readers
+    }
+
+    /// Returns the drivers of every signal of the main circuit that some component drives
+    pub fn drivers_map(&self) -> HashMap<&str, Vec<&Component>> {
+        SignalIndex::new(self).drivers
+    }
+
+    /// Finds signals of the main circuit with no driver or no consumer
+    /// Signals are listed in order of first appearance, with constants after the inputs in name order.
+    /// An alias counts as a source of its name and a consumer of its target
+    pub fn dangling_signals(&self) -> DanglingSignals {
+        let index = SignalIndex::new(self);
+        let mut dangling = DanglingSignals::default();
+        for &signal in &index.signals {
+            let is_input = self.inputs.iter().any(|i| i == signal) || self.constants.contains_key(signal)
+                || self.aliases.iter().any(|alias| alias.name == signal);
+            let is_output = self.outputs.iter().any(|o| o == signal)
+                || self.aliases.iter().any(|alias| alias.target == signal);
+            if !is_input && !index.drivers.contains_key(signal) {
+                dangling.undriven.push(signal.to_string());
+            }
+            if !is_output && !index.readers.contains_key(signal) {
+                dangling.unused.push(signal.to_string());
+            }
+        }
+        dangling
+    }
+}
+
+// Cone of influence
+
+impl Program {
+    /// Returns the inputs of the program that a declared output depends on, in sorted order
+    /// Works backwards from the output through the drivers of each signal, inside subcircuit instances as well,
+    /// and through flip-flops and latches, whose stored values also come from their inputs.
+    /// An output driven only by constants depends on no inputs. Returns SemanticError::UnknownOutput
+    /// if the name is not a declared output, or the first error of Program::validate
+    pub fn input_cone(&self, output: &str) -> Result<BTreeSet<String>, SemanticError> {
+        if !self.outputs.iter().any(|o| o == output) {
+            return Err(SemanticError::UnknownOutput { name: output.to_string() });
+        }
+        if let Some(err) = self.validate().err().and_then(|errors| errors.into_iter().next()) {
+            return Err(err);
+        }
+        let flattened = self.flatten().expect("a validated program can be flattened");
+        let index = SignalIndex::new(&flattened);
+
+        let mut cone = BTreeSet::new();
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut pending = vec![output];
+        while let Some(signal) = pending.pop() {
+            if !seen.insert(signal) {
+                continue;
+            }
+            if flattened.inputs.iter().any(|input| input == signal) {
+                cone.insert(signal.to_string());
+            }
+            for component in index.drivers.get(signal).into_iter().flatten() {
+                pending.extend(component.inputs.iter().filter_map(Signal::wire));
+            }
+        }
+        Ok(cone)
+    }
+}
+
+// Statistics
+
+/// Size and depth figures for a program, counted after flattening its subcircuit instances
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitStats {
+    pub gates: usize,                         // Primitive gates, including those inside subcircuit instances
+    pub gate_counts: BTreeMap<String, usize>, // Gate keyword -> number of primitive gates of that type
+    pub subcircuit_instances: usize,          // Subcircuit instances, including those nested inside other instances
+    pub signals: usize,                       // Distinct signals of the main circuit, as listed by Program::signals
+    pub depth: usize,                         // Gates on the longest path from an input or flip-flop to an output
+}
+
+impl Program {
+    /// Counts the gates, subcircuit instances and signals of the program and measures its combinational depth
+    /// Flip-flops start and end paths, so a DFF adds nothing to the depth, while latches count like gates
+    /// Returns the errors of Program::flatten, or SimError::CombinationalLoop if the depth is unbounded
+    pub fn stats(&self) -> Result<CircuitStats, SimError> {
+        let flattened = self.flatten()?;
+        let components = &flattened.components;
+        let order = match topological_order(components) {
+            Ok(order) => order,
+            Err(_) => {
+                check_acyclic(components)?;
+                unreachable!("components without a loop always have a topological order");
+            }
+        };
+
+        let edges = dependency_edges(components);
+        let mut depths: Vec<usize> = components.iter()
+            .map(|component| usize::from(!matches!(component.gate_type, GateType::Dff)))
+            .collect();
+        for &node in &order {
+            for &(consumer, _) in &edges[node] {
+                depths[consumer] = depths[consumer].max(depths[node] + 1);
+            }
+        }
+        let depth = components.iter().zip(&depths)
+            .filter(|(component, _)| component.outputs.iter().any(|output| flattened.outputs.contains(output)))
+            .map(|(_, &depth)| depth)
+            .max()
+            .unwrap_or(0);
+
+        let mut gate_counts = BTreeMap::new();
+        for component in components {
+            *gate_counts.entry(component.gate_type.keyword().to_string()).or_insert(0) += 1;
+        }
+
+        Ok(CircuitStats {
+            gates: components.len(),
+            gate_counts,
+            subcircuit_instances: count_instances(self, &self.components),
+            signals: self.signals().len(),
+            depth,
+        })
+    }
+}
+
+/// Counts the subcircuit instances in a component list and, recursively, inside each instance
+/// Only called on programs that flatten, so every instance names a defined, non-recursive subcircuit
+fn count_instances(program: &Program, components: &[Component]) -> usize {
+    components.iter()
+        .filter_map(|component| match &component.gate_type {
+            GateType::Subcircuit(name) => program.subcircuits.get(name),
+            _ => None,
+        })
+        .map(|subcircuit| 1 + count_instances(program, &subcircuit.components))
+        .sum()
+}
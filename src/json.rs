@@ -0,0 +1,13 @@
+use crate::lang::*;
+
+impl Program {
+    /// Serializes the program to a JSON string
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Deserializes a program from a JSON string produced by to_json
+    pub fn from_json(json: &str) -> Result<Program, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
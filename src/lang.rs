@@ -1,12 +1,19 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self};
+use std::io::BufRead;
+use std::str::FromStr;
+use crate::graph;
+use crate::validate::SemanticError;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenKind {
 
     // Keywords
     Inputs,
     Outputs,
+    Const,
+    Alias,
     In,
     Out,
  
@@ -14,6 +21,12 @@ pub enum TokenKind {
     Comma,
     ParenOpen,
     ParenClose,
+    BracketOpen,
+    BracketClose,
+    Colon,
+    Semicolon,
+    Equals,
+    Bang,
     Newline,
     EOF,
 
@@ -25,27 +38,217 @@ pub enum TokenKind {
     Nor,
     Xor,
     Xnor,
+    Buf,
+    Tri,
+    Dff,
+    SrLatch,
+    DLatch,
+    HalfAdder,
+    FullAdder,
 
     // Subcircuits
     Subcircuit,
     End,
 
+    // Numbers (bus widths and indices, and the constants 0 and 1)
+    Number(usize),
+
+    // Bus literals such as 4'hA, with the bits of the value from the least significant one on
+    BusLiteral { width: usize, bits: Vec<bool> },
+
     // Identifier
     Identifier(String),
+
+    // Comments, including their "#" or "/* */" delimiters, which are only emitted when LexerConfig::preserve_comments is set
+    Comment(String),
+}
+
+impl TokenKind {
+    /// Returns the canonical spelling of a keyword token, or None for other tokens
+    pub fn keyword(&self) -> Option<&'static str> {
+        Some(match self {
+            TokenKind::Inputs => "INPUTS",
+            TokenKind::Outputs => "OUTPUTS",
+            TokenKind::Const => "CONST",
+            TokenKind::Alias => "ALIAS",
+            TokenKind::In => "IN",
+            TokenKind::Out => "OUT",
+            TokenKind::And => "AND",
+            TokenKind::Or => "OR",
+            TokenKind::Not => "NOT",
+            TokenKind::Nand => "NAND",
+            TokenKind::Nor => "NOR",
+            TokenKind::Xor => "XOR",
+            TokenKind::Xnor => "XNOR",
+            TokenKind::Buf => "BUF",
+            TokenKind::Tri => "TRI",
+            TokenKind::Dff => "DFF",
+            TokenKind::SrLatch => "SRLATCH",
+            TokenKind::DLatch => "DLATCH",
+            TokenKind::HalfAdder => "HALFADDER",
+            TokenKind::FullAdder => "FULLADDER",
+            TokenKind::Subcircuit => "SUBCIRCUIT",
+            TokenKind::End => "END",
+            _ => return None,
+        })
+    }
+}
+
+/// Returns the token of a keyword written in its canonical uppercase spelling, such as "AND" or "SUBCIRCUIT",
+/// or None for any other word. Matching is case-sensitive and ignores aliases like INV, which come from LexerConfig,
+/// so uppercase the word first to follow the default case-insensitive lexer
+pub fn keyword_of(word: &str) -> Option<TokenKind> {
+    Some(match word {
+        "INPUTS" => TokenKind::Inputs,
+        "OUTPUTS" => TokenKind::Outputs,
+        "CONST" => TokenKind::Const,
+        "ALIAS" => TokenKind::Alias,
+        "IN" => TokenKind::In,
+        "OUT" => TokenKind::Out,
+        "AND" => TokenKind::And,
+        "OR" => TokenKind::Or,
+        "NOT" => TokenKind::Not,
+        "NAND" => TokenKind::Nand,
+        "NOR" => TokenKind::Nor,
+        "XOR" => TokenKind::Xor,
+        "XNOR" => TokenKind::Xnor,
+        "BUF" => TokenKind::Buf,
+        "TRI" => TokenKind::Tri,
+        "DFF" => TokenKind::Dff,
+        "SRLATCH" => TokenKind::SrLatch,
+        "DLATCH" => TokenKind::DLatch,
+        "HALFADDER" => TokenKind::HalfAdder,
+        "FULLADDER" => TokenKind::FullAdder,
+        "SUBCIRCUIT" => TokenKind::Subcircuit,
+        "END" => TokenKind::End,
+        _ => return None,
+    })
+}
+
+/// A token together with the part of the source code it was read from
+#[derive(Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
 }
 
 /// The main lexer struct that handles tokenization of source code
-#[derive(Debug)]
+/// Characters are read from the source on demand, so only the current and the next one are held at a time
 pub struct Lexer {
-    source: String,             // The complete source code to be tokenized
-    position: usize,            // Current position in the source code
+    chars: Box<dyn Iterator<Item = char>>, // Characters after next_char, not read yet
     line: usize,                // Current line number (for error reporting)
     column: usize,              // Current column number (for error reporting)
     current_char: Option<char>, // Current character being processed
+    next_char: Option<char>,    // Character after the current one, read ahead for two-character tokens
+    config: LexerConfig,        // Identifier character classes and keyword matching options
+    directives: Vec<(String, String)>, // Key and value of each "# @key value" comment read so far
+}
+
+impl fmt::Debug for Lexer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lexer")
+            .field("line", &self.line)
+            .field("column", &self.column)
+            .field("current_char", &self.current_char)
+            .field("next_char", &self.next_char)
+            .field("config", &self.config)
+            .field("directives", &self.directives)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The characters of an owned source string, read in place instead of being copied out
+struct StringChars {
+    source: String,
+    offset: usize, // Byte offset of the next character
+}
+
+impl Iterator for StringChars {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.source[self.offset..].chars().next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// The characters of a reader, decoded one line at a time
+/// Invalid UTF-8 becomes U+FFFD, and a read error ends the input after a single U+FFFD,
+/// so either one shows up as an unexpected character instead of being dropped silently
+struct ReaderChars<R> {
+    reader: R,
+    line: String,  // Current line, including its line break
+    offset: usize, // Byte offset of the next character in line
+    failed: bool,  // Whether reading has failed, which ends the input
+}
+
+impl<R: BufRead> Iterator for ReaderChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        while self.offset >= self.line.len() {
+            if self.failed {
+                return None;
+            }
+            let mut bytes = vec![];
+            match self.reader.read_until(b'\n', &mut bytes) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    self.line = String::from_utf8_lossy(&bytes).into_owned();
+                    self.offset = 0;
+                }
+                Err(_) => {
+                    self.failed = true;
+                    return Some(char::REPLACEMENT_CHARACTER);
+                }
+            }
+        }
+        let c = self.line[self.offset..].chars().next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// Number of columns between tab stops unless the lexer is configured otherwise
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Returns the column that follows a tab at the given 1-based column, which is the next tab stop
+pub fn next_tab_stop(column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    (column - 1) / tab_width * tab_width + tab_width + 1
+}
+
+/// Configures which characters the lexer accepts in identifiers, how keywords are matched and how tabs are counted
+#[derive(Debug, Clone)]
+pub struct LexerConfig {
+    pub identifier_start: fn(char) -> bool,     // Characters that may start an identifier
+    pub identifier_continue: fn(char) -> bool,  // Characters that may follow the first one
+    pub case_sensitive_keywords: bool,          // Only the uppercase spelling is a keyword when set
+    pub aliases: HashMap<String, TokenKind>,    // Extra spellings of keywords, e.g. "INV" for NOT
+    pub tab_width: usize,                       // Columns between tab stops in reported locations
+    pub preserve_comments: bool,                // Emit comments as Comment tokens instead of skipping them
+}
+
+impl Default for LexerConfig {
+    /// Identifiers start with an ASCII letter and continue with ASCII letters, digits or '_',
+    /// keywords are matched in any case, INV is accepted as an alias for NOT, tab stops are DEFAULT_TAB_WIDTH apart
+    /// and comments are skipped
+    fn default() -> Self {
+        LexerConfig {
+            identifier_start: |c| c.is_ascii_alphabetic(),
+            identifier_continue: |c| c.is_ascii_alphanumeric() || c == '_',
+            case_sensitive_keywords: false,
+            aliases: HashMap::from([("INV".to_string(), TokenKind::Not)]),
+            tab_width: DEFAULT_TAB_WIDTH,
+            preserve_comments: false,
+        }
+    }
 }
 
 /// Represents a location in the source code for error reporting
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Location {
     pub line: usize,        // Line number (1-based)
     pub column: usize,      // Column number (1-based)
@@ -58,18 +261,64 @@ impl Location {
     }
 }
 
+/// Location given to the parts of a program that were not parsed from source text, such as those built in code
+pub(crate) const BUILT_LOCATION: Location = Location { line: 0, column: 0 };
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Represents a range of the source code, from its first character up to the position after its last one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Location,    // Location of the first character
+    pub end: Location,      // Location just past the last character
+}
+
 impl Lexer {
     /// Creates a new Lexer instance with the given source code
     pub fn new(source: String) -> Self {
-        let mut lexer = Lexer {
-            source,
-            position: 0,
-            line: 1,
-            column: 1,
-            current_char: None,
-        };
-        lexer.current_char = lexer.source.chars().nth(0);
-        lexer
+        Lexer::with_config(source, LexerConfig::default())
+    }
+
+    /// Creates a new Lexer instance that uses the given identifier and keyword options
+    pub fn with_config(source: String, config: LexerConfig) -> Self {
+        Lexer::from_chars_with_config(StringChars { source, offset: 0 }, config)
+    }
+
+    /// Creates a new Lexer instance that reads its source from a character iterator as tokens are requested
+    pub fn from_chars(chars: impl Iterator<Item = char> + 'static) -> Self {
+        Lexer::from_chars_with_config(chars, LexerConfig::default())
+    }
+
+    /// Creates a new Lexer instance that streams its source from a reader, such as a buffered file
+    /// Invalid UTF-8 and read errors are reported as an unexpected U+FFFD character
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        Lexer::from_chars(ReaderChars { reader, line: String::new(), offset: 0, failed: false })
+    }
+
+    /// Creates a new Lexer instance over a character iterator, using the given identifier and keyword options
+    /// Alias spellings are matched with the same case rules as the built-in keywords
+    pub fn from_chars_with_config(chars: impl Iterator<Item = char> + 'static, mut config: LexerConfig) -> Self {
+        if !config.case_sensitive_keywords {
+            config.aliases = config.aliases.into_iter()
+                .map(|(alias, kind)| (alias.to_uppercase(), kind))
+                .collect();
+        }
+        let mut chars = Box::new(chars.fuse());
+        let current_char = chars.next();
+        let next_char = chars.next();
+        Lexer { chars, line: 1, column: 1, current_char, next_char, config, directives: vec![] }
+    }
+
+    /// Returns the directives found in line comments so far, in source order
+    /// A line comment of the form "# @key value" is a directive, such as "# @name half_adder",
+    /// whose value is the rest of the line with surrounding whitespace removed and may be empty
+    pub fn directives(&self) -> &[(String, String)] {
+        &self.directives
     }
 
     /// Returns the current location in the source code
@@ -77,25 +326,47 @@ impl Lexer {
         Location::new(self.line, self.column)
     }
 
+    /// Returns the span from the given location up to the current one
+    fn span_from(&self, start: Location) -> Span {
+        Span { start, end: self.get_location() }
+    }
+
     /// Advances the lexer position by one character
-    /// Updates line and column numbers appropriately
+    /// Updates line and column numbers appropriately, counting "\r\n" as a single line break
     fn advance(&mut self) {
         if let Some(c) = self.current_char {
-            if c == '\n' {
+            if c == '\n' || (c == '\r' && self.peek_char() != Some('\n')) {
                 self.line += 1;
                 self.column = 1;
+            } else if c == '\t' {
+                self.column = next_tab_stop(self.column, self.config.tab_width);
             } else {
                 self.column += 1;
             }
         }
-        self.position += 1;
-        self.current_char = self.source.chars().nth(self.position);
+        self.current_char = self.next_char;
+        self.next_char = self.chars.next();
+    }
+
+    /// Returns the character after the current one without advancing
+    fn peek_char(&self) -> Option<char> {
+        self.next_char
+    }
+
+    /// Skips a line break, which is "\n", "\r\n" or a lone "\r"
+    fn skip_newline(&mut self) {
+        if self.current_char == Some('\r') {
+            self.advance();
+        }
+        if self.current_char == Some('\n') {
+            self.advance();
+        }
     }
 
     /// Skips over whitespace characters in the source code
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.current_char {
-            if c == '\n' {
+            if c == '\n' || c == '\r' {
                 break; // Don't skip newlines
             } else if c.is_whitespace() {
                 self.advance();
@@ -105,41 +376,225 @@ impl Lexer {
         }
     }
 
-    fn skip_line_comment(&mut self) {
+    /// Skips a line comment up to, but not including, the line break that ends it, and returns its text
+    /// Records the comment as a directive if it has the form "# @key value"
+    fn skip_line_comment(&mut self) -> String {
+        let mut text = String::new();
         while let Some(c) = self.current_char {
-            if c == '\n' {
-                self.advance();
+            if c == '\n' || c == '\r' {
                 break;
-            } else {
-                self.advance();
+            }
+            text.push(c);
+            self.advance();
+        }
+
+        if let Some(directive) = text.trim_start_matches('#').trim_start().strip_prefix('@') {
+            let (key, value) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            if !key.is_empty() {
+                self.directives.push((key.to_string(), value.trim().to_string()));
+            }
+        }
+        text
+    }
+
+    /// Skips a block comment starting at the current "/*", including nested block comments, and returns its text
+    /// Returns LexerError::UnterminatedComment if the source ends before the closing "*/"
+    fn skip_block_comment(&mut self) -> Result<String, LexerError> {
+        let location = self.get_location();
+        let mut text = String::new();
+        let mut depth = 0;
+
+        loop {
+            match (self.current_char, self.peek_char()) {
+                (Some('/'), Some('*')) => {
+                    text.push_str("/*");
+                    self.advance();
+                    self.advance();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    text.push_str("*/");
+                    self.advance();
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(text);
+                    }
+                }
+                (Some(c), _) => {
+                    text.push(c);
+                    self.advance();
+                }
+                (None, _) => return Err(LexerError::UnterminatedComment(self.span_from(location))),
             }
         }
     }
+
     /// Main tokenization function that returns the next token from the source
-    /// Returns Result<TokenKind, LexerError> to handle potential errors
-    pub fn get_next_token(&mut self) -> Result<TokenKind, LexerError> {
+    /// Returns Result<Token, LexerError> to handle potential errors.
+    /// Comments are skipped unless LexerConfig::preserve_comments is set, which returns each one as a Comment token
+    pub fn get_next_token(&mut self) -> Result<Token, LexerError> {
         self.skip_whitespace();
+        loop {
+            let location = self.get_location();
+            let text = match (self.current_char, self.peek_char()) {
+                (Some('#'), _) => self.skip_line_comment(),
+                (Some('/'), Some('*')) => self.skip_block_comment()?,
+                _ => break,
+            };
+            if self.config.preserve_comments {
+                return Ok(Token { kind: TokenKind::Comment(text), span: self.span_from(location) });
+            }
+            self.skip_whitespace();
+        }
         let location = self.get_location();
-
-        if self.current_char.is_none() {
-            return Ok(TokenKind::EOF);
+        if let Some(kind) = self.current_char.and_then(punctuation) {
+            self.advance();
+            return Ok(Token { kind, span: self.span_from(location) });
         }
 
-        match self.current_char {
-            None => Ok(TokenKind::EOF),
+        let kind = match self.current_char {
+            None => TokenKind::EOF,
             Some(c) => match c {
-                c if c.is_alphabetic() => Ok(self.identifier()?),
-                '#' => {
-                    self.skip_line_comment();
-                    self.get_next_token()
-                },
-                ',' => { self.advance(); Ok(TokenKind::Comma) },
-                '(' => { self.advance(); Ok(TokenKind::ParenOpen) },
-                ')' => { self.advance(); Ok(TokenKind::ParenClose) },
-                '\n' => { self.advance(); Ok(TokenKind::Newline) },
-                _ => Err(LexerError::UnexpectedCharacter(c, location)),
+                '\n' | '\r' => { self.skip_newline(); TokenKind::Newline },
+                c if c.is_ascii_digit() => self.number()?,
+                c if (self.config.identifier_start)(c) => self.identifier()?,
+                c if is_non_ascii_alphanumeric(c) => return Err(self.non_ascii_identifier(String::new(), location)),
+                _ => {
+                    let end = Location::new(location.line, location.column + 1);
+                    return Err(LexerError::UnexpectedCharacter(c, Span { start: location, end }));
+                }
+            }
+        };
+
+        Ok(Token { kind, span: self.span_from(location) })
+    }
+
+    /// Tokenizes the remaining source code, including the final EOF token
+    /// Stops at the first LexerError
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, LexerError> {
+        let mut tokens = vec![];
+        loop {
+            let token = self.get_next_token()?;
+            let is_eof = token.kind == TokenKind::EOF;
+            tokens.push(token);
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    /// Tokenizes the remaining source code, skipping over invalid input instead of stopping
+    /// Returns every token that could be read, including the final EOF token, and all errors found
+    pub fn tokenize_recovering(&mut self) -> (Vec<Token>, Vec<LexerError>) {
+        let mut tokens = vec![];
+        let mut errors = vec![];
+        loop {
+            match self.get_next_token() {
+                Ok(token) => {
+                    let is_eof = token.kind == TokenKind::EOF;
+                    tokens.push(token);
+                    if is_eof {
+                        return (tokens, errors);
+                    }
+                }
+                Err(err) => {
+                    if let LexerError::UnexpectedCharacter(..) = err {
+                        self.skip_invalid_characters();
+                    }
+                    errors.push(err);
+                }
+            }
+        }
+    }
+
+    /// Skips the current character and any directly following characters that cannot start a token,
+    /// so a run of stray characters is reported once
+    fn skip_invalid_characters(&mut self) {
+        self.advance();
+        while let Some(c) = self.current_char {
+            let starts_token = c.is_whitespace()
+                || matches!(c, '#' | '/')
+                || punctuation(c).is_some()
+                || c.is_ascii_digit()
+                || (self.config.identifier_start)(c);
+            if starts_token {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    /// Processes a run of decimal digits, or a bus literal when the digits are followed by a quote
+    /// A run that continues with identifier characters is lexed as an identifier instead,
+    /// if the configuration allows identifiers to start with a digit
+    fn number(&mut self) -> Result<TokenKind, LexerError> {
+        let location = self.get_location();
+        let mut digits = String::new();
+
+        while let Some(c) = self.current_char {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                self.advance();
+            } else {
+                break;
             }
         }
+
+        if self.current_char == Some('\'') {
+            return self.bus_literal(digits, location);
+        }
+
+        if self.current_char.is_some_and(self.config.identifier_continue) {
+            let first = digits.chars().next().unwrap_or_default();
+            if (self.config.identifier_start)(first) {
+                return self.identifier_from(digits, location);
+            }
+            while let Some(c) = self.current_char.filter(|&c| (self.config.identifier_continue)(c)) {
+                digits.push(c);
+                self.advance();
+            }
+            return Err(LexerError::InvalidIdentifier(digits, self.span_from(location)));
+        }
+
+        digits.parse()
+            .map(TokenKind::Number)
+            .map_err(|_| LexerError::InvalidNumber(digits, self.span_from(location)))
+    }
+
+    /// Processes the rest of a bus literal such as 4'b1010, 4'o12, 4'd10 or 4'hA, whose width has already been consumed
+    /// The base letter and hexadecimal digits may be written in either case, and '_' may separate digits.
+    /// Returns LexerError::InvalidNumber for a zero width, an unknown base or a digit outside the base
+    fn bus_literal(&mut self, width: String, location: Location) -> Result<TokenKind, LexerError> {
+        let mut text = format!("{}'", width);
+        self.advance();
+        while let Some(c) = self.current_char.filter(|&c| c.is_ascii_alphanumeric() || c == '_') {
+            text.push(c);
+            self.advance();
+        }
+
+        let mut value = text[width.len() + 1..].chars();
+        let base = match value.next().map(|c| c.to_ascii_lowercase()) {
+            Some('b') => 2,
+            Some('o') => 8,
+            Some('d') => 10,
+            Some('h') => 16,
+            _ => return Err(LexerError::InvalidNumber(text, self.span_from(location))),
+        };
+        let digits: Vec<char> = value.filter(|&c| c != '_').collect();
+        let width = width.parse::<usize>().ok().filter(|&width| width > 0);
+        let (Some(width), false) = (width, digits.is_empty()) else {
+            return Err(LexerError::InvalidNumber(text, self.span_from(location)));
+        };
+
+        let mut bits = vec![];
+        for c in digits {
+            let Some(digit) = c.to_digit(base) else {
+                return Err(LexerError::InvalidNumber(text, self.span_from(location)));
+            };
+            push_digit(&mut bits, base, digit);
+        }
+        Ok(TokenKind::BusLiteral { width, bits })
     }
 
     /// Processes identifiers and keywords
@@ -148,44 +603,106 @@ impl Lexer {
         let location = self.get_location();
         let mut id_str = String::new();
 
+        if let Some(c) = self.current_char {
+            if (self.config.identifier_start)(c) {
+                id_str.push(c);
+                self.advance();
+            }
+        }
+
+        self.identifier_from(id_str, location)
+    }
+
+    /// Finishes an identifier or keyword whose first characters have already been consumed
+    fn identifier_from(&mut self, mut id_str: String, location: Location) -> Result<TokenKind, LexerError> {
         while let Some(c) = self.current_char {
-            if c.is_alphanumeric() || c == '_' {
+            if (self.config.identifier_continue)(c) {
                 id_str.push(c);
                 self.advance();
             } else {
                 break;
             }
         }
+        if self.current_char.is_some_and(is_non_ascii_alphanumeric) {
+            return Err(self.non_ascii_identifier(id_str, location));
+        }
 
         if id_str.is_empty() {
-            return Err(LexerError::InvalidIdentifier(id_str, location));
+            return Err(LexerError::InvalidIdentifier(id_str, self.span_from(location)));
         }
 
         // Match against known keywords, return Identifier if not a keyword
-        Ok(match id_str.to_uppercase().as_str() {
-            "INPUTS" => TokenKind::Inputs,
-            "OUTPUTS" => TokenKind::Outputs,
-            "IN" => TokenKind::In,
-            "OUT" => TokenKind::Out,
-            "AND" => TokenKind::And,
-            "OR" => TokenKind::Or,
-            "NOT" => TokenKind::Not,
-            "NAND" => TokenKind::Nand,
-            "NOR" => TokenKind::Nor,
-            "XOR" => TokenKind::Xor,
-            "XNOR" => TokenKind::Xnor,
-            "SUBCIRCUIT" => TokenKind::Subcircuit,
-            "END" => TokenKind::End,
-            _ => TokenKind::Identifier(id_str),
+        let keyword = if self.config.case_sensitive_keywords {
+            id_str.clone()
+        } else {
+            id_str.to_uppercase()
+        };
+        Ok(match keyword_of(&keyword) {
+            Some(kind) => kind,
+            None => match self.config.aliases.get(&keyword) {
+                Some(kind) => kind.clone(),
+                None => TokenKind::Identifier(id_str),
+            },
         })
     }
+
+    /// Consumes the rest of a word that contains letters or digits the configuration does not accept
+    /// and reports the whole word, so "signalé" is one error rather than an identifier and a stray character
+    fn non_ascii_identifier(&mut self, mut word: String, location: Location) -> LexerError {
+        while let Some(c) = self.current_char.filter(|&c| c.is_alphanumeric() || c == '_') {
+            word.push(c);
+            self.advance();
+        }
+        LexerError::NonAsciiIdentifier(word, self.span_from(location))
+    }
+}
+
+/// Multiplies a number, given as bits from the least significant one on, by the base and adds the digit
+/// The number never gains high zero bits, so its length is the number of bits its value needs
+fn push_digit(bits: &mut Vec<bool>, base: u32, digit: u32) {
+    let mut carry = digit;
+    for bit in bits.iter_mut() {
+        let value = u32::from(*bit) * base + carry;
+        *bit = value % 2 == 1;
+        carry = value / 2;
+    }
+    while carry > 0 {
+        bits.push(carry % 2 == 1);
+        carry /= 2;
+    }
+}
+
+/// Returns the token of a character that is a token on its own, such as "," or "="
+/// The lexer dispatches on this, and error recovery stops at these characters so it never skips a valid token
+fn punctuation(c: char) -> Option<TokenKind> {
+    Some(match c {
+        ',' => TokenKind::Comma,
+        '(' => TokenKind::ParenOpen,
+        ')' => TokenKind::ParenClose,
+        '[' => TokenKind::BracketOpen,
+        ']' => TokenKind::BracketClose,
+        ':' => TokenKind::Colon,
+        '=' => TokenKind::Equals,
+        '!' => TokenKind::Bang,
+        ';' => TokenKind::Semicolon,
+        _ => return None,
+    })
+}
+
+/// Returns true for letters and digits outside ASCII, which identifiers may only contain
+/// if LexerConfig::identifier_start and identifier_continue are changed to accept them
+fn is_non_ascii_alphanumeric(c: char) -> bool {
+    !c.is_ascii() && c.is_alphanumeric()
 }
 
 /// Represents possible errors that can occur during lexical analysis 
 #[derive(Debug)]
 pub enum LexerError {
-    UnexpectedCharacter(char, Location),
-    InvalidIdentifier(String, Location),
+    UnexpectedCharacter(char, Span),
+    InvalidIdentifier(String, Span),
+    UnterminatedComment(Span),
+    InvalidNumber(String, Span),
+    NonAsciiIdentifier(String, Span),
 }
 
 impl std::error::Error for LexerError {}
@@ -194,39 +711,106 @@ impl std::error::Error for LexerError {}
 impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            LexerError::UnexpectedCharacter(c, loc) => 
-                write!(f, "Unexpected character '{}' at line {}, column {}", c, loc.line, loc.column),
-            LexerError::InvalidIdentifier(id, loc) => 
-                write!(f, "Invalid identifier '{}' at line {}, column {}", id, loc.line, loc.column),
+            LexerError::UnexpectedCharacter(c, span) => 
+                write!(f, "Unexpected character '{}' at {}", c, span.start),
+            LexerError::InvalidIdentifier(id, span) => 
+                write!(f, "Invalid identifier '{}' at {}", id, span.start),
+            LexerError::UnterminatedComment(span) => 
+                write!(f, "Unterminated block comment starting at {}", span.start),
+            LexerError::InvalidNumber(digits, span) => 
+                write!(f, "Invalid number '{}' at {}", digits, span.start),
+            LexerError::NonAsciiIdentifier(id, span) =>
+                write!(f, "Identifier '{}' contains non-ASCII characters at {}", id, span.start),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Subcircuit {
     pub name: String,
     pub inputs: Vec<String>,
     pub outputs: Vec<String>,
     pub components: Vec<Component>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub aliases: Vec<Alias>, // Other names for signals of the subcircuit, declared with ALIAS
+    pub location: Location, // Location of the SUBCIRCUIT keyword
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub comments: Vec<String>, // Comments just before the definition, kept when the lexer preserves comments
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub output_locations: HashMap<String, Location>, // Output -> location of its name in the OUTPUTS section
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub subcircuits: HashMap<String, Subcircuit>,
-    pub inputs: Vec<String>,
-    pub outputs: Vec<String>,
+    pub inputs: Vec<String>,  // Input names in declaration order, which truth tables and their CSV columns follow
+    pub outputs: Vec<String>, // Output names in declaration order, which truth tables and their CSV columns follow
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub constants: HashMap<String, bool>, // Named constant signals of the main circuit, declared with CONST
     pub components: Vec<Component>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub aliases: Vec<Alias>, // Other names for signals of the main circuit, declared with ALIAS
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub comments: Vec<String>, // Comments just before the INPUTS section, kept when the lexer preserves comments
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub output_locations: HashMap<String, Location>, // Output -> location of its name in the OUTPUTS section
 }
 
-#[derive(Debug)]
+/// Another name for a signal, declared with "ALIAS name = target"
+/// Reading the alias reads the target, so it behaves like a BUF without being a component
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Alias {
+    pub name: String,
+    pub target: String,
+    pub location: Location, // Location of the ALIAS keyword
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Component {
     pub gate_type: GateType,
     pub identifier: String,
-    pub inputs: Vec<String>,
+    pub inputs: Vec<Signal>,
     pub outputs: Vec<String>,
+    pub location: Location,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub comments: Vec<String>, // Comments just before the component, kept when the lexer preserves comments
 }
 
-#[derive(Debug)]
+/// A value feeding a component input, either a named wire or a constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Signal {
+    Wire(String),
+    Constant(bool),
+}
+
+impl Signal {
+    /// Returns the wire name, or None for constants
+    pub fn wire(&self) -> Option<&str> {
+        match self {
+            Signal::Wire(name) => Some(name),
+            Signal::Constant(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signal::Wire(name) => write!(f, "{}", name),
+            Signal::Constant(value) => write!(f, "{}", *value as u8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "name", rename_all = "lowercase"))]
 pub enum GateType {
     And,
     Or,
@@ -235,175 +819,561 @@ pub enum GateType {
     Nor,
     Xor,
     Xnor,
+    Buf,
+    Tri,
+    Dff,
+    SrLatch, // IN(s, r) OUT(q)
+    DLatch, // IN(d, enable) OUT(q)
+    HalfAdder, // IN(a, b) OUT(sum, carry)
+    FullAdder, // IN(a, b, cin) OUT(sum, cout)
     Subcircuit(String),
 }
 
-pub struct Parser {
-    tokens: Vec<TokenKind>,
-    position: usize,
-}
+impl GateType {
+    /// Returns the source keyword of a primitive gate, or the subcircuit name for instances
+    pub fn keyword(&self) -> &str {
+        match self {
+            GateType::And => "AND",
+            GateType::Or => "OR",
+            GateType::Not => "NOT",
+            GateType::Nand => "NAND",
+            GateType::Nor => "NOR",
+            GateType::Xor => "XOR",
+            GateType::Xnor => "XNOR",
+            GateType::Buf => "BUF",
+            GateType::Tri => "TRI",
+            GateType::Dff => "DFF",
+            GateType::SrLatch => "SRLATCH",
+            GateType::DLatch => "DLATCH",
+            GateType::HalfAdder => "HALFADDER",
+            GateType::FullAdder => "FULLADDER",
+            GateType::Subcircuit(name) => name,
+        }
+    }
 
-impl Parser {
-    pub fn new(tokens: Vec<TokenKind>) -> Self {
-        Parser { tokens, position: 0 }
+    /// Returns the fewest inputs the gate takes
+    /// Subcircuit instances return 0, since their input count comes from the subcircuit definition
+    pub fn min_inputs(&self) -> usize {
+        match self {
+            GateType::Not | GateType::Buf => 1,
+            GateType::FullAdder => 3,
+            GateType::Subcircuit(_) => 0,
+            _ => 2,
+        }
     }
 
-    fn current_token(&self) -> Option<&TokenKind> {
-        self.tokens.get(self.position)
+    /// Returns the most inputs the gate takes, or None if there is no limit
+    /// Subcircuit instances return None, since their input count comes from the subcircuit definition
+    pub fn max_inputs(&self) -> Option<usize> {
+        match self {
+            GateType::Not | GateType::Buf => Some(1),
+            GateType::Tri | GateType::Dff | GateType::SrLatch | GateType::DLatch | GateType::HalfAdder => Some(2),
+            GateType::FullAdder => Some(3),
+            _ => None,
+        }
     }
 
-    fn advance(&mut self) {
-        self.position += 1;
+    /// Returns the number of outputs the gate drives, or None for subcircuit instances,
+    /// which drive as many outputs as the subcircuit declares
+    /// Adders drive their sum followed by their carry, the other primitive gates drive one output
+    pub fn output_count(&self) -> Option<usize> {
+        match self {
+            GateType::HalfAdder | GateType::FullAdder => Some(2),
+            GateType::Subcircuit(_) => None,
+            _ => Some(1),
+        }
     }
 
-    fn expect(&mut self, expected: TokenKind) -> Result<(), String> {
-        if let Some(token) = self.current_token() {
-            if *token == expected {
-                self.advance();
-                Ok(())
-            } else {
-                Err(format!("Expected {:?}, found {:?}", expected, token))
-            }
-        } else {
-            Err(format!("Unexpected end of input, expected {:?}", expected))
+    /// Returns the Verilog operator of a combinational gate, such as "&" for AND, "~&" for NAND and "~" for NOT
+    /// BUF has no operator and returns an empty string, TRI, DFF, latches, adders and subcircuit instances return their keyword
+    pub fn symbol(&self) -> &str {
+        match self {
+            GateType::And => "&",
+            GateType::Or => "|",
+            GateType::Xor => "^",
+            GateType::Nand => "~&",
+            GateType::Nor => "~|",
+            GateType::Xnor => "~^",
+            GateType::Not => "~",
+            GateType::Buf => "",
+            gate_type => gate_type.keyword(),
         }
     }
+
+    /// Returns true if the order of the inputs does not affect the outputs, which holds for AND, OR, XOR,
+    /// their inverses and the adders
+    pub fn is_commutative(&self) -> bool {
+        matches!(
+            self,
+            GateType::And | GateType::Or | GateType::Xor | GateType::Nand | GateType::Nor | GateType::Xnor
+                | GateType::HalfAdder | GateType::FullAdder
+        )
+    }
+}
+
+/// Deepest nesting the parser accepts unless changed with Parser::set_max_depth
+/// Counts parentheses and NOTs in an expression, and subcircuit instances inside each other
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    comments: Vec<Token>, // Comment tokens taken out of the token list, in source order
+    next_comment: usize,  // Index of the first comment not yet attached to a definition
+    position: usize,
+    depth: usize,     // Nesting level of the expression being parsed
+    max_depth: usize, // Deepest nesting accepted before ParseError::NestingTooDeep
+    external_subcircuits: bool, // Whether instances may name subcircuits the source does not define
 }
 
 impl Parser {
-    pub fn parse_program(&mut self) -> Result<Program, String> {
+    /// Creates a parser for the given tokens
+    /// Comment tokens, which the lexer emits when it preserves comments, are set aside and attached
+    /// to the subcircuit, component or main circuit they precede
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let (comments, tokens) = tokens.into_iter().partition(|token| matches!(token.kind, TokenKind::Comment(_)));
+        Parser {
+            tokens,
+            comments,
+            next_comment: 0,
+            position: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            external_subcircuits: false,
+        }
+    }
 
-        // Parse subcircuits first
-        let mut subcircuits = HashMap::new();
-        
-        while let Some(token) = self.current_token() {
-            match token {
-                TokenKind::Subcircuit => {
-                    let subcircuit = self.parse_subcircuit()?;
-                    subcircuits.insert(subcircuit.name.clone(), subcircuit);
-                }
-                _ => break // Break if it's not a subcircuit
+    /// Returns the text of the comments that start before the given location and are not attached yet
+    fn take_comments(&mut self, before: Location) -> Vec<String> {
+        let mut comments = vec![];
+        while let Some(token) = self.comments.get(self.next_comment) {
+            let start = token.span.start;
+            if (start.line, start.column) >= (before.line, before.column) {
+                break;
+            }
+            if let TokenKind::Comment(text) = &token.kind {
+                comments.push(text.clone());
             }
+            self.next_comment += 1;
         }
+        comments
+    }
 
-        let inputs = self.parse_inputs_section()?;
-        let outputs = self.parse_outputs_section()?;
-        let components = self.parse_component_list()?;
-        Ok(Program { subcircuits, inputs, outputs, components })
+    /// Sets whether instances may name subcircuits that the parsed source does not define
+    /// Such subcircuits are expected to come from a library merged in with Program::with_library,
+    /// and Program::validate reports the ones that are still missing
+    pub fn set_external_subcircuits(&mut self, allowed: bool) {
+        self.external_subcircuits = allowed;
     }
-}
 
-impl Parser {
-    fn parse_subcircuit(&mut self) -> Result<Subcircuit, String> {
-        self.expect(TokenKind::Subcircuit)?;
-        
-        let name = if let Some(TokenKind::Identifier(name)) = self.current_token() {
-            name.clone()
-        } else {
-            return Err("Expected subcircuit name".to_string());
-        };
-        self.advance();
-        
-        self.expect(TokenKind::Newline)?;
-        
-        let inputs = self.parse_inputs_section()?;
-        
-        let outputs = self.parse_outputs_section()?;
-        
-        let components = self.parse_component_list()?;
-        
-        self.expect(TokenKind::End)?;
-        
-        self.expect(TokenKind::Newline)?;
-        
+    /// Sets the deepest nesting of expressions and subcircuit instances that parse_program accepts
+    /// Deeper input fails with ParseError::NestingTooDeep instead of exhausting the stack
+    pub fn set_max_depth(&mut self, limit: usize) {
+        self.max_depth = limit;
+    }
+
+    /// Returns the token n positions ahead of the current one without advancing
+    pub fn peek(&self, n: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.position + n).map(|token| &token.kind)
+    }
+
+    fn current_token(&self) -> Option<&TokenKind> {
+        self.peek(0)
+    }
+
+    /// Builds ParseError::KeywordAsIdentifier if the current token is a keyword where a name is required
+    fn keyword_as_identifier(&self) -> Option<ParseError> {
+        let token = self.tokens.get(self.position)?;
+        let keyword = token.kind.keyword()?;
+        Some(ParseError::KeywordAsIdentifier { keyword, location: token.span.start })
+    }
+
+    /// Skips any blank lines at the current position
+    fn skip_newlines(&mut self) {
+        while self.current_token() == Some(&TokenKind::Newline) {
+            self.advance();
+        }
+    }
+
+    /// Returns true if the current token ends a statement, which is either a newline or a semicolon
+    fn at_terminator(&self) -> bool {
+        matches!(self.current_token(), Some(TokenKind::Newline) | Some(TokenKind::Semicolon))
+    }
+
+    /// Skips any newlines and semicolons at the current position
+    fn skip_terminators(&mut self) {
+        while self.at_terminator() {
+            self.advance();
+        }
+    }
+
+    /// Consumes the newline or semicolon that ends a statement
+    fn expect_terminator(&mut self) -> Result<(), ParseError> {
+        if self.at_terminator() {
+            self.advance();
+            Ok(())
+        } else {
+            self.expect(TokenKind::Newline)
+        }
+    }
+
+    /// Builds an error for a token that is not valid in the given context
+    fn unexpected_in(&self, context: &'static str) -> ParseError {
+        match self.tokens.get(self.position) {
+            Some(token) if token.kind != TokenKind::EOF =>
+                ParseError::InvalidToken { context, found: token.kind.clone(), location: token.span.start },
+            _ => self.end_of_input(context.to_string()),
+        }
+    }
+
+    /// Builds an error for a token that is none of the alternatives valid at a branch of the grammar
+    fn expected_one_of(&self, one_of: &[&'static str]) -> ParseError {
+        match self.tokens.get(self.position) {
+            Some(token) if token.kind != TokenKind::EOF =>
+                ParseError::Expected { one_of: one_of.to_vec(), found: token.kind.clone(), location: token.span.start },
+            _ => self.end_of_input(one_of.join(" or ")),
+        }
+    }
+
+    /// Builds an error for a comma that does not follow a name in a list
+    fn unexpected_comma(&self) -> ParseError {
+        let location = self.tokens.get(self.position).map_or(Location::new(1, 1), |token| token.span.start);
+        ParseError::UnexpectedComma { location }
+    }
+
+    /// Builds an error for input that ends while the parser still expects something
+    /// The error points just past the last token, which is where the EOF token sits
+    fn end_of_input(&self, expected: String) -> ParseError {
+        let location = self.tokens.get(self.position)
+            .or(self.tokens.last())
+            .map_or(Location::new(1, 1), |token| token.span.end);
+        ParseError::UnexpectedEof { expected, location }
+    }
+
+    fn advance(&mut self) {
+        self.position += 1;
+    }
+
+    fn expect(&mut self, expected: TokenKind) -> Result<(), ParseError> {
+        match self.tokens.get(self.position) {
+            Some(token) if token.kind == expected => {
+                self.advance();
+                Ok(())
+            }
+            Some(token) if token.kind != TokenKind::EOF =>
+                Err(ParseError::UnexpectedToken { expected, found: token.kind.clone(), location: token.span.start }),
+            _ => Err(self.end_of_input(format!("{:?}", expected))),
+        }
+    }
+
+    /// Consumes the keyword that opens a section and returns its location
+    /// Returns ParseError::MissingSection if the section is absent and ParseError::EmptySection
+    /// if the keyword is not followed by at least one name
+    fn expect_section(&mut self, keyword: TokenKind, section: &'static str) -> Result<Location, ParseError> {
+        self.skip_terminators();
+        let location = match self.tokens.get(self.position) {
+            Some(token) if token.kind == keyword => token.span.start,
+            Some(token) => return Err(ParseError::MissingSection { section, found: token.kind.clone(), location: token.span.start }),
+            None => return Err(self.end_of_input(format!("{} section", section))),
+        };
+        self.advance();
+
+        if self.at_terminator() || matches!(self.current_token(), None | Some(TokenKind::EOF)) {
+            return Err(ParseError::EmptySection { section, location });
+        }
+        Ok(location)
+    }
+}
+
+impl Parser {
+    pub fn parse_program(&mut self) -> Result<Program, ParseError> {
+
+        // Parse subcircuits first, which may be preceded and separated by blank lines
+        let subcircuits = self.parse_subcircuits()?;
+
+        let comments = match self.tokens.get(self.position) {
+            Some(token) => self.take_comments(token.span.start),
+            None => vec![],
+        };
+        let (inputs, _) = self.parse_port_section(TokenKind::Inputs)?;
+        let (outputs, output_locations) = self.parse_port_section(TokenKind::Outputs)?;
+        let constants = self.parse_constants_section(&inputs, &outputs)?;
+        let ports = inputs.iter().chain(&outputs).chain(constants.keys()).map(String::as_str).collect();
+        self.skip_terminators();
+        let (components, aliases) = self.parse_component_list(&ports, "end of input")?;
+        if let Some(token) = self.tokens.get(self.position).filter(|token| token.kind != TokenKind::EOF) {
+            return Err(ParseError::TrailingTokens { location: token.span.start });
+        }
+        let program = Program { subcircuits, inputs, outputs, constants, components, aliases, comments, output_locations };
+        check_subcircuit_references(&program, self.external_subcircuits)?;
+        check_subcircuit_depth(&program, self.max_depth)?;
+        Ok(program)
+    }
+
+    /// Parses a library that contains only subcircuit definitions, without a main circuit
+    /// Subcircuits may instantiate each other, and the result can be merged into a program's subcircuits.
+    /// Returns ParseError::Expected if anything other than a subcircuit follows the definitions
+    pub fn parse_library(&mut self) -> Result<HashMap<String, Subcircuit>, ParseError> {
+        let subcircuits = self.parse_subcircuits()?;
+        if !matches!(self.current_token(), None | Some(TokenKind::EOF)) {
+            return Err(self.expected_one_of(&["SUBCIRCUIT", "end of input"]));
+        }
+
+        let library = Program {
+            subcircuits,
+            inputs: vec![],
+            outputs: vec![],
+            constants: HashMap::new(),
+            components: vec![],
+            aliases: vec![],
+            comments: vec![],
+            output_locations: HashMap::new(),
+        };
+        check_subcircuit_references(&library, self.external_subcircuits)?;
+        check_subcircuit_depth(&library, self.max_depth)?;
+        Ok(library.subcircuits)
+    }
+
+    /// Parses consecutive subcircuit definitions, which may be preceded and separated by blank lines
+    /// Returns ParseError::DuplicateSubcircuit if two of them share a name
+    fn parse_subcircuits(&mut self) -> Result<HashMap<String, Subcircuit>, ParseError> {
+        let mut subcircuits: HashMap<String, Subcircuit> = HashMap::new();
+
+        loop {
+            self.skip_terminators();
+            if self.current_token() != Some(&TokenKind::Subcircuit) {
+                return Ok(subcircuits);
+            }
+            let subcircuit = self.parse_subcircuit()?;
+            if let Some(first) = subcircuits.get(&subcircuit.name) {
+                return Err(ParseError::DuplicateSubcircuit {
+                    name: subcircuit.name,
+                    first_location: first.location,
+                    second_location: subcircuit.location,
+                });
+            }
+            subcircuits.insert(subcircuit.name.clone(), subcircuit);
+        }
+    }
+}
+
+impl Parser {
+    /// Parses one subcircuit definition, from its SUBCIRCUIT line up to and including the line of its END
+    /// Instances inside it are not checked against other subcircuits, which parse_program and parse_library do
+    pub fn parse_subcircuit(&mut self) -> Result<Subcircuit, ParseError> {
+        let location = match self.tokens.get(self.position) {
+            Some(token) => token.span.start,
+            None => return Err(self.unexpected_in("subcircuit")),
+        };
+        let comments = self.take_comments(location);
+        self.expect(TokenKind::Subcircuit)?;
+        
+        let name = if let Some(TokenKind::Identifier(name)) = self.current_token() {
+            name.clone()
+        } else if let Some(err) = self.keyword_as_identifier() {
+            return Err(err);
+        } else {
+            return Err(match self.tokens.get(self.position) {
+                Some(token) if token.kind != TokenKind::EOF =>
+                    ParseError::ExpectedSubcircuitName { found: token.kind.clone(), location: token.span.start },
+                _ => self.end_of_input("subcircuit name".to_string()),
+            });
+        };
+        self.advance();
+        
+        self.expect_terminator()?;
+        
+        let (inputs, _) = self.parse_port_section(TokenKind::Inputs)?;
+        
+        let (outputs, output_locations) = self.parse_port_section(TokenKind::Outputs)?;
+        
+        let ports = inputs.iter().chain(&outputs).map(String::as_str).collect();
+        self.skip_terminators();
+        let (components, aliases) = self.parse_component_list(&ports, "END")?;
+
+        // Comments after the last component have nothing to precede inside the subcircuit
+        if let Some(token) = self.tokens.get(self.position) {
+            self.take_comments(token.span.start);
+        }
+        self.expect(TokenKind::End)?;
+        
+        self.expect_terminator()?;
+        
         Ok(Subcircuit {
             name,
             inputs,
             outputs,
             components,
+            aliases,
+            location,
+            comments,
+            output_locations,
         })
     }
 }
 
 impl Parser {
-    fn parse_inputs_section(&mut self) -> Result<Vec<String>, String> {
-
-        loop {
-            if self.current_token() == Some(&TokenKind::Newline) {
-                self.advance();
-            } else {
-               break;
-            }
-        }
-
-        self.expect(TokenKind::Inputs)?; // Expect "INPUTS"
-        let mut inputs = vec![];
+    /// Parses the INPUTS or OUTPUTS section of a scope, a comma-separated list of names and buses up to the end of the line
+    /// Returns the declared signals in order, and where each of them is named
+    fn parse_port_section(&mut self, section: TokenKind) -> Result<(Vec<String>, HashMap<String, Location>), ParseError> {
+        let (keyword, context) = match section {
+            TokenKind::Inputs => ("INPUTS", "INPUTS section"),
+            _ => ("OUTPUTS", "OUTPUTS section"),
+        };
+        self.expect_section(section, keyword)?;
+        let mut ports = vec![];
+        let mut locations = HashMap::new();
+        let mut after_name = false; // A comma must follow a name
 
         loop {
             match self.current_token() {
-                Some(TokenKind::Identifier(name)) => {
-                    inputs.push(name.clone());
+                Some(TokenKind::Identifier(_)) => {
+                    let (names, location) = self.parse_declaration()?;
+                    locations.extend(names.iter().map(|name| (name.clone(), location)));
+                    ports.extend(names);
+                    after_name = true;
+                }
+                Some(TokenKind::Comma) if after_name => {
                     self.advance();
+                    after_name = false;
                 }
-                Some(TokenKind::Comma) => self.advance(),
-                Some(TokenKind::Newline) => {
+                Some(TokenKind::Comma) => return Err(self.unexpected_comma()),
+                Some(TokenKind::Newline) | Some(TokenKind::Semicolon) => {
                     self.advance();
                     break;
                 }
-                _ => return Err(format!("Unexpected token in INPUTS section: {:?}", self.current_token()).to_string()),
+                _ => return Err(self.keyword_as_identifier().unwrap_or_else(|| self.unexpected_in(context))),
             }
         }
 
-        Ok(inputs)
+        Ok((ports, locations))
     }
 }
 
 impl Parser {
-    fn parse_outputs_section(&mut self) -> Result<Vec<String>, String> {
-        self.expect(TokenKind::Outputs)?; // Expect "OUTPUTS"
-        let mut inputs = vec![];
-
+    /// Parses the CONST lines that may follow the OUTPUTS section, such as "CONST gnd = 0"
+    /// Returns ParseError::DuplicateConstant for a name that is already an input, an output or a constant
+    fn parse_constants_section(&mut self, inputs: &[String], outputs: &[String]) -> Result<HashMap<String, bool>, ParseError> {
+        let mut constants = HashMap::new();
         loop {
-            match self.current_token() {
-                Some(TokenKind::Identifier(name)) => {
-                    inputs.push(name.clone());
+            self.skip_terminators();
+            if self.current_token() != Some(&TokenKind::Const) {
+                return Ok(constants);
+            }
+            self.advance();
+
+            let location = match self.tokens.get(self.position) {
+                Some(token) => token.span.start,
+                None => return Err(self.unexpected_in("constant name")),
+            };
+            let names = self.parse_reference()?;
+            self.expect(TokenKind::Equals)?;
+            let bits = match self.current_token() {
+                Some(TokenKind::Number(value @ (0 | 1))) => {
+                    let bits = vec![*value == 1];
                     self.advance();
+                    bits
                 }
-                Some(TokenKind::Comma) => self.advance(),
-                Some(TokenKind::Newline) => {
-                    self.advance();
-                    break;
+                Some(TokenKind::BusLiteral { .. }) => self.expect_bus_literal()?,
+                _ => return Err(self.expected_one_of(&["0", "1", "bus literal"])),
+            };
+
+            let names = literal_targets(names, bits.len());
+            if names.len() != bits.len() {
+                return Err(ParseError::LiteralWidthMismatch { expected: names.len(), found: bits.len(), location });
+            }
+            for (name, value) in names.into_iter().zip(bits.into_iter().rev()) {
+                if inputs.contains(&name) || outputs.contains(&name) || constants.contains_key(&name) {
+                    return Err(ParseError::DuplicateConstant { name, location });
                 }
-                _ => return Err(format!("Unexpected token in OUTPUTS section: {:?}", self.current_token()).to_string()),
+                constants.insert(name, value);
+            }
+            if !matches!(self.current_token(), None | Some(TokenKind::EOF)) {
+                self.expect_terminator()?;
             }
         }
-
-        Ok(inputs)
     }
 }
 
+/// What may start a statement in a component list, as listed by ParseError::Expected
+const STATEMENT_STARTS: &[&str] = &[
+    "AND", "OR", "NOT", "NAND", "NOR", "XOR", "XNOR", "BUF", "TRI", "DFF", "SRLATCH", "DLATCH", "HALFADDER", "FULLADDER",
+    "subcircuit name", "assignment", "ALIAS",
+];
+
 impl Parser {
-    fn parse_component_list(&mut self) -> Result<Vec<Component>, String> {
+    /// Parses the components and aliases of a scope, whose ports are needed to name the signals created by assignments
+    /// The list ends at END or at the end of input, and the one the scope ends with is named in ParseError::Expected
+    fn parse_component_list(&mut self, ports: &HashSet<&str>, scope_end: &'static str) -> Result<(Vec<Component>, Vec<Alias>), ParseError> {
         let mut components = vec![];
+        let mut aliases = vec![];
+        let mut desugared = vec![];
 
         while let Some(token) = self.current_token() {
+            let location = self.tokens[self.position].span.start;
+            let start = components.len();
             match token {
+                TokenKind::Identifier(_) if matches!(self.peek(1), Some(TokenKind::Equals) | Some(TokenKind::BracketOpen)) => {
+                    self.parse_assignment(&mut components, &mut desugared)?;
+                }
+                TokenKind::Alias => {
+                    aliases.push(self.parse_alias()?);
+                }
                 TokenKind::And | TokenKind::Or | TokenKind::Not 
-                | TokenKind::Nand | TokenKind::Nor | TokenKind::Xor | TokenKind::Xnor | TokenKind::Identifier(_) => {
-                    components.push(self.parse_component()?);
+                | TokenKind::Nand | TokenKind::Nor | TokenKind::Xor | TokenKind::Xnor | TokenKind::Buf | TokenKind::Tri | TokenKind::Dff
+                | TokenKind::SrLatch | TokenKind::DLatch | TokenKind::HalfAdder | TokenKind::FullAdder
+                | TokenKind::Identifier(_) => {
+                    let (component, inverted) = self.parse_component()?;
+                    invert_connections(component, inverted, &mut components, &mut desugared);
                 }
-                TokenKind::Newline => {
+                TokenKind::Newline | TokenKind::Semicolon => {
                     self.advance();
                 }
                 TokenKind::End | TokenKind::EOF => break,
-                _ => return Err(format!("Unexpected token in component list: {:?}", self.current_token()).to_string()),
+                _ => {
+                    let mut one_of = STATEMENT_STARTS.to_vec();
+                    one_of.push(scope_end);
+                    return Err(self.expected_one_of(&one_of));
+                }
+            }
+
+            // Comments before a statement belong to the first component it produced, or wait for the next one
+            if components.len() > start {
+                components[start].comments = self.take_comments(location);
             }
         }
 
-        Ok(components)
+        // Temporaries must not take the name of an alias or of the signal it names
+        let names: HashSet<&str> = ports.iter().copied()
+            .chain(aliases.iter().flat_map(|alias| [alias.name.as_str(), alias.target.as_str()]))
+            .collect();
+        name_desugared_components(&mut components, &desugared, &names);
+        check_duplicate_components(&components)?;
+        name_anonymous_components(&mut components);
+        Ok((components, aliases))
+    }
+
+    /// Parses an "ALIAS name = target" line, where both sides are single signals such as "carry" or "sum[0]"
+    fn parse_alias(&mut self) -> Result<Alias, ParseError> {
+        let location = match self.tokens.get(self.position) {
+            Some(token) => token.span.start,
+            None => return Err(self.unexpected_in("alias")),
+        };
+        self.expect(TokenKind::Alias)?;
+        let name = single_signal(self.parse_reference()?, location)?;
+        self.expect(TokenKind::Equals)?;
+        let target = single_signal(self.parse_reference()?, location)?;
+        if !self.at_terminator() && !matches!(self.current_token(), None | Some(TokenKind::End) | Some(TokenKind::EOF)) {
+            return Err(self.unexpected_in("alias"));
+        }
+        Ok(Alias { name, target, location })
     }
 
-    fn parse_component(&mut self) -> Result<Component, String> {
+    /// Parses a gate or subcircuit instance, returning the positions of the connections written with a "!" prefix
+    fn parse_component(&mut self) -> Result<(Component, Inverted), ParseError> {
+        let location = match self.tokens.get(self.position) {
+            Some(token) => token.span.start,
+            None => return Err(self.unexpected_in("gate type")),
+        };
 
         let gate_type = match self.current_token() {
             Some(TokenKind::And) => GateType::And,
@@ -413,36 +1383,75 @@ impl Parser {
             Some(TokenKind::Nor) => GateType::Nor,
             Some(TokenKind::Xor) => GateType::Xor,
             Some(TokenKind::Xnor) => GateType::Xnor,
+            Some(TokenKind::Buf) => GateType::Buf,
+            Some(TokenKind::Tri) => GateType::Tri,
+            Some(TokenKind::Dff) => GateType::Dff,
+            Some(TokenKind::SrLatch) => GateType::SrLatch,
+            Some(TokenKind::DLatch) => GateType::DLatch,
+            Some(TokenKind::HalfAdder) => GateType::HalfAdder,
+            Some(TokenKind::FullAdder) => GateType::FullAdder,
             Some(TokenKind::Identifier(name)) => {
                 GateType::Subcircuit(name.clone())
             }
-            _ => return Err(format!(
-                "Unexpected token for gate type: {:?}",
-                self.current_token()
-            )),
+            _ => return Err(self.unexpected_in("gate type")),
         };
     
         self.advance();
     
+        // Subcircuit instances and primitive gates written without an identifier are named after parsing
         let identifier = if let GateType::Subcircuit(_) = gate_type {
             "".to_string()
+        } else if self.current_token() == Some(&TokenKind::In) {
+            "".to_string()
         } else {
             if let Some(TokenKind::Identifier(name)) = self.current_token() {
                 let id = name.clone();
                 self.advance();
                 id
+            } else if let Some(err) = self.keyword_as_identifier() {
+                return Err(err);
             } else {
-                return Err("Expected identifier for component".to_string());
+                return Err(match self.tokens.get(self.position) {
+                    Some(token) if token.kind != TokenKind::EOF =>
+                        ParseError::ExpectedIdentifier { found: token.kind.clone(), location: token.span.start },
+                    _ => self.end_of_input("component identifier".to_string()),
+                });
             }
         };
     
         self.expect(TokenKind::In)?;
         self.expect(TokenKind::ParenOpen)?;
     
+        // Signal lists may be spread over several lines
         let mut inputs = vec![];
-        while let Some(TokenKind::Identifier(name)) = self.current_token() {
-            inputs.push(name.clone());
-            self.advance();
+        let mut inverted = Inverted::default();
+        loop {
+            self.skip_newlines();
+            match self.current_token() {
+                Some(TokenKind::Identifier(_)) => {
+                    inputs.extend(self.parse_reference()?.into_iter().map(Signal::Wire));
+                }
+                Some(TokenKind::Bang) => {
+                    self.advance();
+                    let bits = self.parse_reference()?;
+                    inverted.inputs.extend(inputs.len()..inputs.len() + bits.len());
+                    inputs.extend(bits.into_iter().map(Signal::Wire));
+                }
+                Some(TokenKind::Number(value @ (0 | 1))) => {
+                    inputs.push(Signal::Constant(*value == 1));
+                    self.advance();
+                }
+                Some(TokenKind::Number(_)) => return Err(self.unexpected_in("signal list")),
+                Some(TokenKind::BusLiteral { .. }) => {
+                    inputs.extend(self.expect_bus_literal()?.into_iter().rev().map(Signal::Constant));
+                }
+                Some(TokenKind::Comma) => return Err(self.unexpected_comma()),
+                _ => match self.keyword_as_identifier() {
+                    Some(err) => return Err(err),
+                    None => break,
+                },
+            }
+            self.skip_newlines();
             if let Some(TokenKind::Comma) = self.current_token() {
                 self.advance();
             } else {
@@ -452,16 +1461,43 @@ impl Parser {
     
         self.expect(TokenKind::ParenClose)?;
 
-        
+        // A named single-output gate without an OUT clause drives a signal named after itself
+        if gate_type.output_count() == Some(1) && !identifier.is_empty() && self.current_token() != Some(&TokenKind::Out) {
+            let component = Component {
+                gate_type,
+                outputs: vec![identifier.clone()],
+                identifier,
+                inputs,
+                location,
+                comments: vec![],
+            };
+            return Ok((component, inverted));
+        }
+
         self.expect(TokenKind::Out)?;
         self.expect(TokenKind::ParenOpen)?;
     
         let mut outputs = vec![];
-        while let Some(TokenKind::Identifier(name)) = self.current_token() {
-            outputs.push(name.clone());
-            self.advance();
+        self.skip_newlines();
+        loop {
+            if let Some(err) = self.keyword_as_identifier() {
+                return Err(err);
+            }
+            match self.current_token() {
+                Some(TokenKind::Identifier(_)) => outputs.extend(self.parse_reference()?),
+                Some(TokenKind::Bang) => {
+                    self.advance();
+                    let bits = self.parse_reference()?;
+                    inverted.outputs.extend(outputs.len()..outputs.len() + bits.len());
+                    outputs.extend(bits);
+                }
+                Some(TokenKind::Comma) => return Err(self.unexpected_comma()),
+                _ => break,
+            }
+            self.skip_newlines();
             if let Some(TokenKind::Comma) = self.current_token() {
                 self.advance();
+                self.skip_newlines();
             } else {
                 break; 
             }
@@ -469,11 +1505,695 @@ impl Parser {
     
         self.expect(TokenKind::ParenClose)?;
         
-        Ok(Component {
+        let component = Component {
             gate_type,
             identifier,
             inputs,
             outputs,
-        })
+            location,
+            comments: vec![],
+        };
+        Ok((component, inverted))
     }    
 }
+
+/// The connections of a component written with a "!" prefix, such as "IN(!a, b)" or "OUT(!y)"
+#[derive(Default)]
+struct Inverted {
+    inputs: Vec<usize>,  // Positions of the inverted inputs
+    outputs: Vec<usize>, // Positions of the inverted outputs
+}
+
+/// Appends a component followed by a NOT gate for each of its inverted connections
+/// An inverted input reads the NOT of its signal and an inverted output drives the NOT of the signal,
+/// through temporary signals named "{signal}_n_{n}" by name_desugared_components
+fn invert_connections(mut component: Component, inverted: Inverted, components: &mut Vec<Component>, desugared: &mut Vec<Desugared>) {
+    let index = components.len();
+    let location = component.location;
+    let mut gates = vec![];
+    for position in inverted.inputs {
+        let signal = std::mem::replace(&mut component.inputs[position], Signal::Wire(String::new()));
+        let base = format!("{}_n", signal.wire().unwrap_or_default().replace('[', "_").replace(']', ""));
+        desugared.push(Desugared { component: index + 1 + gates.len(), base, reader: Some((index, position)), driver: None });
+        gates.push(Component {
+            gate_type: GateType::Not,
+            identifier: String::new(),
+            inputs: vec![signal],
+            outputs: vec![String::new()],
+            location,
+            comments: vec![],
+        });
+    }
+    for position in inverted.outputs {
+        let signal = std::mem::take(&mut component.outputs[position]);
+        let base = format!("{}_n", signal.replace('[', "_").replace(']', ""));
+        desugared.push(Desugared { component: index + 1 + gates.len(), base, reader: None, driver: Some((index, position)) });
+        gates.push(Component {
+            gate_type: GateType::Not,
+            identifier: String::new(),
+            inputs: vec![Signal::Wire(String::new())],
+            outputs: vec![signal],
+            location,
+            comments: vec![],
+        });
+    }
+    components.push(component);
+    components.extend(gates);
+}
+
+/// An inline gate expression, before it is lowered to components
+enum Expr {
+    Signal(Signal),
+    Gate { gate_type: GateType, operands: Vec<Expr>, location: Location },
+}
+
+/// A component created from an assignment, whose identifier is chosen once the whole scope is parsed
+struct Desugared {
+    component: usize,               // Index of the component in the scope
+    base: String,                   // Name the identifier is derived from, the assignment target without brackets
+    reader: Option<(usize, usize)>, // Component and input position reading the temporary signal, None if the component drives the target
+    driver: Option<(usize, usize)>, // Component and output position driving the temporary signal that the component reads instead
+}
+
+/// Number of binary operator precedence levels in inline expressions
+const BINARY_LEVELS: usize = 3;
+
+/// Returns the gate of a binary operator token if the operator belongs to the given precedence level
+/// Level 0 binds loosest: OR and NOR, then XOR and XNOR, then AND and NAND
+fn binary_operator(kind: &TokenKind, level: usize) -> Option<GateType> {
+    match (level, kind) {
+        (0, TokenKind::Or) => Some(GateType::Or),
+        (0, TokenKind::Nor) => Some(GateType::Nor),
+        (1, TokenKind::Xor) => Some(GateType::Xor),
+        (1, TokenKind::Xnor) => Some(GateType::Xnor),
+        (2, TokenKind::And) => Some(GateType::And),
+        (2, TokenKind::Nand) => Some(GateType::Nand),
+        _ => None,
+    }
+}
+
+impl Parser {
+    /// Parses an assignment such as "y = (a AND b) OR c" and appends the components it stands for
+    /// Every operator becomes one gate, and the result of a nested operator is carried by a temporary signal
+    /// An assignment of a plain signal, such as "y = a", becomes a BUF, and so does each bit of a bus literal
+    fn parse_assignment(&mut self, components: &mut Vec<Component>, desugared: &mut Vec<Desugared>) -> Result<(), ParseError> {
+        let location = match self.tokens.get(self.position) {
+            Some(token) => token.span.start,
+            None => return Err(self.unexpected_in("assignment")),
+        };
+        let targets = self.parse_reference()?;
+        self.expect(TokenKind::Equals)?;
+        let literal_ends = matches!(self.peek(1), None | Some(TokenKind::Newline | TokenKind::Semicolon | TokenKind::End | TokenKind::EOF));
+        if matches!(self.current_token(), Some(TokenKind::BusLiteral { .. })) && literal_ends {
+            return self.parse_literal_assignment(targets, location, components, desugared);
+        }
+
+        let target = single_signal(targets, location)?;
+        let expression = self.parse_expression()?;
+        if !self.at_terminator() && !matches!(self.current_token(), None | Some(TokenKind::End) | Some(TokenKind::EOF)) {
+            return Err(self.unexpected_in("expression"));
+        }
+
+        let base = target.replace('[', "_").replace(']', "");
+        let (gate_type, operands, location) = match expression {
+            Expr::Signal(signal) => (GateType::Buf, vec![Expr::Signal(signal)], location),
+            Expr::Gate { gate_type, operands, location } => (gate_type, operands, location),
+        };
+        let component = lower_expression(gate_type, operands, location, target, &base, components, desugared);
+        desugared.push(Desugared { component, base, reader: None, driver: None });
+        Ok(())
+    }
+
+    /// Parses the bus literal of an assignment such as "d = 4'hA" and drives each target bit with a BUF of its constant
+    /// Returns ParseError::LiteralWidthMismatch if the literal is not as wide as the targets
+    fn parse_literal_assignment(
+        &mut self,
+        targets: Vec<String>,
+        location: Location,
+        components: &mut Vec<Component>,
+        desugared: &mut Vec<Desugared>,
+    ) -> Result<(), ParseError> {
+        let bits = self.expect_bus_literal()?;
+        let targets = literal_targets(targets, bits.len());
+        if targets.len() != bits.len() {
+            return Err(ParseError::LiteralWidthMismatch { expected: targets.len(), found: bits.len(), location });
+        }
+
+        for (target, value) in targets.into_iter().zip(bits.into_iter().rev()) {
+            let base = target.replace('[', "_").replace(']', "");
+            desugared.push(Desugared { component: components.len(), base, reader: None, driver: None });
+            components.push(Component {
+                gate_type: GateType::Buf,
+                identifier: String::new(),
+                inputs: vec![Signal::Constant(value)],
+                outputs: vec![target],
+                location,
+                comments: vec![],
+            });
+        }
+        Ok(())
+    }
+
+    /// Parses an inline gate expression
+    /// NOT binds tightest, followed by AND and NAND, XOR and XNOR, and finally OR and NOR.
+    /// Operators of equal precedence group from the left
+    fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+        self.parse_binary(0)
+    }
+
+    /// Parses a chain of binary operators of one precedence level
+    /// Repeated AND, OR and XOR operators are merged into a single gate with more inputs
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, ParseError> {
+        if level == BINARY_LEVELS {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(gate_type) = self.current_token().and_then(|kind| binary_operator(kind, level)) {
+            let location = self.tokens[self.position].span.start;
+            self.advance();
+            let right = self.parse_binary(level + 1)?;
+            left = match left {
+                Expr::Gate { gate_type: previous, mut operands, location }
+                    if previous == gate_type && matches!(gate_type, GateType::And | GateType::Or | GateType::Xor) =>
+                {
+                    operands.push(right);
+                    Expr::Gate { gate_type, operands, location }
+                }
+                left => Expr::Gate { gate_type, operands: vec![left, right], location },
+            };
+        }
+        Ok(left)
+    }
+
+    /// Parses a NOT, a parenthesized expression, a signal reference, one of the constants 0 and 1 or a 1-bit literal
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let location = match self.tokens.get(self.position) {
+            Some(token) => token.span.start,
+            None => return Err(self.unexpected_in("expression")),
+        };
+        match self.current_token() {
+            Some(TokenKind::Not) => {
+                self.advance();
+                self.enter_nesting(location)?;
+                let operand = self.parse_unary()?;
+                self.depth -= 1;
+                Ok(Expr::Gate { gate_type: GateType::Not, operands: vec![operand], location })
+            }
+            Some(TokenKind::ParenOpen) => {
+                self.advance();
+                self.enter_nesting(location)?;
+                let expression = self.parse_expression()?;
+                self.depth -= 1;
+                self.expect(TokenKind::ParenClose)?;
+                Ok(expression)
+            }
+            Some(TokenKind::Identifier(_)) => {
+                let name = single_signal(self.parse_reference()?, location)?;
+                Ok(Expr::Signal(Signal::Wire(name)))
+            }
+            Some(TokenKind::Number(value @ (0 | 1))) => {
+                let value = *value == 1;
+                self.advance();
+                Ok(Expr::Signal(Signal::Constant(value)))
+            }
+            Some(TokenKind::BusLiteral { .. }) => match self.expect_bus_literal()?.as_slice() {
+                &[value] => Ok(Expr::Signal(Signal::Constant(value))),
+                _ => Err(ParseError::ExpectedSingleSignal { location }),
+            },
+            _ => Err(self.expected_one_of(&["NOT", "(", "signal name", "0", "1", "bus literal"])),
+        }
+    }
+}
+
+impl Parser {
+    /// Enters one more level of expression nesting, returning ParseError::NestingTooDeep past the limit
+    fn enter_nesting(&mut self, location: Location) -> Result<(), ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::NestingTooDeep { limit: self.max_depth, location });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+/// Returns the only signal of a reference, or ParseError::ExpectedSingleSignal for a bus slice
+fn single_signal(mut bits: Vec<String>, location: Location) -> Result<String, ParseError> {
+    match bits.len() {
+        1 => Ok(bits.remove(0)),
+        _ => Err(ParseError::ExpectedSingleSignal { location }),
+    }
+}
+
+/// Appends the components computing a gate of an expression and returns the index of the gate
+/// Nested gates come first and drive temporary signals, which are named by name_desugared_components
+fn lower_expression(
+    gate_type: GateType,
+    operands: Vec<Expr>,
+    location: Location,
+    output: String,
+    base: &str,
+    components: &mut Vec<Component>,
+    desugared: &mut Vec<Desugared>,
+) -> usize {
+    let mut inputs = vec![];
+    let mut temporaries = vec![];
+    for operand in operands {
+        match operand {
+            Expr::Signal(signal) => inputs.push(signal),
+            Expr::Gate { gate_type, operands, location } => {
+                let driver = lower_expression(gate_type, operands, location, String::new(), base, components, desugared);
+                temporaries.push((driver, inputs.len()));
+                inputs.push(Signal::Wire(String::new()));
+            }
+        }
+    }
+
+    let index = components.len();
+    components.push(Component { gate_type, identifier: String::new(), inputs, outputs: vec![output], location, comments: vec![] });
+    for (driver, position) in temporaries {
+        desugared.push(Desugared { component: driver, base: base.to_string(), reader: Some((index, position)), driver: None });
+    }
+    index
+}
+
+impl Parser {
+    /// Parses a port declaration, either a single name or a bus such as "a[4]"
+    /// A bus of width n declares the bits "a[0]" through "a[n-1]". Also returns the location of the name
+    fn parse_declaration(&mut self) -> Result<(Vec<String>, Location), ParseError> {
+        let (name, location) = self.expect_identifier()?;
+        if self.current_token() != Some(&TokenKind::BracketOpen) {
+            return Ok((vec![name], location));
+        }
+
+        self.advance();
+        let width = self.expect_number()?;
+        self.expect(TokenKind::BracketClose)?;
+        if width == 0 {
+            return Err(ParseError::InvalidBusWidth { name, location });
+        }
+        Ok(((0..width).map(|i| bus_bit(&name, i)).collect(), location))
+    }
+
+    /// Parses a signal reference, either a single name, one bus bit such as "a[2]",
+    /// or an inclusive bus slice such as "a[3:0]" whose bits are listed in the written order
+    fn parse_reference(&mut self) -> Result<Vec<String>, ParseError> {
+        let (name, _) = self.expect_identifier()?;
+        if self.current_token() != Some(&TokenKind::BracketOpen) {
+            return Ok(vec![name]);
+        }
+
+        self.advance();
+        let first = self.expect_number()?;
+        let last = if self.current_token() == Some(&TokenKind::Colon) {
+            self.advance();
+            self.expect_number()?
+        } else {
+            first
+        };
+        self.expect(TokenKind::BracketClose)?;
+
+        let bits: Vec<usize> = if first <= last {
+            (first..=last).collect()
+        } else {
+            (last..=first).rev().collect()
+        };
+        Ok(bits.into_iter().map(|i| bus_bit(&name, i)).collect())
+    }
+
+    /// Consumes an identifier token, returning its name and location
+    fn expect_identifier(&mut self) -> Result<(String, Location), ParseError> {
+        match self.tokens.get(self.position) {
+            Some(Token { kind: TokenKind::Identifier(name), span }) => {
+                let result = (name.clone(), span.start);
+                self.advance();
+                Ok(result)
+            }
+            _ => Err(self.keyword_as_identifier().unwrap_or_else(|| self.unexpected_in("signal name"))),
+        }
+    }
+
+    /// Consumes a number token, returning its value
+    fn expect_number(&mut self) -> Result<usize, ParseError> {
+        match self.current_token() {
+            Some(TokenKind::Number(value)) => {
+                let value = *value;
+                self.advance();
+                Ok(value)
+            }
+            _ => Err(self.unexpected_in("bus index")),
+        }
+    }
+
+    /// Consumes a bus literal token, returning its bits from the least significant one on, padded with zeros to its width
+    /// Returns ParseError::LiteralWidthMismatch if the value needs more bits than the width allows
+    fn expect_bus_literal(&mut self) -> Result<Vec<bool>, ParseError> {
+        let Some(Token { kind: TokenKind::BusLiteral { width, bits }, span }) = self.tokens.get(self.position) else {
+            return Err(self.unexpected_in("bus literal"));
+        };
+        if bits.len() > *width {
+            return Err(ParseError::LiteralWidthMismatch { expected: *width, found: bits.len(), location: span.start });
+        }
+        let mut bits = bits.clone();
+        bits.resize(*width, false);
+        self.advance();
+        Ok(bits)
+    }
+}
+
+/// Returns the signals a literal of the given width assigns to, most significant first
+/// A plain name assigned a literal wider than one bit stands for the bus bits name[width-1] down to name[0]
+fn literal_targets(targets: Vec<String>, width: usize) -> Vec<String> {
+    match targets.as_slice() {
+        [name] if width > 1 && !name.contains('[') => (0..width).rev().map(|i| bus_bit(name, i)).collect(),
+        _ => targets,
+    }
+}
+
+/// Returns the name of one bit of a bus
+pub(crate) fn bus_bit(name: &str, index: usize) -> String {
+    format!("{}[{}]", name, index)
+}
+
+/// Returns ParseError::DuplicateComponent if two components in one scope share an identifier
+fn check_duplicate_components(components: &[Component]) -> Result<(), ParseError> {
+    let mut seen: HashMap<&str, Location> = HashMap::new();
+    for component in components.iter().filter(|c| !c.identifier.is_empty()) {
+        if let Some(&first_location) = seen.get(component.identifier.as_str()) {
+            return Err(ParseError::DuplicateComponent {
+                name: component.identifier.clone(),
+                first_location,
+                second_location: component.location,
+            });
+        }
+        seen.insert(&component.identifier, component.location);
+    }
+    Ok(())
+}
+
+/// Checks that every subcircuit instance refers to a defined subcircuit with matching port counts
+/// Subcircuit bodies are checked in name order, followed by the main circuit.
+/// Instances of undefined subcircuits are skipped when external subcircuits are allowed
+fn check_subcircuit_references(program: &Program, external: bool) -> Result<(), ParseError> {
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+
+    let scopes = names.into_iter()
+        .map(|name| &program.subcircuits[name].components)
+        .chain(std::iter::once(&program.components));
+
+    for component in scopes.flatten() {
+        let GateType::Subcircuit(name) = &component.gate_type else {
+            continue;
+        };
+        let Some(subcircuit) = program.subcircuits.get(name) else {
+            if external {
+                continue;
+            }
+            return Err(ParseError::UnknownSubcircuit { name: name.clone(), location: component.location });
+        };
+
+        for (direction, expected, found) in [
+            ("input", subcircuit.inputs.len(), component.inputs.len()),
+            ("output", subcircuit.outputs.len(), component.outputs.len()),
+        ] {
+            if expected != found {
+                return Err(ParseError::PortCountMismatch {
+                    name: name.clone(),
+                    direction,
+                    expected,
+                    found,
+                    location: component.location,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns ParseError::NestingTooDeep for the first subcircuit instance that contains more than limit levels of subcircuits,
+/// counting the instance itself. Subcircuit bodies are checked in name order, followed by the main circuit
+fn check_subcircuit_depth(program: &Program, limit: usize) -> Result<(), ParseError> {
+    let depths = graph::subcircuit_depths(program);
+
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+    let scopes = names.into_iter()
+        .map(|name| &program.subcircuits[name].components)
+        .chain(std::iter::once(&program.components));
+
+    for component in scopes.flatten() {
+        if let GateType::Subcircuit(name) = &component.gate_type {
+            if depths.get(name.as_str()).is_some_and(|&depth| depth > limit) {
+                return Err(ParseError::NestingTooDeep { limit, location: component.location });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Gives every component without an identifier a unique one, of the form "{subcircuit}_{n}" for subcircuit instances
+/// and "{gate}_{n}" with the lowercase keyword for primitive gates, such as "and_0"
+/// Numbers count up per subcircuit or gate type in source order, skipping names already taken in the scope
+fn name_anonymous_components(components: &mut [Component]) {
+    let mut taken: HashSet<String> = components.iter()
+        .map(|c| c.identifier.clone())
+        .filter(|id| !id.is_empty())
+        .collect();
+    let mut counters: HashMap<String, usize> = HashMap::new();
+
+    for component in components.iter_mut() {
+        if !component.identifier.is_empty() {
+            continue;
+        }
+        let name = match &component.gate_type {
+            GateType::Subcircuit(name) => name.clone(),
+            gate_type => gate_type.keyword().to_lowercase(),
+        };
+        let counter = counters.entry(name.clone()).or_insert(0);
+        let mut identifier = format!("{}_{}", name, counter);
+        while taken.contains(&identifier) {
+            *counter += 1;
+            identifier = format!("{}_{}", name, counter);
+        }
+        *counter += 1;
+        taken.insert(identifier.clone());
+        component.identifier = identifier;
+    }
+}
+
+/// Gives the components created from assignments their identifiers, and names the temporary signals between them
+/// The gate driving a target is named after the target unless another component already uses that identifier.
+/// Temporary signals are named "{target}_{n}" or "{signal}_n_{n}", skipping names already used in the scope, and so
+/// is the gate driving each of them, or for an inverted output the NOT gate reading it
+fn name_desugared_components(components: &mut [Component], desugared: &[Desugared], ports: &HashSet<&str>) {
+    let mut identifiers: HashSet<String> = components.iter()
+        .map(|c| c.identifier.clone())
+        .filter(|id| !id.is_empty())
+        .collect();
+    let mut signals: HashSet<String> = components.iter()
+        .flat_map(|c| c.inputs.iter().filter_map(Signal::wire).chain(c.outputs.iter().map(String::as_str)))
+        .chain(ports.iter().copied())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let mut counters: HashMap<&str, usize> = HashMap::new();
+
+    for entry in desugared {
+        if entry.reader.is_none() && entry.driver.is_none() && !identifiers.contains(&entry.base) {
+            identifiers.insert(entry.base.clone());
+            components[entry.component].identifier = entry.base.clone();
+            continue;
+        }
+
+        let counter = counters.entry(&entry.base).or_insert(0);
+        let mut name = format!("{}_{}", entry.base, counter);
+        while identifiers.contains(&name) || signals.contains(&name) {
+            *counter += 1;
+            name = format!("{}_{}", entry.base, counter);
+        }
+        *counter += 1;
+        identifiers.insert(name.clone());
+        components[entry.component].identifier = name.clone();
+
+        if let Some((reader, position)) = entry.reader {
+            signals.insert(name.clone());
+            components[entry.component].outputs = vec![name.clone()];
+            components[reader].inputs[position] = Signal::Wire(name);
+        } else if let Some((driver, position)) = entry.driver {
+            signals.insert(name.clone());
+            components[entry.component].inputs = vec![Signal::Wire(name.clone())];
+            components[driver].outputs[position] = name;
+        }
+    }
+}
+
+/// Represents possible errors that can occur while parsing a token stream
+#[derive(Debug)]
+pub enum ParseError {
+    UnexpectedToken { expected: TokenKind, found: TokenKind, location: Location },
+    UnexpectedEof { expected: String, location: Location },
+    ExpectedIdentifier { found: TokenKind, location: Location },
+    ExpectedSubcircuitName { found: TokenKind, location: Location },
+    InvalidToken { context: &'static str, found: TokenKind, location: Location },
+    Expected { one_of: Vec<&'static str>, found: TokenKind, location: Location },
+    DuplicateComponent { name: String, first_location: Location, second_location: Location },
+    DuplicateSubcircuit { name: String, first_location: Location, second_location: Location },
+    UnknownSubcircuit { name: String, location: Location },
+    PortCountMismatch { name: String, direction: &'static str, expected: usize, found: usize, location: Location },
+    InvalidBusWidth { name: String, location: Location },
+    MissingSection { section: &'static str, found: TokenKind, location: Location },
+    EmptySection { section: &'static str, location: Location },
+    KeywordAsIdentifier { keyword: &'static str, location: Location },
+    UnexpectedComma { location: Location },
+    DuplicateConstant { name: String, location: Location },
+    ExpectedSingleSignal { location: Location },
+    NestingTooDeep { limit: usize, location: Location },
+    LiteralWidthMismatch { expected: usize, found: usize, location: Location },
+    TrailingTokens { location: Location },
+}
+
+impl std::error::Error for ParseError {}
+
+/// Implements Display trait for ParseError to provide human-readable error messages
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { expected, found, location } =>
+                write!(f, "Expected {:?}, found {:?} at {}", expected, found, location),
+            ParseError::UnexpectedEof { expected, location } =>
+                write!(f, "Unexpected end of input, expected {} at {}", expected, location),
+            ParseError::ExpectedIdentifier { found, location } =>
+                write!(f, "Expected identifier for component, found {:?} at {}", found, location),
+            ParseError::ExpectedSubcircuitName { found, location } =>
+                write!(f, "Expected subcircuit name, found {:?} at {}", found, location),
+            ParseError::InvalidToken { context, found, location } =>
+                write!(f, "Unexpected token in {}: {:?} at {}", context, found, location),
+            ParseError::Expected { one_of, found, location } =>
+                write!(f, "Expected one of {}, found {:?} at {}", one_of.join(", "), found, location),
+            ParseError::DuplicateComponent { name, first_location, second_location } =>
+                write!(f, "Duplicate component identifier '{}' at {}, first defined at {}", name, second_location, first_location),
+            ParseError::DuplicateSubcircuit { name, first_location, second_location } =>
+                write!(f, "Duplicate subcircuit '{}' at {}, first defined at {}", name, second_location, first_location),
+            ParseError::UnknownSubcircuit { name, location } =>
+                write!(f, "Unknown subcircuit '{}' at {}", name, location),
+            ParseError::PortCountMismatch { name, direction, expected, found, location } =>
+                write!(f, "Subcircuit '{}' expects {} {}(s) but {} were given at {}", name, expected, direction, found, location),
+            ParseError::InvalidBusWidth { name, location } =>
+                write!(f, "Bus '{}' must be at least one bit wide at {}", name, location),
+            ParseError::MissingSection { section, found, location } =>
+                write!(f, "Expected {} section, found {:?} at {}", section, found, location),
+            ParseError::EmptySection { section, location } =>
+                write!(f, "{} section at {} declares no signals", section, location),
+            ParseError::KeywordAsIdentifier { keyword, location } =>
+                write!(f, "'{}' is a reserved keyword and cannot be used as a name at {}", keyword, location),
+            ParseError::UnexpectedComma { location } =>
+                write!(f, "Unexpected comma at {}", location),
+            ParseError::DuplicateConstant { name, location } =>
+                write!(f, "Constant '{}' reuses the name of an input, an output or another constant at {}", name, location),
+            ParseError::ExpectedSingleSignal { location } =>
+                write!(f, "Expected a single signal in an assignment, found a bus slice or a literal wider than one bit at {}", location),
+            ParseError::NestingTooDeep { limit, location } =>
+                write!(f, "Nesting is deeper than the limit of {} levels at {}", limit, location),
+            ParseError::LiteralWidthMismatch { expected, found, location } =>
+                write!(f, "Literal value is {} bit(s) wide where {} bit(s) are expected at {}", found, expected, location),
+            ParseError::TrailingTokens { location } =>
+                write!(f, "Unexpected input after the last component of the main circuit at {}", location),
+        }
+    }
+}
+
+/// Represents an error found by any stage of turning source code into a program
+/// A lexer or parse error is the source of the CompileError, so handlers that print the whole chain show it
+/// after the stage that failed. Semantic errors are listed by the message itself, since there may be several
+#[derive(Debug)]
+pub enum CompileError {
+    Lexer(LexerError),
+    Parse(ParseError),
+    Semantic(Vec<SemanticError>), // Every error found by Program::validate, in the order it reports them
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Lexer(err) => Some(err),
+            CompileError::Parse(err) => Some(err),
+            CompileError::Semantic(_) => None,
+        }
+    }
+}
+
+/// Implements Display trait for CompileError to provide human-readable error messages
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Lexer(_) => write!(f, "Could not tokenize the source code"),
+            CompileError::Parse(_) => write!(f, "Could not parse the source code"),
+            CompileError::Semantic(errors) => {
+                let messages: Vec<String> = errors.iter().map(SemanticError::to_string).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+impl From<LexerError> for CompileError {
+    fn from(err: LexerError) -> Self {
+        CompileError::Lexer(err)
+    }
+}
+
+impl From<ParseError> for CompileError {
+    fn from(err: ParseError) -> Self {
+        CompileError::Parse(err)
+    }
+}
+
+impl From<Vec<SemanticError>> for CompileError {
+    fn from(errors: Vec<SemanticError>) -> Self {
+        CompileError::Semantic(errors)
+    }
+}
+
+/// Lexes and parses source code into a program, as in `let program: Program = source.parse()?`
+/// The program is not validated, use Program::parse_validated to check it as well
+impl FromStr for Program {
+    type Err = CompileError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let tokens = Lexer::new(source.to_string()).tokenize()?;
+        Ok(Parser::new(tokens).parse_program()?)
+    }
+}
+
+impl Program {
+    /// Lexes, parses and validates source code, returning CompileError::Semantic with every problem validate finds
+    pub fn parse_validated(source: &str) -> Result<Program, CompileError> {
+        let program: Program = source.parse()?;
+        program.validate()?;
+        Ok(program)
+    }
+
+    /// Returns the position of an input in the INPUTS section, which is also its column in truth tables
+    pub fn input_index(&self, name: &str) -> Option<usize> {
+        self.inputs.iter().position(|input| input == name)
+    }
+
+    /// Returns the position of an output in the OUTPUTS section, which is also its position among a truth table's outputs
+    pub fn output_index(&self, name: &str) -> Option<usize> {
+        self.outputs.iter().position(|output| output == name)
+    }
+
+    /// Adds the subcircuits of a library, such as one read with Parser::parse_library, to the program
+    /// Returns SemanticError::DuplicateSubcircuit without changing the program if the library defines
+    /// a name the program already has, checking the library's names in sorted order
+    pub fn with_library(&mut self, library: HashMap<String, Subcircuit>) -> Result<(), SemanticError> {
+        let mut names: Vec<&String> = library.keys().collect();
+        names.sort();
+        if let Some(name) = names.into_iter().find(|name| self.subcircuits.contains_key(*name)) {
+            return Err(SemanticError::DuplicateSubcircuit { name: name.clone(), location: library[name].location });
+        }
+        self.subcircuits.extend(library);
+        Ok(())
+    }
+}
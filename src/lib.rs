@@ -0,0 +1,13 @@
+pub mod lang;
+pub mod eval;
+pub mod graph;
+pub mod validate;
+pub mod logic;
+pub mod sim;
+pub mod export;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod transform;
+pub mod format;
+pub mod diagnostic;
+pub mod builder;
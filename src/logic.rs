@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// The value of a signal during simulation
+/// Besides low and high, a signal can be left floating by disabled tri-state buffers (Z),
+/// or be unknown when drivers conflict or a gate reads a floating signal (X)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Logic {
+    Low,
+    High,
+    Z, // High impedance, nothing drives the signal
+    X, // Unknown, e.g. two drivers disagree
+}
+
+impl Logic {
+    /// Returns the boolean value of a low or high signal, or None for Z and X
+    pub fn to_bool(self) -> Option<bool> {
+        match self {
+            Logic::Low => Some(false),
+            Logic::High => Some(true),
+            Logic::Z | Logic::X => None,
+        }
+    }
+
+    /// Combines the values of two drivers of the same wire
+    /// A floating driver gives way to the other one, and drivers that disagree make the wire unknown
+    pub fn resolve(self, other: Logic) -> Logic {
+        match (self, other) {
+            (Logic::Z, value) | (value, Logic::Z) => value,
+            (a, b) if a == b => a,
+            _ => Logic::X,
+        }
+    }
+
+    /// Returns the value a gate input sees, where a floating signal reads as unknown
+    pub fn read(self) -> Logic {
+        match self {
+            Logic::Z => Logic::X,
+            value => value,
+        }
+    }
+}
+
+impl From<bool> for Logic {
+    fn from(value: bool) -> Self {
+        if value { Logic::High } else { Logic::Low }
+    }
+}
+
+/// Formats a value as 0, 1, Z or X
+impl fmt::Display for Logic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Logic::Low => write!(f, "0"),
+            Logic::High => write!(f, "1"),
+            Logic::Z => write!(f, "Z"),
+            Logic::X => write!(f, "X"),
+        }
+    }
+}
@@ -1,6 +1,19 @@
-mod lang;
-mod eval;
-use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::ExitCode;
+use osd::{eval, lang};
+use osd::lang::CompileError;
+use osd::sim::{SimError, Simulator};
+use clap::{ArgGroup, Parser, Subcommand};
+
+/// Exit code when the input file cannot be read
+const EXIT_IO_ERROR: u8 = 1;
+/// Exit code when a command-line value is malformed, matching clap's own usage errors
+const EXIT_USAGE_ERROR: u8 = 2;
+/// Exit code when the source has lexer, parse or semantic errors
+const EXIT_SOURCE_ERROR: u8 = 3;
+/// Exit code when simulating or exporting the program fails
+const EXIT_RUN_ERROR: u8 = 4;
 
 #[derive(Parser)]
 #[clap(name = "osd")]
@@ -15,40 +28,172 @@ enum Command {
     Generate {
         file_path: String,
     },
+    /// Simulates the input file for one set of inputs, or prints its truth table, DOT graph, Mermaid flowchart, Verilog or waveform
+    #[clap(group(ArgGroup::new("mode").required(true).args(["inputs", "truth_table", "dot", "mermaid", "verilog", "vcd"])))]
+    Run {
+        file_path: String,
+        /// Input values to simulate, e.g. a=1,b=0
+        #[clap(long, value_delimiter = ',')]
+        inputs: Vec<String>,
+        /// Prints the full truth table
+        #[clap(long)]
+        truth_table: bool,
+        /// Prints the circuit as a Graphviz DOT graph
+        #[clap(long)]
+        dot: bool,
+        /// Prints the circuit as a Mermaid flowchart
+        #[clap(long)]
+        mermaid: bool,
+        /// Prints the circuit as a Verilog module
+        #[clap(long)]
+        verilog: bool,
+        /// Prints a Value Change Dump of the given number of clock cycles, with every input low
+        #[clap(long, value_name = "CYCLES")]
+        vcd: Option<usize>,
+    },
 }
 
-
-fn main() {
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
     match cli.command {
         Command::Generate { file_path } => {
-            let source_code = match std::fs::read_to_string(file_path) {
-                Ok(content) => content,
-                Err(err) => {
-                    eprintln!("Error reading file: {}", err);
-                    return;
-                }
+            let program = match load(&file_path) {
+                Ok(program) => program,
+                Err(code) => return code,
             };
-        
-            let mut lexer = lang::Lexer::new(source_code);
-            let mut tokens = vec![];
-            while let Ok(token) = lexer.get_next_token() {
-                if token == lang::TokenKind::EOF {
-                    break;
-                }
-                tokens.push(token);
+            if let Err(err) = eval::print_truth_table(&program) {
+                eprintln!("Error: {}", err);
+                return ExitCode::from(EXIT_RUN_ERROR);
             }
-        
-            let mut parser = lang::Parser::new(tokens);
-            match parser.parse_program() {
-                Ok(program) => {
-                    eval::print_truth_table(&program);
-                }
-                Err(err) => println!("Error: {}", err),
+        }
+        Command::Run { file_path, inputs, truth_table, dot, mermaid, verilog, vcd } => {
+            let program = match load(&file_path) {
+                Ok(program) => program,
+                Err(code) => return code,
+            };
+
+            let result = if truth_table {
+                eval::print_truth_table(&program).map_err(|err| err.to_string())
+            } else if dot {
+                print!("{}", program.to_dot());
+                Ok(())
+            } else if mermaid {
+                print!("{}", program.to_mermaid());
+                Ok(())
+            } else if verilog {
+                program.to_verilog()
+                    .map(|verilog| print!("{}", verilog))
+                    .map_err(|err| err.to_string())
+            } else if let Some(cycles) = vcd {
+                Simulator::new(&program).to_vcd(cycles)
+                    .map(|vcd| print!("{}", vcd))
+                    .map_err(|err| err.to_string())
+            } else {
+                let values = match parse_inputs(&inputs) {
+                    Ok(values) => values,
+                    Err(message) => {
+                        eprintln!("Error: {}", message);
+                        return ExitCode::from(EXIT_USAGE_ERROR);
+                    }
+                };
+                print_outputs(&program, &values).map_err(|err| err.to_string())
+            };
+
+            if let Err(message) = result {
+                eprintln!("Error: {}", message);
+                return ExitCode::from(EXIT_RUN_ERROR);
             }
         }
     }
 
-    
+    ExitCode::SUCCESS
+}
+
+/// Reads, lexes, parses and validates a source file, printing every error found and then any warnings
+fn load(file_path: &str) -> Result<lang::Program, ExitCode> {
+    let source_code = match std::fs::read_to_string(file_path) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Error reading file: {}", err);
+            return Err(ExitCode::from(EXIT_IO_ERROR));
+        }
+    };
+
+    let program = match compile(&source_code) {
+        Ok(program) => program,
+        Err(errors) => {
+            for err in errors {
+                report(&err, &source_code);
+            }
+            return Err(ExitCode::from(EXIT_SOURCE_ERROR));
+        }
+    };
+    for lint in program.lint() {
+        eprintln!("Warning: {}", lint);
+    }
+    Ok(program)
+}
+
+/// Lexes, parses and validates source code, returning every lexer error rather than only the first
+fn compile(source_code: &str) -> Result<lang::Program, Vec<CompileError>> {
+    let mut lexer = lang::Lexer::new(source_code.to_string());
+    let (tokens, errors) = lexer.tokenize_recovering();
+    if !errors.is_empty() {
+        return Err(errors.into_iter().map(CompileError::from).collect());
+    }
+
+    let program = lang::Parser::new(tokens).parse_program().map_err(|err| vec![CompileError::from(err)])?;
+    program.validate().map_err(|errors| vec![CompileError::from(errors)])?;
+    Ok(program)
+}
+
+/// Prints a compile error followed by the errors that caused it and the source line they point at
+/// Semantic errors have no cause, so each of them is printed with its own source line instead
+fn report(err: &CompileError, source_code: &str) {
+    let rendered = err.render(source_code);
+    if err.source().is_none() {
+        eprint!("{}", rendered);
+        return;
+    }
+
+    eprintln!("Error: {}", err);
+    let mut cause = err.source();
+    while let Some(inner) = cause {
+        eprintln!("Caused by: {}", inner);
+        cause = inner.source();
+    }
+    // The rendered error starts with the message of its cause, which was just printed
+    eprint!("{}", rendered.split_once('\n').map_or("", |(_, snippet)| snippet));
+}
+
+/// Parses input assignments such as "a=1" into a map of input values
+fn parse_inputs(assignments: &[String]) -> Result<HashMap<String, bool>, String> {
+    assignments.iter()
+        .map(|assignment| {
+            let (name, value) = assignment.split_once('=')
+                .ok_or_else(|| format!("Expected NAME=VALUE in --inputs, found '{}'", assignment))?;
+            let value = match value.trim() {
+                "0" => false,
+                "1" => true,
+                other => return Err(format!("Value of input '{}' must be 0 or 1, found '{}'", name.trim(), other)),
+            };
+            Ok((name.trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// Simulates the program once and prints each declared output as "name = value"
+fn print_outputs(program: &lang::Program, inputs: &HashMap<String, bool>) -> Result<(), SimError> {
+    let mut names: Vec<&String> = inputs.keys().collect();
+    names.sort();
+    if let Some(unknown) = names.into_iter().find(|name| !program.inputs.contains(name)) {
+        return Err(SimError::UnknownInput(unknown.clone()));
+    }
+
+    let outputs = Simulator::new(program).evaluate(inputs)?;
+    for output in &program.outputs {
+        println!("{} = {}", output, outputs[output] as u8);
+    }
+    Ok(())
 }
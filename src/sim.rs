@@ -0,0 +1,1101 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use crate::graph;
+use crate::lang::*;
+use crate::logic::Logic;
+use crate::transform::HIERARCHY_SEPARATOR;
+
+/// Represents possible errors that can occur while simulating a program
+#[derive(Debug)]
+pub enum SimError {
+    MissingInput(String),
+    UnknownInput(String),
+    UndefinedSignal(String),
+    UnknownSubcircuit(String),
+    CombinationalLoop { signals: Vec<String> },
+    TooManyInputs { count: usize, limit: usize },
+    RecursiveSubcircuit { chain: Vec<String> },
+    UnknownComponent(String),
+    DidNotStabilize { limit: u64 },
+    DidNotConverge { after: usize },
+    BadArity { component: String, expected: usize, found: usize },
+    PortCountMismatch { component: String, subcircuit: String, direction: &'static str, expected: usize, found: usize },
+    UndrivenOutput(String),
+    NotBinary { signal: String, value: Logic },
+    InvalidLatchState { component: String },
+    UnknownSignal(String),
+    SeparatorInName(String),
+}
+
+impl std::error::Error for SimError {}
+
+/// Implements Display trait for SimError to provide human-readable error messages
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::MissingInput(name) =>
+                write!(f, "No value given for input '{}'", name),
+            SimError::UnknownInput(name) =>
+                write!(f, "'{}' is not an input of the program", name),
+            SimError::UndefinedSignal(name) =>
+                write!(f, "Signal '{}' is never driven", name),
+            SimError::UnknownSubcircuit(name) =>
+                write!(f, "Unknown subcircuit '{}'", name),
+            SimError::CombinationalLoop { signals } =>
+                write!(f, "Combinational loop through signals: {}", signals.join(" -> ")),
+            SimError::TooManyInputs { count, limit } =>
+                write!(f, "Program has {} inputs, but at most {} are supported", count, limit),
+            SimError::RecursiveSubcircuit { chain } =>
+                write!(f, "Subcircuit instantiates itself: {}", chain.join(" -> ")),
+            SimError::UnknownComponent(name) =>
+                write!(f, "Unknown component '{}'", name),
+            SimError::DidNotStabilize { limit } =>
+                write!(f, "Circuit did not stabilize within {} time units", limit),
+            SimError::DidNotConverge { after } =>
+                write!(f, "Circuit did not converge after {} pass(es)", after),
+            SimError::BadArity { component, expected, found } =>
+                write!(f, "Component '{}' takes exactly {} input(s) but has {}", component, expected, found),
+            SimError::PortCountMismatch { component, subcircuit, direction, expected, found } =>
+                write!(f, "Instance '{}' of subcircuit '{}' connects {} {}(s), but the subcircuit declares {}", component, subcircuit, found, direction, expected),
+            SimError::UndrivenOutput(name) =>
+                write!(f, "Output '{}' is never driven", name),
+            SimError::NotBinary { signal, value } =>
+                write!(f, "Signal '{}' is {} rather than 0 or 1", signal, value),
+            SimError::InvalidLatchState { component } =>
+                write!(f, "Latch '{}' has both set and reset high", component),
+            SimError::UnknownSignal(name) =>
+                write!(f, "'{}' is not a signal of the program", name),
+            SimError::SeparatorInName(name) =>
+                write!(f, "Name '{}' contains '{}', which separates instance names from inner names when flattening", name, HIERARCHY_SEPARATOR),
+        }
+    }
+}
+
+/// Propagation delay of a gate, in time units, unless changed with Simulator::set_delay
+pub const DEFAULT_GATE_DELAY: u64 = 1;
+
+/// Longest time run_until_stable waits for the circuit to settle
+pub const MAX_SETTLE_TIME: u64 = 1_000_000;
+
+/// Most rounds of events run_until_stable processes at a single time, which bounds feedback loops of zero-delay gates
+pub const MAX_EVENT_ROUNDS: usize = 10_000;
+
+/// Evaluates a parsed program by propagating values through its components
+/// Supports one-shot evaluation with evaluate, incremental simulation with set_input, step, tick and get,
+/// and timed simulation with set_delay and run_until_stable
+pub struct Simulator<'a> {
+    program: &'a Program,
+    resolved: Option<Program>,  // The program with its aliases resolved, when it declares any
+    faults: BTreeMap<String, bool>, // Signals forced to a value by inject_fault
+    faulted: Option<Program>,   // The flattened program with its faults applied, while there are any
+    state: Option<State>,       // Netlist and signal values for incremental simulation, built on first use
+    max_iterations: Option<usize>, // Passes step may make through feedback loops, or None to reject loops
+    debug_trace: bool,          // Whether evaluate logs every gate it evaluates to stderr
+    watches: Mutex<Vec<Watch>>, // Callbacks registered with watch, locked so evaluate can call them through &self
+    subcircuit_cache: Option<Mutex<SubcircuitCache>>, // Outputs of subcircuit instances seen during one evaluate, when enabled
+    subcircuit_evaluations: AtomicUsize, // Subcircuit instances evaluated by one-shot evaluation, not counting cache hits
+}
+
+/// Subcircuit name and input values -> the subcircuit's output values
+type SubcircuitCache = HashMap<(String, Vec<Logic>), Vec<Logic>>;
+
+/// A callback registered with Simulator::watch
+struct Watch {
+    signal: String,
+    callback: Box<dyn FnMut(Logic) + Send>,
+}
+
+/// The flattened netlist of a program together with the signal values kept between steps
+#[derive(Clone)]
+struct State {
+    components: Vec<Component>,           // Primitive gates in topological order
+    readers: HashMap<String, Vec<usize>>, // Signal -> indices of the gates reading it
+    values: HashMap<String, Logic>,       // Current value of every settled signal
+    dirty: BTreeSet<usize>,               // Gates whose inputs changed since the last step
+    flip_flops: BTreeMap<usize, FlipFlop>, // Gate index -> stored state of each DFF
+    latches: BTreeMap<usize, Logic>,      // Gate index -> stored output of each SRLATCH and DLATCH
+    driven: Vec<Vec<Logic>>,              // Values each gate drove last, Z before its first evaluation
+    buses: HashMap<String, Vec<(usize, usize)>>, // Wire driven by several tri-state buffers -> gate index and output position of each
+    delays: Vec<u64>,                     // Propagation delay of each gate
+    time: u64,                            // Current time of the timed simulation
+}
+
+/// The topological order of the components of the main circuit and of each subcircuit, shared by evaluations
+struct Orders {
+    main: Vec<usize>,
+    subcircuits: HashMap<String, Vec<usize>>, // Subcircuit name -> order of its components
+}
+
+/// The stored state of one D flip-flop
+#[derive(Clone)]
+struct FlipFlop {
+    q: Logic,     // Latched output value
+    clock: Logic, // Clock level seen at the previous tick
+}
+
+impl<'a> Simulator<'a> {
+    /// Creates a new Simulator for the given program
+    pub fn new(program: &'a Program) -> Self {
+        let resolved = program.has_aliases().then(|| program.resolve_aliases());
+        Simulator {
+            program,
+            resolved,
+            faults: BTreeMap::new(),
+            faulted: None,
+            state: None,
+            max_iterations: None,
+            debug_trace: false,
+            watches: Mutex::new(vec![]),
+            subcircuit_cache: None,
+            subcircuit_evaluations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allows combinational feedback loops in incremental simulation, such as cross-coupled NOR gates
+    /// step then sweeps the gates again while signals keep changing, and returns SimError::DidNotConverge once
+    /// they are still changing after the given number of passes, as an oscillator does. At least one pass is
+    /// always made, and gates that have not been computed yet read as low. One-shot evaluation still
+    /// rejects loops with SimError::CombinationalLoop
+    pub fn with_max_iterations(mut self, limit: usize) -> Self {
+        self.max_iterations = Some(limit.max(1));
+        self.state = None;
+        self
+    }
+
+    /// Makes one-shot evaluation remember the outputs of each subcircuit for the input values it was given
+    /// Instances of the same subcircuit with the same inputs are then only evaluated once per call to evaluate,
+    /// which helps datapaths built from many copies of one block. Results are the same as without the cache,
+    /// but the debug trace only shows the gates inside the first instance evaluated for each set of inputs
+    pub fn with_subcircuit_cache(mut self) -> Self {
+        self.subcircuit_cache = Some(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// Returns the program that is actually evaluated, in which every alias has been resolved and every fault applied
+    fn netlist(&self) -> &Program {
+        self.faulted.as_ref().or(self.resolved.as_ref()).unwrap_or(self.program)
+    }
+
+    /// Forces a signal to a constant value regardless of what drives it, modelling a stuck-at-0 or stuck-at-1 fault
+    /// Every later evaluation and simulation step sees the faulty value, so comparing outputs with a fault-free
+    /// simulator shows which inputs detect the fault. Signals inside subcircuit instances use flattened names
+    /// such as "full_adder_0.temp1", and a faulty input ignores the value it is given.
+    /// Incremental simulation starts over, and SimError::UnknownSignal is returned for a name that is not a signal
+    pub fn inject_fault(&mut self, signal: &str, stuck_at: bool) -> Result<(), SimError> {
+        let flattened = self.program.flatten()?;
+        if !flattened.signals().contains(signal) {
+            return Err(SimError::UnknownSignal(signal.to_string()));
+        }
+        self.faults.insert(signal.to_string(), stuck_at);
+        self.faulted = Some(apply_faults(flattened, &self.faults));
+        self.state = None;
+        Ok(())
+    }
+
+    /// Removes every fault added with inject_fault, starting incremental simulation over
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+        self.faulted = None;
+        self.state = None;
+    }
+
+    /// Returns how many subcircuit instances one-shot evaluation has evaluated so far, including nested ones
+    /// Instances answered by the subcircuit cache are not counted, so this shows how much work the cache saves
+    pub fn subcircuit_evaluations(&self) -> usize {
+        self.subcircuit_evaluations.load(Ordering::Relaxed)
+    }
+
+    /// Registers a callback that is called with the new value of a signal every time it changes
+    /// evaluate reports every input, constant and settled signal of the main circuit once, in evaluation order,
+    /// while set_input, step, tick and run_until_stable report each change as it happens.
+    /// Signals inside subcircuit instances use flattened names such as "full_adder_0.temp1" and are only
+    /// reported by incremental simulation. Callbacks only observe values, so they cannot change the result
+    pub fn watch(&mut self, signal: &str, callback: Box<dyn FnMut(Logic) + Send>) {
+        let watches = self.watches.get_mut().unwrap_or_else(PoisonError::into_inner);
+        watches.push(Watch { signal: signal.to_string(), callback });
+    }
+
+    /// Enables or disables logging of each gate's inputs and computed outputs to stderr during evaluate
+    /// Gates are evaluated one at a time while tracing, so the log follows the evaluation order
+    pub fn set_debug_trace(&mut self, enabled: bool) {
+        self.debug_trace = enabled;
+    }
+
+    /// Returns the program being simulated
+    pub fn program(&self) -> &'a Program {
+        self.program
+    }
+
+    /// Returns a simulator of the same program with a copy of this one's faults and incremental state
+    /// Watches, the debug trace and the subcircuit cache are not copied, so running it leaves this one untouched
+    pub(crate) fn fork(&self) -> Simulator<'a> {
+        Simulator {
+            program: self.program,
+            resolved: self.resolved.clone(),
+            faults: self.faults.clone(),
+            faulted: self.faulted.clone(),
+            state: self.state.clone(),
+            max_iterations: self.max_iterations,
+            debug_trace: false,
+            watches: Mutex::new(vec![]),
+            subcircuit_cache: None,
+            subcircuit_evaluations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the components of the main circuit in the order evaluate runs them
+    /// Returns an empty list when the main circuit contains a combinational loop
+    pub fn evaluation_order(&self) -> Vec<&'a Component> {
+        // Resolving aliases keeps the components in place and appends buffers for outputs, which are left out
+        let components = &self.program.components;
+        graph::topological_order(&self.resolved.as_ref().unwrap_or(self.program).components)
+            .map(|order| order.into_iter().filter_map(|index| components.get(index)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Evaluates the program for one set of input values
+    /// Returns the values of the program's declared outputs, or SimError::NotBinary for the first
+    /// output that is floating or unknown
+    pub fn evaluate(&self, inputs: &HashMap<String, bool>) -> Result<HashMap<String, bool>, SimError> {
+        let values = self.evaluate_logic(&logic_inputs(inputs))?;
+        self.binary_outputs(values)
+    }
+
+    /// Evaluates the program for many sets of input values, returning the outputs for each in the same order
+    /// The program is checked and its components are ordered once for the whole batch instead of once per call
+    /// to evaluate, which makes running a long list of test vectors faster. Stops at the first vector that fails
+    pub fn evaluate_batch(&self, vectors: &[HashMap<String, bool>]) -> Result<Vec<HashMap<String, bool>>, SimError> {
+        let orders = self.order_scopes()?;
+        vectors.iter()
+            .map(|inputs| {
+                let values = self.evaluate_ordered(&orders, &logic_inputs(inputs))?;
+                self.binary_outputs(values)
+            })
+            .collect()
+    }
+
+    /// Converts the output values of one evaluation to booleans, or returns SimError::NotBinary for the first
+    /// output that is floating or unknown
+    fn binary_outputs(&self, values: HashMap<String, Logic>) -> Result<HashMap<String, bool>, SimError> {
+        self.program.outputs.iter()
+            .map(|output| {
+                let value = values[output];
+                value.to_bool()
+                    .map(|value| (output.clone(), value))
+                    .ok_or_else(|| SimError::NotBinary { signal: output.clone(), value })
+            })
+            .collect()
+    }
+
+    /// Evaluates the program for one set of input values, which may themselves be floating or unknown
+    /// Returns the values of the program's declared outputs
+    pub fn evaluate_logic(&self, inputs: &HashMap<String, Logic>) -> Result<HashMap<String, Logic>, SimError> {
+        let orders = self.order_scopes()?;
+        self.evaluate_ordered(&orders, inputs)
+    }
+
+    /// Checks that the program can be evaluated and orders the components of the main circuit and of every subcircuit
+    fn order_scopes(&self) -> Result<Orders, SimError> {
+        let program = self.netlist();
+        if let Some(chain) = graph::recursive_subcircuits(program).into_iter().next() {
+            return Err(SimError::RecursiveSubcircuit { chain });
+        }
+        self.check_loops()?;
+        check_outputs_driven(program)?;
+
+        let subcircuits = program.subcircuits.iter()
+            .map(|(name, subcircuit)| Ok((name.clone(), scope_order(&subcircuit.components)?)))
+            .collect::<Result<HashMap<String, Vec<usize>>, SimError>>()?;
+        Ok(Orders { main: scope_order(&program.components)?, subcircuits })
+    }
+
+    /// Evaluates the program for one set of input values, with its components already ordered by order_scopes
+    fn evaluate_ordered(&self, orders: &Orders, inputs: &HashMap<String, Logic>) -> Result<HashMap<String, Logic>, SimError> {
+        let program = self.netlist();
+        if let Some(cache) = &self.subcircuit_cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+
+        let mut values: HashMap<String, Logic> = program.constants.iter()
+            .map(|(name, &value)| (name.clone(), Logic::from(value)))
+            .collect();
+        for input in &program.inputs {
+            let value = inputs.get(input).ok_or_else(|| SimError::MissingInput(input.clone()))?;
+            values.insert(input.clone(), *value);
+        }
+
+        let values = self.evaluate_scope(&program.components, &orders.main, orders, values)?;
+        self.notify_evaluated(&values);
+        read_signals(&values, &program.outputs)
+    }
+
+    /// Reports the signals of the main circuit to the watches after a one-shot evaluation
+    /// Constants and inputs come first, followed by the outputs of each component in evaluation order.
+    /// A wire driven by several tri-state buffers is reported once, after its last driver
+    fn notify_evaluated(&self, values: &HashMap<String, Logic>) {
+        let mut watches = self.watches.lock().unwrap_or_else(PoisonError::into_inner);
+        if watches.is_empty() {
+            return;
+        }
+
+        let program = self.netlist();
+        let mut constants: Vec<&String> = program.constants.keys().collect();
+        constants.sort();
+        let order = graph::topological_order(&program.components).unwrap_or_default();
+        let outputs = order.into_iter().flat_map(|index| program.components[index].outputs.iter());
+        let mut signals: Vec<&String> = constants.into_iter().chain(&program.inputs).chain(outputs).collect();
+
+        let mut seen = BTreeSet::new();
+        signals.reverse();
+        signals.retain(|signal| seen.insert(*signal));
+        for signal in signals.into_iter().rev() {
+            if let Some(&value) = values.get(signal) {
+                notify(&mut watches, signal, value);
+            }
+        }
+    }
+
+    /// Sets the value of a program input for the next step, from a bool or a Logic value
+    /// Inputs start low, and only gates downstream of inputs that actually change are recomputed
+    pub fn set_input(&mut self, name: &str, value: impl Into<Logic>) -> Result<(), SimError> {
+        if !self.program.inputs.iter().any(|input| input == name) {
+            return Err(SimError::UnknownInput(name.to_string()));
+        }
+        let value = value.into();
+        let state = self.state()?;
+        if state.values.insert(name.to_string(), value) != Some(value) {
+            state.mark_readers_dirty(name);
+            notify(self.watches.get_mut().unwrap_or_else(PoisonError::into_inner), name, value);
+        }
+        Ok(())
+    }
+
+    /// Propagates input changes made since the last step until every affected signal has settled
+    /// Each gate downstream of a change is evaluated at most once, in dependency order,
+    /// flip-flops keep driving their latched value and latches update their stored value from their inputs.
+    /// With feedback loops allowed by with_max_iterations, gates are swept in passes until nothing changes.
+    /// Returns SimError::InvalidLatchState if set and reset of an SRLATCH are both high
+    pub fn step(&mut self) -> Result<(), SimError> {
+        self.state()?;
+        let limit = self.max_iterations;
+        let State { components, readers, values, dirty, flip_flops, latches, driven, buses, .. } =
+            self.state.as_mut().expect("state was just built");
+        let watches = self.watches.get_mut().unwrap_or_else(PoisonError::into_inner);
+
+        // A pass evaluates pending gates in order, and gates before the last one evaluated wait for the next pass
+        let mut passes = 1;
+        let mut next = 0;
+        loop {
+            let index = match dirty.range(next..).next() {
+                Some(&index) => index,
+                None if dirty.is_empty() => break,
+                None => {
+                    if limit.is_some_and(|limit| passes >= limit) {
+                        return Err(SimError::DidNotConverge { after: passes });
+                    }
+                    passes += 1;
+                    next = 0;
+                    continue;
+                }
+            };
+            dirty.remove(&index);
+            next = index + 1;
+
+            let component = &components[index];
+            let results = match flip_flops.get(&index) {
+                Some(flip_flop) => vec![flip_flop.q],
+                None => {
+                    let input_values = component.inputs.iter()
+                        .map(|input| read_signal(values, input))
+                        .collect::<Result<Vec<Logic>, SimError>>()?;
+                    match latches.get_mut(&index) {
+                        Some(q) => {
+                            *q = eval_latch(component, &input_values, *q)?;
+                            vec![*q]
+                        }
+                        None => eval_gate_logic(&component.gate_type, &input_values),
+                    }
+                }
+            };
+
+            driven[index] = results;
+            for (position, output) in component.outputs.iter().enumerate() {
+                let value = wire_value(buses, driven, output, driven[index][position]);
+                if values.insert(output.clone(), value) != Some(value) {
+                    dirty.extend(readers.get(output).into_iter().flatten());
+                    notify(watches, output, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Settles the circuit, then latches d into q on every DFF whose clock input rose since the previous tick
+    /// All flip-flops sample their inputs at the same time, after which the circuit settles again
+    pub fn tick(&mut self) -> Result<(), SimError> {
+        self.step()?;
+
+        let State { components, values, dirty, flip_flops, .. } = self.state()?;
+        for (&index, flip_flop) in flip_flops.iter_mut() {
+            let inputs = &components[index].inputs;
+            let data = read_signal(values, &inputs[0])?.read();
+            let clock = read_signal(values, &inputs[1])?;
+            if clock == Logic::High && flip_flop.clock != Logic::High && flip_flop.q != data {
+                flip_flop.q = data;
+                dirty.insert(index);
+            }
+            flip_flop.clock = clock;
+        }
+
+        self.step()
+    }
+
+    /// Sets the propagation delay of a gate for timed simulation
+    /// Gates inside subcircuit instances use flattened names such as "full_adder_0.gate1"
+    pub fn set_delay(&mut self, component: &str, delay: u64) -> Result<(), SimError> {
+        let state = self.state()?;
+        let index = state.components.iter()
+            .position(|c| c.identifier == component)
+            .ok_or_else(|| SimError::UnknownComponent(component.to_string()))?;
+        state.delays[index] = delay;
+        Ok(())
+    }
+
+    /// Propagates input changes made since the last step through an event queue, honouring gate delays
+    /// A gate whose inputs change at time t drives its outputs at t + delay, so short glitches propagate
+    /// Signals that have not been computed yet read as low
+    /// Returns the number of time units until the last signal changed, SimError::DidNotStabilize
+    /// if signals are still changing after MAX_SETTLE_TIME or after MAX_EVENT_ROUNDS rounds of events at one time,
+    /// as zero-delay gates on a loop can cause, or SimError::InvalidLatchState as step does
+    pub fn run_until_stable(&mut self) -> Result<u64, SimError> {
+        self.state()?;
+        let State { components, readers, values, dirty, flip_flops, latches, driven, buses, delays, time } =
+            self.state.as_mut().expect("state was just built");
+        let watches = self.watches.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let start = *time;
+        let mut settled = start;
+
+        // Time -> gates driving a new value at that time, in scheduling order
+        let mut events: BTreeMap<u64, Vec<(usize, Vec<Logic>)>> = BTreeMap::new();
+        let mut pending: BTreeSet<usize> = std::mem::take(dirty);
+        let mut now = start;
+        let mut rounds = 0; // Rounds of events processed at the current time
+        loop {
+            for index in std::mem::take(&mut pending) {
+                let component = &components[index];
+                let results = match flip_flops.get(&index) {
+                    Some(flip_flop) => vec![flip_flop.q],
+                    None => {
+                        let input_values: Vec<Logic> = component.inputs.iter()
+                            .map(|input| read_signal(values, input).unwrap_or(Logic::Low))
+                            .collect();
+                        match latches.get_mut(&index) {
+                            Some(q) => {
+                                *q = eval_latch(component, &input_values, *q)?;
+                                vec![*q]
+                            }
+                            None => eval_gate_logic(&component.gate_type, &input_values),
+                        }
+                    }
+                };
+                events.entry(now + delays[index]).or_default().push((index, results));
+            }
+
+            let Some((event_time, changes)) = events.pop_first() else {
+                break;
+            };
+            rounds = if event_time == now { rounds + 1 } else { 1 };
+            if event_time - start > MAX_SETTLE_TIME || rounds > MAX_EVENT_ROUNDS {
+                return Err(SimError::DidNotStabilize { limit: MAX_SETTLE_TIME });
+            }
+            now = event_time;
+            for (index, results) in changes {
+                driven[index] = results;
+                for (position, output) in components[index].outputs.iter().enumerate() {
+                    let value = wire_value(buses, driven, output, driven[index][position]);
+                    if values.insert(output.clone(), value) != Some(value) {
+                        settled = now;
+                        pending.extend(readers.get(output).into_iter().flatten());
+                        notify(watches, output, value);
+                    }
+                }
+            }
+        }
+
+        *time = now;
+        Ok(settled - start)
+    }
+
+    /// Returns the current value of any signal, or None before it has been computed or while it is floating or unknown
+    /// Signals inside subcircuit instances use flattened names such as "full_adder_0.temp1"
+    pub fn get(&self, signal: &str) -> Option<bool> {
+        self.get_logic(signal)?.to_bool()
+    }
+
+    /// Returns the current value of any signal, including Z and X, or None before it has been computed
+    pub fn get_logic(&self, signal: &str) -> Option<Logic> {
+        self.state.as_ref()?.values.get(signal).copied()
+    }
+
+    /// Returns the incremental simulation state, building it on first use
+    fn state(&mut self) -> Result<&mut State, SimError> {
+        if self.state.is_none() {
+            let state = State::new(self.netlist(), self.max_iterations.is_some())?;
+            self.state = Some(state);
+        }
+        Ok(self.state.as_mut().expect("state was just built"))
+    }
+
+    /// Returns SimError::CombinationalLoop if the main circuit or any subcircuit feeds back into itself
+    /// Subcircuits are checked in name order after the main circuit
+    pub fn check_loops(&self) -> Result<(), SimError> {
+        let program = self.netlist();
+        check_acyclic(&program.components)?;
+
+        let mut names: Vec<&String> = program.subcircuits.keys().collect();
+        names.sort();
+        for name in names {
+            check_acyclic(&program.subcircuits[name].components)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates a list of components in the given topological order, starting from the given signal values
+    /// Returns the values of every signal in the scope
+    fn evaluate_scope(&self, components: &[Component], order: &[usize], orders: &Orders, values: HashMap<String, Logic>) -> Result<HashMap<String, Logic>, SimError> {
+        #[cfg(feature = "parallel")]
+        if !self.debug_trace {
+            return self.evaluate_levels(components, order, orders, values);
+        }
+        self.evaluate_in_order(components, order, orders, values)
+    }
+
+    /// Evaluates components one after another in the given topological order
+    fn evaluate_in_order(&self, components: &[Component], order: &[usize], orders: &Orders, mut values: HashMap<String, Logic>) -> Result<HashMap<String, Logic>, SimError> {
+        for &index in order {
+            let component = &components[index];
+            let results = self.evaluate_component(component, orders, &values)?;
+            if self.debug_trace {
+                eprintln!("{}", trace_line(component, &values, &results));
+            }
+            for (output_name, result) in component.outputs.iter().zip(results) {
+                drive(&mut values, output_name, result);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluates components level by level, running the independent components of each level in parallel
+    /// A failing level reports the error of its first failing component in evaluation order, so the error is
+    /// always the same, though it may differ from the first one the serial path would come across
+    #[cfg(feature = "parallel")]
+    fn evaluate_levels(&self, components: &[Component], order: &[usize], orders: &Orders, mut values: HashMap<String, Logic>) -> Result<HashMap<String, Logic>, SimError> {
+        use rayon::prelude::*;
+
+        for level in graph::levels(components, order) {
+            let results: Vec<Result<Vec<Logic>, SimError>> = level.par_iter()
+                .map(|&index| self.evaluate_component(&components[index], orders, &values))
+                .collect();
+            for (&index, outputs) in level.iter().zip(results) {
+                for (output_name, result) in components[index].outputs.iter().zip(outputs?) {
+                    drive(&mut values, output_name, result);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Computes the output values of one component from signals that have already been evaluated
+    fn evaluate_component(&self, component: &Component, orders: &Orders, values: &HashMap<String, Logic>) -> Result<Vec<Logic>, SimError> {
+        check_gate_arity(component)?;
+
+        // Without clock ticks a flip-flop stays in its initial low state
+        if let GateType::Dff = component.gate_type {
+            return Ok(vec![Logic::Low; component.outputs.len()]);
+        }
+
+        let input_values = component.inputs.iter()
+            .map(|input| read_signal(values, input))
+            .collect::<Result<Vec<Logic>, SimError>>()?;
+
+        // Latches start low as well, so they only hold that value while disabled
+        match &component.gate_type {
+            GateType::Subcircuit(name) => self.evaluate_subcircuit(component, name, orders, &input_values),
+            GateType::SrLatch | GateType::DLatch => Ok(vec![eval_latch(component, &input_values, Logic::Low)?]),
+            gate_type => Ok(eval_gate_logic(gate_type, &input_values)),
+        }
+    }
+
+    /// Evaluates a subcircuit instance, binding its formal inputs to the given values by position
+    /// The i-th returned value belongs to the subcircuit's i-th formal output
+    fn evaluate_subcircuit(&self, component: &Component, name: &str, orders: &Orders, input_values: &[Logic]) -> Result<Vec<Logic>, SimError> {
+        let subcircuit = self.netlist().subcircuits.get(name)
+            .ok_or_else(|| SimError::UnknownSubcircuit(name.to_string()))?;
+        check_port_counts(component, subcircuit)?;
+
+        // The lock is only held for the lookup and the insertion, since nested instances use the cache as well
+        let key = (name.to_string(), input_values.to_vec());
+        if let Some(cache) = &self.subcircuit_cache {
+            if let Some(outputs) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(&key) {
+                return Ok(outputs.clone());
+            }
+        }
+
+        self.subcircuit_evaluations.fetch_add(1, Ordering::Relaxed);
+        let values = subcircuit.inputs.iter().cloned()
+            .zip(input_values.iter().copied())
+            .collect();
+        let values = self.evaluate_scope(&subcircuit.components, &orders.subcircuits[name], orders, values)?;
+
+        let outputs = subcircuit.outputs.iter()
+            .map(|output| values.get(output).copied().ok_or_else(|| SimError::UndefinedSignal(output.clone())))
+            .collect::<Result<Vec<Logic>, SimError>>()?;
+        if let Some(cache) = &self.subcircuit_cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).insert(key, outputs.clone());
+        }
+        Ok(outputs)
+    }
+}
+
+impl State {
+    /// Flattens the program and orders its gates, with every input low and every gate pending
+    /// Feedback loops are rejected unless allowed, in which case every gate output starts low as well
+    fn new(program: &Program, feedback: bool) -> Result<Self, SimError> {
+        if let Some(chain) = graph::recursive_subcircuits(program).into_iter().next() {
+            return Err(SimError::RecursiveSubcircuit { chain });
+        }
+        check_outputs_driven(program)?;
+        let mut flattened = program.flatten()?;
+        for component in &flattened.components {
+            check_gate_arity(component)?;
+        }
+        let order = match graph::topological_order(&flattened.components) {
+            Ok(order) => order,
+            Err(_) if feedback => graph::feedback_order(&flattened.components),
+            Err(_) => {
+                check_acyclic(&flattened.components)?;
+                unreachable!("components without a loop always have a topological order");
+            }
+        };
+
+        let mut slots: Vec<Option<Component>> = flattened.components.drain(..).map(Some).collect();
+        let components: Vec<Component> = order.into_iter()
+            .filter_map(|index| slots[index].take())
+            .collect();
+
+        let mut readers: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, component) in components.iter().enumerate() {
+            for wire in component.inputs.iter().filter_map(Signal::wire) {
+                readers.entry(wire.to_string()).or_default().push(index);
+            }
+        }
+
+        let flip_flops = components.iter().enumerate()
+            .filter(|(_, component)| matches!(component.gate_type, GateType::Dff))
+            .map(|(index, _)| (index, FlipFlop { q: Logic::Low, clock: Logic::Low }))
+            .collect();
+        let latches = components.iter().enumerate()
+            .filter(|(_, component)| matches!(component.gate_type, GateType::SrLatch | GateType::DLatch))
+            .map(|(index, _)| (index, Logic::Low))
+            .collect();
+
+        let mut buses: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        for (index, component) in components.iter().enumerate() {
+            for (position, output) in component.outputs.iter().enumerate() {
+                buses.entry(output.clone()).or_default().push((index, position));
+            }
+        }
+        buses.retain(|_, drivers| drivers.len() > 1);
+
+        let mut values: HashMap<String, Logic> = HashMap::new();
+        if feedback {
+            values.extend(components.iter().flat_map(|c| c.outputs.iter()).map(|output| (output.clone(), Logic::Low)));
+        }
+        values.extend(program.inputs.iter().map(|input| (input.clone(), Logic::Low)));
+        values.extend(program.constants.iter().map(|(name, &value)| (name.clone(), Logic::from(value))));
+
+        Ok(State {
+            values,
+            dirty: (0..components.len()).collect(),
+            flip_flops,
+            latches,
+            driven: components.iter().map(|component| vec![Logic::Z; component.outputs.len()]).collect(),
+            buses,
+            delays: vec![DEFAULT_GATE_DELAY; components.len()],
+            time: 0,
+            components,
+            readers,
+        })
+    }
+
+    /// Schedules every gate that reads the given signal for re-evaluation
+    fn mark_readers_dirty(&mut self, signal: &str) {
+        if let Some(readers) = self.readers.get(signal) {
+            self.dirty.extend(readers);
+        }
+    }
+}
+
+/// Converts boolean input values to logic levels
+fn logic_inputs(inputs: &HashMap<String, bool>) -> HashMap<String, Logic> {
+    inputs.iter()
+        .map(|(name, &value)| (name.clone(), Logic::from(value)))
+        .collect()
+}
+
+/// Orders the components of a scope, returning SimError::CombinationalLoop if they form a loop
+fn scope_order(components: &[Component]) -> Result<Vec<usize>, SimError> {
+    match graph::topological_order(components) {
+        Ok(order) => Ok(order),
+        Err(_) => {
+            check_acyclic(components)?;
+            unreachable!("components without a loop always have a topological order");
+        }
+    }
+}
+
+/// Applies stuck-at faults to a flattened program
+/// Readers of a faulty input or constant read the stuck value instead, while the drivers of any other faulty signal
+/// drive a new wire that nothing reads and a BUF of the stuck value drives the signal in their place
+fn apply_faults(mut program: Program, faults: &BTreeMap<String, bool>) -> Program {
+    let mut taken = program.signals();
+    taken.extend(program.components.iter().map(|component| component.identifier.clone()));
+    let mut unique = |base: String| {
+        let mut name = base.clone();
+        let mut counter = 0;
+        while taken.contains(&name) {
+            name = format!("{}_{}", base, counter);
+            counter += 1;
+        }
+        taken.insert(name.clone());
+        name
+    };
+
+    for (signal, &value) in faults {
+        if program.inputs.contains(signal) || program.constants.contains_key(signal) {
+            for input in program.components.iter_mut().flat_map(|component| component.inputs.iter_mut()) {
+                if input.wire() == Some(signal) {
+                    *input = Signal::Constant(value);
+                }
+            }
+            continue;
+        }
+
+        let detached = unique(format!("{}_fault_free", signal));
+        let mut location = BUILT_LOCATION;
+        for component in &mut program.components {
+            for output in component.outputs.iter_mut().filter(|output| *output == signal) {
+                *output = detached.clone();
+                location = component.location;
+            }
+        }
+        program.components.push(Component {
+            gate_type: GateType::Buf,
+            identifier: unique(format!("{}_stuck_at_{}", signal, value as u8)),
+            inputs: vec![Signal::Constant(value)],
+            outputs: vec![signal.clone()],
+            location,
+            comments: vec![],
+        });
+    }
+    program
+}
+
+/// Formats one evaluated gate for the debug trace, e.g. "AND g1 IN(a=1, b=0) OUT(y=0)"
+fn trace_line(component: &Component, values: &HashMap<String, Logic>, results: &[Logic]) -> String {
+    let inputs: Vec<String> = component.inputs.iter()
+        .map(|input| match input {
+            Signal::Wire(name) => match values.get(name) {
+                Some(value) => format!("{}={}", name, value),
+                None => format!("{}=?", name),
+            },
+            Signal::Constant(value) => (*value as u8).to_string(),
+        })
+        .collect();
+    let outputs: Vec<String> = component.outputs.iter()
+        .zip(results)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    let kind = match &component.gate_type {
+        GateType::Subcircuit(name) => name.as_str(),
+        gate_type => gate_type.keyword(),
+    };
+    format!("{} {} IN({}) OUT({})", kind, graph::component_label(component), inputs.join(", "), outputs.join(", "))
+}
+
+/// Calls every callback watching the given signal with its new value, in registration order
+fn notify(watches: &mut [Watch], signal: &str, value: Logic) {
+    for watch in watches.iter_mut().filter(|watch| watch.signal == signal) {
+        (watch.callback)(value);
+    }
+}
+
+/// Returns the value of a component input from the current signal values
+fn read_signal(values: &HashMap<String, Logic>, signal: &Signal) -> Result<Logic, SimError> {
+    match signal {
+        Signal::Wire(name) => values.get(name).copied()
+            .ok_or_else(|| SimError::UndefinedSignal(name.clone())),
+        Signal::Constant(value) => Ok(Logic::from(*value)),
+    }
+}
+
+/// Records a value driven onto a wire during one-shot evaluation,
+/// resolving it against the value of any other tri-state buffer already driving the same wire
+fn drive(values: &mut HashMap<String, Logic>, wire: &str, value: Logic) {
+    values.entry(wire.to_string())
+        .and_modify(|current| *current = current.resolve(value))
+        .or_insert(value);
+}
+
+/// Returns the value of a wire after one of its drivers drove the given value
+/// A wire on a bus takes the resolved value of everything that drives it
+fn wire_value(buses: &HashMap<String, Vec<(usize, usize)>>, driven: &[Vec<Logic>], wire: &str, value: Logic) -> Logic {
+    match buses.get(wire) {
+        Some(drivers) => drivers.iter().map(|&(index, position)| driven[index][position]).fold(Logic::Z, Logic::resolve),
+        None => value,
+    }
+}
+
+/// Returns SimError::UndrivenOutput for the first declared output of the main circuit or a subcircuit
+/// that nothing drives, checking subcircuits in name order after the main circuit
+fn check_outputs_driven(program: &Program) -> Result<(), SimError> {
+    let mut undriven = graph::undriven_outputs(&program.inputs, &program.outputs, &program.components).into_iter()
+        .filter(|output| !program.constants.contains_key(*output));
+    if let Some(output) = undriven.next() {
+        return Err(SimError::UndrivenOutput(output.clone()));
+    }
+
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+    for name in names {
+        let subcircuit = &program.subcircuits[name];
+        if let Some(output) = graph::undriven_outputs(&subcircuit.inputs, &subcircuit.outputs, &subcircuit.components).into_iter().next() {
+            return Err(SimError::UndrivenOutput(output.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns SimError::CombinationalLoop naming the wires of the first loop in the component list
+pub(crate) fn check_acyclic(components: &[Component]) -> Result<(), SimError> {
+    match graph::find_cycle(components) {
+        Some(cycle) => Err(SimError::CombinationalLoop {
+            signals: cycle.into_iter().map(|(_, wire)| wire).collect(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Collects the values of the named signals
+fn read_signals(values: &HashMap<String, Logic>, names: &[String]) -> Result<HashMap<String, Logic>, SimError> {
+    names.iter()
+        .map(|name| match values.get(name) {
+            Some(value) => Ok((name.clone(), *value)),
+            None => Err(SimError::UndefinedSignal(name.clone())),
+        })
+        .collect()
+}
+
+/// Returns SimError::PortCountMismatch unless a subcircuit instance lists exactly one actual wire
+/// per formal input and output of the subcircuit
+pub(crate) fn check_port_counts(component: &Component, subcircuit: &Subcircuit) -> Result<(), SimError> {
+    for (direction, expected, found) in [
+        ("input", subcircuit.inputs.len(), component.inputs.len()),
+        ("output", subcircuit.outputs.len(), component.outputs.len()),
+    ] {
+        if expected != found {
+            return Err(SimError::PortCountMismatch {
+                component: component.identifier.clone(),
+                subcircuit: subcircuit.name.clone(),
+                direction,
+                expected,
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns SimError::BadArity if a gate that takes a fixed number of inputs has a different number
+/// NOT and BUF take exactly one input, TRI, DFF, the latches and HALFADDER exactly two and FULLADDER exactly three
+fn check_gate_arity(component: &Component) -> Result<(), SimError> {
+    let expected = component.gate_type.min_inputs();
+    if component.gate_type.max_inputs() != Some(expected) {
+        return Ok(());
+    }
+    if component.inputs.len() != expected {
+        return Err(SimError::BadArity {
+            component: component.identifier.clone(),
+            expected,
+            found: component.inputs.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the bits "bus[0]", "bus[1]" and so on of evaluated outputs, up to the first missing index
+/// Returns None if bus[0] is missing or the bus is wider than 64 bits
+fn bus_bits(values: &HashMap<String, bool>, bus: &str) -> Option<Vec<bool>> {
+    let bits: Vec<bool> = (0..)
+        .map_while(|index| values.get(&bus_bit(bus, index)).copied())
+        .collect();
+    (!bits.is_empty() && bits.len() <= 64).then_some(bits)
+}
+
+/// Reads a bus of evaluated outputs as an unsigned integer, bus[0] being the least significant bit
+/// Returns None if bus[0] is missing or the bus is wider than 64 bits
+pub fn read_bus(values: &HashMap<String, bool>, bus: &str) -> Option<u64> {
+    let bits = bus_bits(values, bus)?;
+    Some(bits.iter().rev().fold(0, |value, &bit| value << 1 | bit as u64))
+}
+
+/// Reads a bus of evaluated outputs as a two's complement integer, whose most significant bit is the sign
+/// Returns None if bus[0] is missing or the bus is wider than 64 bits
+pub fn read_signed_bus(values: &HashMap<String, bool>, bus: &str) -> Option<i64> {
+    let bits = bus_bits(values, bus)?;
+    let unused = 64 - bits.len() as u32;
+    let value = bits.iter().rev().fold(0, |value, &bit| value << 1 | bit as u64);
+    Some(((value << unused) as i64) >> unused)
+}
+
+/// Evaluates a combinational primitive gate for the given input values, returning one value per output
+/// AND, OR, NAND and NOR reduce over all of their inputs, XOR is odd parity and XNOR is even parity,
+/// so a 3-input XOR is high when one or three inputs are high. Adders return their sum followed by their carry
+/// Panics if NOT, BUF or an adder is given the wrong number of inputs, or if the gate is a TRI, DFF, latch or subcircuit instance
+pub fn eval_gate(gate: &GateType, inputs: &[bool]) -> Vec<bool> {
+    let output = match (gate, inputs) {
+        (GateType::And, _) => eval_and(inputs),
+        (GateType::Or, _) => eval_or(inputs),
+        (GateType::Nand, _) => eval_nand(inputs),
+        (GateType::Nor, _) => eval_nor(inputs),
+        (GateType::Xor, _) => eval_xor(inputs),
+        (GateType::Xnor, _) => eval_xnor(inputs),
+        (GateType::Not, &[input]) => eval_not(input),
+        (GateType::Buf, &[input]) => input,
+        (GateType::Not | GateType::Buf, _) => panic!("{} takes exactly one input, got {}", gate.keyword(), inputs.len()),
+        (GateType::HalfAdder, &[_, _]) | (GateType::FullAdder, &[_, _, _]) => return eval_adder(inputs),
+        (GateType::HalfAdder | GateType::FullAdder, _) =>
+            panic!("{} takes exactly {} inputs, got {}", gate.keyword(), gate.min_inputs(), inputs.len()),
+        (GateType::Tri, _) => panic!("tri-state buffers can float, evaluate them with eval_gate_logic"),
+        (GateType::Dff, _) => panic!("flip-flops are not combinational gates"),
+        (GateType::SrLatch | GateType::DLatch, _) => panic!("latches are not combinational gates"),
+        (GateType::Subcircuit(_), _) => panic!("subcircuit instances are not primitive gates"),
+    };
+    vec![output]
+}
+
+/// Evaluates a combinational primitive gate or tri-state buffer for the given input values, returning one value per output
+/// Floating inputs read as X, and an X input makes the output X unless another input decides it,
+/// e.g. AND with a low input is low. TRI takes (data, enable) and floats while enable is low
+/// Panics under the same conditions as eval_gate, or if TRI is not given exactly two inputs
+pub fn eval_gate_logic(gate: &GateType, inputs: &[Logic]) -> Vec<Logic> {
+    let inputs: Vec<Logic> = inputs.iter().map(|value| value.read()).collect();
+    if let GateType::Tri = gate {
+        let &[data, enable] = inputs.as_slice() else {
+            panic!("TRI takes exactly two inputs, got {}", inputs.len());
+        };
+        let output = match enable {
+            Logic::High => data,
+            Logic::Low => Logic::Z,
+            _ => Logic::X,
+        };
+        return vec![output];
+    }
+
+    let Some(values) = inputs.iter().map(|value| value.to_bool()).collect::<Option<Vec<bool>>>() else {
+        let output = match gate {
+            GateType::Not | GateType::Buf if inputs.len() != 1 =>
+                panic!("{} takes exactly one input, got {}", gate.keyword(), inputs.len()),
+            GateType::HalfAdder | GateType::FullAdder if inputs.len() != gate.min_inputs() =>
+                panic!("{} takes exactly {} inputs, got {}", gate.keyword(), gate.min_inputs(), inputs.len()),
+            GateType::Dff => panic!("flip-flops are not combinational gates"),
+            GateType::SrLatch | GateType::DLatch => panic!("latches are not combinational gates"),
+            GateType::Subcircuit(_) => panic!("subcircuit instances are not primitive gates"),
+            GateType::HalfAdder | GateType::FullAdder => return vec![Logic::X, adder_carry_logic(&inputs)],
+            GateType::And if inputs.contains(&Logic::Low) => Logic::Low,
+            GateType::Nand if inputs.contains(&Logic::Low) => Logic::High,
+            GateType::Or if inputs.contains(&Logic::High) => Logic::High,
+            GateType::Nor if inputs.contains(&Logic::High) => Logic::Low,
+            _ => Logic::X,
+        };
+        return vec![output];
+    };
+    eval_gate(gate, &values).into_iter().map(Logic::from).collect()
+}
+
+/// Computes the new output of an SRLATCH or DLATCH from its inputs and the value it currently holds
+/// SRLATCH takes (set, reset) and DLATCH takes (data, enable), and both hold q while their inputs are low.
+/// An unknown input gives X unless every value it could have leads to the same output
+/// Returns SimError::InvalidLatchState if set and reset are both high
+fn eval_latch(component: &Component, inputs: &[Logic], q: Logic) -> Result<Logic, SimError> {
+    let inputs: Vec<Logic> = inputs.iter().map(|value| value.read()).collect();
+    let &[first, second] = inputs.as_slice() else {
+        panic!("{} takes exactly two inputs, got {}", component.gate_type.keyword(), inputs.len());
+    };
+    let output = match (&component.gate_type, first, second) {
+        (GateType::SrLatch, Logic::High, Logic::High) =>
+            return Err(SimError::InvalidLatchState { component: component.identifier.clone() }),
+        (GateType::SrLatch, Logic::Low, Logic::Low) => q,
+        (GateType::SrLatch, Logic::High, Logic::Low) => Logic::High,
+        (GateType::SrLatch, Logic::Low, Logic::High) => Logic::Low,
+        (GateType::SrLatch, Logic::Low, _) if q == Logic::Low => Logic::Low,
+        (GateType::SrLatch, _, Logic::Low) if q == Logic::High => Logic::High,
+        (GateType::SrLatch, _, _) => Logic::X,
+        (GateType::DLatch, data, Logic::High) => data,
+        (GateType::DLatch, _, Logic::Low) => q,
+        (GateType::DLatch, data, _) if data == q => q,
+        (GateType::DLatch, _, _) => Logic::X,
+        (gate_type, _, _) => panic!("{} is not a latch", gate_type.keyword()),
+    };
+    Ok(output)
+}
+
+/// Adds the inputs of a half or full adder, returning the sum bit followed by the carry
+fn eval_adder(inputs: &[bool]) -> Vec<bool> {
+    let high = inputs.iter().filter(|&&x| x).count();
+    vec![high % 2 == 1, high >= 2]
+}
+
+/// Returns the carry of an adder with unknown inputs, which is decided when two inputs are high
+/// or when too few inputs can be high
+fn adder_carry_logic(inputs: &[Logic]) -> Logic {
+    let high = inputs.iter().filter(|&&value| value == Logic::High).count();
+    let unknown = inputs.iter().filter(|&&value| value == Logic::X).count();
+    if high >= 2 {
+        Logic::High
+    } else if high + unknown < 2 {
+        Logic::Low
+    } else {
+        Logic::X
+    }
+}
+
+fn eval_and(inputs: &[bool]) -> bool {
+    inputs.iter().all(|&x| x)
+}
+
+fn eval_or(inputs: &[bool]) -> bool {
+    inputs.iter().any(|&x| x)
+}
+
+fn eval_not(input: bool) -> bool {
+    !input
+}
+
+fn eval_nand(inputs: &[bool]) -> bool {
+    !eval_and(inputs)
+}
+
+fn eval_nor(inputs: &[bool]) -> bool {
+    !eval_or(inputs)
+}
+
+fn eval_xor(inputs: &[bool]) -> bool {
+    inputs.iter().fold(false, |acc, &x| acc ^ x)
+}
+
+fn eval_xnor(inputs: &[bool]) -> bool {
+    !eval_xor(inputs)
+}
@@ -0,0 +1,590 @@
+use std::collections::{HashMap, HashSet};
+use crate::lang::*;
+use crate::sim::{check_port_counts, eval_gate, SimError};
+
+// Aliases
+
+/// Returns the signal an alias chain ends at, or the name itself when it is not an alias
+/// Following stops after as many steps as there are aliases, so a cyclic chain cannot loop forever
+fn alias_root<'a>(targets: &HashMap<&'a str, &'a str>, name: &'a str) -> &'a str {
+    let mut root = name;
+    for _ in 0..targets.len() {
+        match targets.get(root) {
+            Some(target) => root = target,
+            None => break,
+        }
+    }
+    root
+}
+
+/// Rewires the components of one scope to read the signals its aliases name
+/// A declared output that is an alias has no driver of its own, so it gets a buffer of the aliased signal
+fn resolve_scope(outputs: &[String], aliases: &[Alias], components: &mut Vec<Component>) {
+    let targets: HashMap<&str, &str> = aliases.iter()
+        .map(|alias| (alias.name.as_str(), alias.target.as_str()))
+        .collect();
+    for component in components.iter_mut() {
+        for input in &mut component.inputs {
+            if let Signal::Wire(name) = input {
+                let root = alias_root(&targets, name).to_string();
+                *name = root;
+            }
+        }
+    }
+
+    let mut identifiers: HashSet<String> = components.iter().map(|c| c.identifier.clone()).collect();
+    for alias in aliases.iter().filter(|alias| outputs.contains(&alias.name)) {
+        let base = alias.name.replace('[', "_").replace(']', "");
+        let mut identifier = base.clone();
+        let mut counter = 0;
+        while identifiers.contains(&identifier) {
+            identifier = format!("{}_{}", base, counter);
+            counter += 1;
+        }
+        identifiers.insert(identifier.clone());
+        components.push(Component {
+            gate_type: GateType::Buf,
+            identifier,
+            inputs: vec![Signal::Wire(alias_root(&targets, &alias.target).to_string())],
+            outputs: vec![alias.name.clone()],
+            location: alias.location,
+            comments: vec![],
+        });
+    }
+}
+
+impl Program {
+    /// Returns whether the main circuit or any subcircuit declares an alias
+    pub fn has_aliases(&self) -> bool {
+        !self.aliases.is_empty() || self.subcircuits.values().any(|subcircuit| !subcircuit.aliases.is_empty())
+    }
+
+    /// Returns a copy of the program without aliases, in which every reader of an alias reads the aliased
+    /// signal instead. Chains of aliases are followed to the signal at their end, and a declared output
+    /// that is an alias is driven by a BUF appended after the scope's components and named after the output.
+    /// Resolving keeps every other component where it was, in both the main circuit and the subcircuits
+    pub fn resolve_aliases(&self) -> Program {
+        let mut program = self.clone();
+        let aliases = std::mem::take(&mut program.aliases);
+        resolve_scope(&program.outputs, &aliases, &mut program.components);
+        for subcircuit in program.subcircuits.values_mut() {
+            let aliases = std::mem::take(&mut subcircuit.aliases);
+            resolve_scope(&subcircuit.outputs, &aliases, &mut subcircuit.components);
+        }
+        program
+    }
+}
+
+// Flattening
+
+/// Separator between instance names and inner names in flattened identifiers
+/// The default lexer never puts it in a name, and flatten rejects names that contain it
+pub const HIERARCHY_SEPARATOR: char = '.';
+
+/// Resolves names inside one subcircuit instance to names in the enclosing scope
+struct Binding<'a> {
+    prefix: String,                      // Instance path prepended to internal names
+    inputs: HashMap<&'a str, Signal>,    // Formal input name -> actual signal
+    outputs: HashMap<&'a str, String>,   // Formal output name -> actual wire
+}
+
+impl Binding<'_> {
+    /// Returns the outer signal feeding an inner component input
+    fn input(&self, signal: &Signal) -> Signal {
+        match signal {
+            Signal::Wire(name) => {
+                if let Some(actual) = self.inputs.get(name.as_str()) {
+                    actual.clone()
+                } else {
+                    Signal::Wire(self.output(name))
+                }
+            }
+            Signal::Constant(value) => Signal::Constant(*value),
+        }
+    }
+
+    /// Returns the outer wire for an inner name that a component drives
+    fn output(&self, name: &str) -> String {
+        match self.outputs.get(name) {
+            Some(actual) => actual.clone(),
+            None => self.internal(name),
+        }
+    }
+
+    /// Prefixes an internal name with the instance path
+    fn internal(&self, name: &str) -> String {
+        format!("{}{}{}", self.prefix, HIERARCHY_SEPARATOR, name)
+    }
+}
+
+/// Returns SimError::SeparatorInName for the first name in the program that contains HIERARCHY_SEPARATOR
+/// Scopes are checked in sorted subcircuit name order, then the main circuit
+fn check_separator_free(program: &Program) -> Result<(), SimError> {
+    let mut names: Vec<&String> = program.subcircuits.keys().collect();
+    names.sort();
+    let scopes = names.into_iter()
+        .map(|name| {
+            let subcircuit = &program.subcircuits[name];
+            (&subcircuit.inputs, &subcircuit.outputs, &subcircuit.components)
+        })
+        .chain(std::iter::once((&program.inputs, &program.outputs, &program.components)));
+    for (inputs, outputs, components) in scopes {
+        let wires = components.iter().flat_map(|component| {
+            std::iter::once(component.identifier.as_str())
+                .chain(component.inputs.iter().filter_map(Signal::wire))
+                .chain(component.outputs.iter().map(String::as_str))
+        });
+        let mut names = inputs.iter().chain(outputs).map(String::as_str).chain(wires);
+        if let Some(name) = names.find(|name| name.contains(HIERARCHY_SEPARATOR)) {
+            return Err(SimError::SeparatorInName(name.to_string()));
+        }
+    }
+    match program.constants.keys().filter(|name| name.contains(HIERARCHY_SEPARATOR)).min() {
+        Some(name) => Err(SimError::SeparatorInName(name.clone())),
+        None => Ok(()),
+    }
+}
+
+impl Program {
+    /// Inlines every subcircuit instance, returning a program made only of primitive gates
+    /// Inner identifiers and wires are prefixed with the instance name, e.g. "full_adder_0.temp1",
+    /// and the instance's actual wires are connected to the subcircuit's formal ports by position,
+    /// with SimError::PortCountMismatch if an instance has too many or too few wires.
+    /// Aliases are resolved first, so the flattened program has none. A program with subcircuits may not use
+    /// HIERARCHY_SEPARATOR in its names, since they could then clash with flattened ones, and
+    /// SimError::SeparatorInName is returned for the first such name
+    pub fn flatten(&self) -> Result<Program, SimError> {
+        if self.has_aliases() {
+            return self.resolve_aliases().flatten();
+        }
+        if !self.subcircuits.is_empty() {
+            check_separator_free(self)?;
+        }
+        let mut components = vec![];
+        let mut stack = vec![];
+        for component in &self.components {
+            self.flatten_component(component, None, &mut stack, &mut components)?;
+        }
+
+        Ok(Program {
+            subcircuits: HashMap::new(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            constants: self.constants.clone(),
+            components,
+            aliases: vec![],
+            comments: self.comments.clone(),
+            output_locations: self.output_locations.clone(),
+        })
+    }
+
+    /// Appends the primitive gates of one component to the flattened list
+    /// The stack holds the subcircuits currently being expanded, to reject recursive definitions
+    fn flatten_component(
+        &self,
+        component: &Component,
+        binding: Option<&Binding>,
+        stack: &mut Vec<String>,
+        flattened: &mut Vec<Component>,
+    ) -> Result<(), SimError> {
+        let identifier = match binding {
+            Some(binding) => binding.internal(&component.identifier),
+            None => component.identifier.clone(),
+        };
+        let inputs: Vec<Signal> = component.inputs.iter()
+            .map(|input| binding.map_or_else(|| input.clone(), |b| b.input(input)))
+            .collect();
+        let outputs: Vec<String> = component.outputs.iter()
+            .map(|output| binding.map_or_else(|| output.clone(), |b| b.output(output)))
+            .collect();
+
+        let GateType::Subcircuit(name) = &component.gate_type else {
+            flattened.push(Component {
+                gate_type: component.gate_type.clone(),
+                identifier,
+                inputs,
+                outputs,
+                location: component.location,
+                comments: component.comments.clone(),
+            });
+            return Ok(());
+        };
+
+        if let Some(start) = stack.iter().position(|n| n == name) {
+            let mut chain = stack[start..].to_vec();
+            chain.push(name.clone());
+            return Err(SimError::RecursiveSubcircuit { chain });
+        }
+        let subcircuit = self.subcircuits.get(name)
+            .ok_or_else(|| SimError::UnknownSubcircuit(name.clone()))?;
+        check_port_counts(component, subcircuit)?;
+
+        let inner = Binding {
+            prefix: identifier,
+            inputs: subcircuit.inputs.iter().map(String::as_str).zip(inputs).collect(),
+            outputs: subcircuit.outputs.iter().map(String::as_str).zip(outputs).collect(),
+        };
+
+        // A formal output that is also a formal input passes the value straight through
+        for output in &subcircuit.outputs {
+            if let (Some(source), Some(actual)) = (inner.inputs.get(output.as_str()), inner.outputs.get(output.as_str())) {
+                flattened.push(Component {
+                    gate_type: GateType::Buf,
+                    identifier: inner.internal(output),
+                    inputs: vec![source.clone()],
+                    outputs: vec![actual.clone()],
+                    location: component.location,
+                    comments: vec![],
+                });
+            }
+        }
+
+        stack.push(name.clone());
+        for inner_component in &subcircuit.components {
+            self.flatten_component(inner_component, Some(&inner), stack, flattened)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+// Constant folding
+
+/// What a gate with some constant inputs can be replaced by
+enum Folded {
+    Unchanged,                   // Nothing to simplify
+    Constant(bool),              // The gate always drives this value
+    Gate(GateType, Vec<Signal>), // An equivalent gate reading only the remaining wires
+}
+
+/// Simplifies a combinational gate whose inputs include constants
+/// Constants that cannot change the result are dropped, so OR(x, 0) becomes BUF(x) and XOR(x, 1) becomes NOT(x)
+fn fold_gate(gate_type: &GateType, inputs: &[Signal]) -> Folded {
+    let constants: Vec<bool> = inputs.iter()
+        .filter_map(|input| match input {
+            Signal::Constant(value) => Some(*value),
+            Signal::Wire(_) => None,
+        })
+        .collect();
+    if constants.is_empty() {
+        return Folded::Unchanged;
+    }
+
+    let wires: Vec<Signal> = inputs.iter().filter(|input| input.wire().is_some()).cloned().collect();
+    let (base, inverted) = match gate_type {
+        GateType::Not | GateType::Buf if wires.is_empty() && constants.len() == 1 =>
+            return Folded::Constant(eval_gate(gate_type, &constants)[0]),
+        GateType::And => (GateType::And, false),
+        GateType::Nand => (GateType::And, true),
+        GateType::Or => (GateType::Or, false),
+        GateType::Nor => (GateType::Or, true),
+        GateType::Xor => (GateType::Xor, false),
+        GateType::Xnor => (GateType::Xor, true),
+        _ => return Folded::Unchanged,
+    };
+    if wires.is_empty() {
+        return Folded::Constant(eval_gate(gate_type, &constants)[0]);
+    }
+
+    // A controlling constant decides the result, the others are the identity of AND and OR,
+    // and every high input into an XOR inverts its result
+    let inverted = match base {
+        GateType::And if constants.contains(&false) => return Folded::Constant(inverted),
+        GateType::Or if constants.contains(&true) => return Folded::Constant(!inverted),
+        GateType::Xor => inverted ^ (constants.iter().filter(|&&value| value).count() % 2 == 1),
+        _ => inverted,
+    };
+    let gate_type = match (base, inverted) {
+        _ if wires.len() == 1 => if inverted { GateType::Not } else { GateType::Buf },
+        (GateType::And, false) => GateType::And,
+        (GateType::And, true) => GateType::Nand,
+        (GateType::Or, false) => GateType::Or,
+        (GateType::Or, true) => GateType::Nor,
+        (_, false) => GateType::Xor,
+        (_, true) => GateType::Xnor,
+    };
+    Folded::Gate(gate_type, wires)
+}
+
+/// Returns the signals read by the given components of a scope, together with its declared outputs
+fn read_signals<'a>(components: impl Iterator<Item = &'a Component>, outputs: &'a [String]) -> HashSet<&'a str> {
+    components
+        .flat_map(|component| component.inputs.iter().filter_map(Signal::wire))
+        .chain(outputs.iter().map(String::as_str))
+        .collect()
+}
+
+/// Folds the constants of one scope until nothing changes, keeping its declared outputs driven
+/// The constants map starts with the scope's named constants and grows with the wires of folded gates.
+/// Only gates whose readers were folded away are removed as dead, and the last gate reading one of the
+/// given inputs is kept, so the formal inputs of a subcircuit stay connected
+fn fold_scope(inputs: &[String], outputs: &[String], mut constants: HashMap<String, bool>, mut components: Vec<Component>) -> Vec<Component> {
+    let read = read_signals(components.iter(), outputs);
+    let unread: HashSet<String> = components.iter()
+        .filter(|component| !component.outputs.iter().any(|output| read.contains(output.as_str())))
+        .map(|component| component.identifier.clone())
+        .collect();
+
+    loop {
+        let mut changed = false;
+        let mut removed = vec![false; components.len()];
+        for (index, component) in components.iter_mut().enumerate() {
+            for input in &mut component.inputs {
+                if let Some(&value) = input.wire().and_then(|wire| constants.get(wire)) {
+                    *input = Signal::Constant(value);
+                    changed = true;
+                }
+            }
+
+            match fold_gate(&component.gate_type, &component.inputs) {
+                Folded::Unchanged => {}
+                Folded::Gate(gate_type, inputs) => {
+                    component.gate_type = gate_type;
+                    component.inputs = inputs;
+                    changed = true;
+                }
+                Folded::Constant(value) => {
+                    for output in &component.outputs {
+                        changed |= constants.insert(output.clone(), value) != Some(value);
+                    }
+                    // A declared output still needs a driver, so it keeps a buffer of the constant
+                    if component.outputs.iter().any(|output| outputs.contains(output)) {
+                        let buffer = [Signal::Constant(value)];
+                        if component.gate_type != GateType::Buf || component.inputs != buffer {
+                            component.gate_type = GateType::Buf;
+                            component.inputs = buffer.to_vec();
+                            changed = true;
+                        }
+                    } else {
+                        removed[index] = true;
+                    }
+                }
+            }
+        }
+
+        // Gates whose outputs nothing reads any more are dead, unless nothing read them before folding either
+        let kept = components.iter().zip(&removed).filter(|(_, &removed)| !removed).map(|(component, _)| component);
+        let read = read_signals(kept, outputs);
+        for (component, removed) in components.iter().zip(removed.iter_mut()) {
+            if !unread.contains(&component.identifier) && !component.outputs.iter().any(|output| read.contains(output.as_str())) {
+                *removed = true;
+            }
+        }
+
+        // The formal inputs of a subcircuit must stay connected, so the last gate reading one is kept
+        let kept = components.iter().zip(&removed).filter(|(_, &removed)| !removed).map(|(component, _)| component);
+        let read = read_signals(kept, &[]);
+        for input in inputs.iter().filter(|input| !read.contains(input.as_str())) {
+            let reader = components.iter().zip(&removed)
+                .position(|(component, &removed)| removed && component.inputs.iter().any(|signal| signal.wire() == Some(input)));
+            if let Some(reader) = reader {
+                removed[reader] = false;
+            }
+        }
+
+        if removed.contains(&true) {
+            let mut removed = removed.into_iter();
+            components.retain(|_| !removed.next().unwrap_or(false));
+            changed = true;
+        }
+
+        if !changed {
+            return components;
+        }
+    }
+}
+
+impl Program {
+    /// Returns a copy of the program with gates that have constant inputs evaluated ahead of time
+    /// Gates whose output is fixed are removed and their readers get the constant instead, gates with
+    /// some constant inputs become simpler gates over the remaining wires, and gates nothing reads any more
+    /// are removed, repeating until nothing changes. Readers of named constants get the value as well.
+    /// Declared outputs keep their values for every input, and subcircuits are folded the same way
+    /// without changing their ports, keeping a gate that reads each of their inputs. Aliases are resolved first, so the folded program has none
+    pub fn fold_constants(&self) -> Program {
+        let mut program = self.resolve_aliases();
+        program.components = fold_scope(&[], &program.outputs, program.constants.clone(), std::mem::take(&mut program.components));
+        for subcircuit in program.subcircuits.values_mut() {
+            let components = std::mem::take(&mut subcircuit.components);
+            subcircuit.components = fold_scope(&subcircuit.inputs, &subcircuit.outputs, HashMap::new(), components);
+        }
+        program
+    }
+}
+
+// Inverter chains
+
+/// Returns the input of the only component driving a signal, if that component is a NOT or BUF with one input
+fn chain_driver<'a>(drivers: &HashMap<&str, Vec<&'a Component>>, signal: &str) -> Option<(&'a GateType, &'a Signal)> {
+    match drivers.get(signal).map(Vec::as_slice) {
+        Some([component]) if matches!(component.gate_type, GateType::Not | GateType::Buf) && component.inputs.len() == 1 =>
+            Some((&component.gate_type, &component.inputs[0])),
+        _ => None,
+    }
+}
+
+/// Returns the furthest upstream signal that always has the same value as the given one,
+/// looking through buffers and through pairs of inverters. Signals on a loop resolve to themselves
+fn same_signal<'a>(
+    signal: &'a str,
+    drivers: &HashMap<&str, Vec<&'a Component>>,
+    memo: &mut HashMap<&'a str, Signal>,
+    visiting: &mut HashSet<&'a str>,
+) -> Signal {
+    if let Some(same) = memo.get(signal) {
+        return same.clone();
+    }
+    if !visiting.insert(signal) {
+        return Signal::Wire(signal.to_string());
+    }
+
+    let same = match chain_driver(drivers, signal) {
+        Some((GateType::Buf, Signal::Wire(input))) => same_signal(input, drivers, memo, visiting),
+        Some((GateType::Buf, constant)) => constant.clone(),
+        Some((_, Signal::Wire(input))) => match same_signal(input, drivers, memo, visiting) {
+            // The inverter's input equals the output of another inverter, so the two cancel out
+            Signal::Wire(upstream) => match chain_driver(drivers, &upstream) {
+                Some((GateType::Not, Signal::Wire(source))) => same_signal(source, drivers, memo, visiting),
+                Some((GateType::Not, constant)) => constant.clone(),
+                _ => Signal::Wire(signal.to_string()),
+            },
+            Signal::Constant(_) => Signal::Wire(signal.to_string()),
+        },
+        _ => Signal::Wire(signal.to_string()),
+    };
+    visiting.remove(signal);
+    memo.insert(signal, same.clone());
+    same
+}
+
+/// Rewires the readers of buffered and doubly inverted signals in one scope to the signal upstream of the chain
+/// Declared outputs keep a driver, which becomes a BUF of the upstream signal, while buffers and inverters
+/// that nothing reads any more are removed. Gates that were unread to begin with are left alone
+fn simplify_scope(outputs: &[String], mut components: Vec<Component>) -> Vec<Component> {
+    let mut replacements: HashMap<String, Signal> = HashMap::new();
+    {
+        let mut drivers: HashMap<&str, Vec<&Component>> = HashMap::new();
+        for component in &components {
+            for output in &component.outputs {
+                drivers.entry(output).or_default().push(component);
+            }
+        }
+        let mut memo = HashMap::new();
+        let mut visiting = HashSet::new();
+        let signals = components.iter().flat_map(|component| {
+            component.inputs.iter().filter_map(Signal::wire).chain(component.outputs.iter().map(String::as_str))
+        });
+        for signal in signals {
+            let same = same_signal(signal, &drivers, &mut memo, &mut visiting);
+            if same.wire() != Some(signal) {
+                replacements.insert(signal.to_string(), same);
+            }
+        }
+    }
+
+    let read_before: HashSet<String> = components.iter()
+        .flat_map(|component| component.inputs.iter().filter_map(Signal::wire))
+        .map(str::to_string)
+        .collect();
+    for component in &mut components {
+        for input in &mut component.inputs {
+            if let Some(same) = input.wire().and_then(|wire| replacements.get(wire)) {
+                *input = same.clone();
+            }
+        }
+        let is_chain = matches!(component.gate_type, GateType::Not | GateType::Buf) && component.outputs.len() == 1;
+        if is_chain && outputs.contains(&component.outputs[0]) {
+            if let Some(same) = replacements.get(&component.outputs[0]) {
+                component.gate_type = GateType::Buf;
+                component.inputs = vec![same.clone()];
+            }
+        }
+    }
+
+    loop {
+        let read: HashSet<&str> = components.iter()
+            .flat_map(|component| component.inputs.iter().filter_map(Signal::wire))
+            .chain(outputs.iter().map(String::as_str))
+            .collect();
+        let dead: Vec<bool> = components.iter()
+            .map(|component| {
+                matches!(component.gate_type, GateType::Not | GateType::Buf)
+                    && component.outputs.iter().all(|output| !read.contains(output.as_str()) && read_before.contains(output))
+            })
+            .collect();
+        if !dead.contains(&true) {
+            return components;
+        }
+        let mut dead = dead.into_iter();
+        components.retain(|_| !dead.next().unwrap_or(false));
+    }
+}
+
+impl Program {
+    /// Returns a copy of the program where buffers and pairs of inverters in a row are bypassed
+    /// Readers of a signal that always equals one further upstream, such as the output of NOT(NOT(x)) or BUF(x),
+    /// read the upstream signal instead, and the buffers and inverters left without readers are removed.
+    /// A chain only passes through signals with a single driver, an intermediate signal that other gates
+    /// still read keeps its driver, and a declared output keeps a BUF of the upstream signal.
+    /// Subcircuits are simplified the same way without changing their ports, and aliases are resolved first
+    pub fn simplify_inverters(&self) -> Program {
+        let mut program = self.resolve_aliases();
+        program.components = simplify_scope(&program.outputs, std::mem::take(&mut program.components));
+        for subcircuit in program.subcircuits.values_mut() {
+            subcircuit.components = simplify_scope(&subcircuit.outputs, std::mem::take(&mut subcircuit.components));
+        }
+        program
+    }
+}
+
+// Renaming
+
+/// Prefix of the names minimize_signals gives to internal signals
+pub const MINIMIZED_SIGNAL_PREFIX: &str = "n";
+
+impl Program {
+    /// Returns a copy of the program whose internal signals are renamed to "n0", "n1" and so on,
+    /// together with a map from each old name to its new name
+    /// Internal signals are the wires of the main circuit that are neither inputs, outputs nor constants,
+    /// numbered in order of first appearance in the component list. Names already used by an input,
+    /// output or constant are skipped. Subcircuits keep their names, since their wires are local to them.
+    /// Aliases are resolved first, so the renamed program has none
+    pub fn minimize_signals(&self) -> (Program, HashMap<String, String>) {
+        if self.has_aliases() {
+            return self.resolve_aliases().minimize_signals();
+        }
+        let is_port = |name: &str| {
+            self.inputs.iter().chain(&self.outputs).any(|port| port == name) || self.constants.contains_key(name)
+        };
+
+        let mut renames: HashMap<String, String> = HashMap::new();
+        let mut counter = 0;
+        let wires = self.components.iter().flat_map(|component| {
+            component.inputs.iter().filter_map(Signal::wire).chain(component.outputs.iter().map(String::as_str))
+        });
+        for wire in wires {
+            if is_port(wire) || renames.contains_key(wire) {
+                continue;
+            }
+            let mut name = format!("{}{}", MINIMIZED_SIGNAL_PREFIX, counter);
+            while is_port(&name) {
+                counter += 1;
+                name = format!("{}{}", MINIMIZED_SIGNAL_PREFIX, counter);
+            }
+            counter += 1;
+            renames.insert(wire.to_string(), name);
+        }
+
+        let rename = |name: &String| renames.get(name).unwrap_or(name).clone();
+        let mut program = self.clone();
+        for component in &mut program.components {
+            for input in &mut component.inputs {
+                if let Signal::Wire(name) = input {
+                    *name = rename(name);
+                }
+            }
+            component.outputs = component.outputs.iter().map(rename).collect();
+        }
+        (program, renames)
+    }
+}
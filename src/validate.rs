@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::graph;
+use crate::lang::*;
+
+/// Represents problems in a parsed program that make it impossible to simulate
+#[derive(Debug)]
+pub enum SemanticError {
+    UndefinedSignal { signal: String, component: String, location: Location },
+    BadArity { gate: String, expected: String, found: usize, location: Location },
+    BadOutputCount { gate: String, expected: usize, found: usize, location: Location },
+    RecursiveSubcircuit { chain: Vec<String> },
+    MultipleDrivers { signal: String, locations: Vec<Location> },
+    DrivenInput { signal: String, location: Location },
+    DrivenConstant { signal: String, location: Location },
+    UnknownSubcircuit { name: String, location: Location },
+    UndrivenOutput { signal: String, subcircuit: Option<String>, location: Location }, // Location of the output in its OUTPUTS section
+    InputOutputCollision { name: String, subcircuit: Option<String> },
+    DuplicateSubcircuit { name: String, location: Location },
+    AliasCollision { name: String, location: Location },
+    UndefinedAliasTarget { name: String, target: String, location: Location },
+    AliasCycle { chain: Vec<String>, location: Location },
+    UnknownOutput { name: String },
+    UnconnectedPort { subcircuit: String, port: String },
+}
+
+impl std::error::Error for SemanticError {}
+
+/// Implements Display trait for SemanticError to provide human-readable error messages
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::UndefinedSignal { signal, component, location } =>
+                write!(f, "Undefined signal '{}' used by component '{}' at {}", signal, component, location),
+            SemanticError::BadArity { gate, expected, found, location } =>
+                write!(f, "Component '{}' takes {} input(s) but has {} at {}", gate, expected, found, location),
+            SemanticError::BadOutputCount { gate, expected, found, location } =>
+                write!(f, "Component '{}' drives {} output(s) but declares {} at {}", gate, expected, found, location),
+            SemanticError::RecursiveSubcircuit { chain } =>
+                write!(f, "Subcircuit instantiates itself: {}", chain.join(" -> ")),
+            SemanticError::MultipleDrivers { signal, locations } => {
+                let locations: Vec<String> = locations.iter().map(Location::to_string).collect();
+                write!(f, "Signal '{}' is driven by more than one component, at {}", signal, locations.join(" and "))
+            }
+            SemanticError::DrivenInput { signal, location } =>
+                write!(f, "Input '{}' is also driven by a component at {}", signal, location),
+            SemanticError::DrivenConstant { signal, location } =>
+                write!(f, "Constant '{}' is also driven by a component at {}", signal, location),
+            SemanticError::UnknownSubcircuit { name, location } =>
+                write!(f, "Unknown subcircuit '{}' at {}", name, location),
+            SemanticError::UndrivenOutput { signal, subcircuit: Some(name), location } =>
+                write!(f, "Output '{}' of subcircuit '{}' is never driven, declared at {}", signal, name, location),
+            SemanticError::UndrivenOutput { signal, subcircuit: None, location } =>
+                write!(f, "Output '{}' is never driven, declared at {}", signal, location),
+            SemanticError::InputOutputCollision { name, subcircuit: Some(subcircuit) } =>
+                write!(f, "'{}' is declared as both an input and an output of subcircuit '{}'", name, subcircuit),
+            SemanticError::InputOutputCollision { name, subcircuit: None } =>
+                write!(f, "'{}' is declared as both an input and an output", name),
+            SemanticError::DuplicateSubcircuit { name, location } =>
+                write!(f, "Subcircuit '{}' is defined by both the program and the library at {}", name, location),
+            SemanticError::AliasCollision { name, location } =>
+                write!(f, "Alias '{}' is already the name of another signal at {}", name, location),
+            SemanticError::UndefinedAliasTarget { name, target, location } =>
+                write!(f, "Alias '{}' refers to undefined signal '{}' at {}", name, target, location),
+            SemanticError::AliasCycle { chain, location } =>
+                write!(f, "Aliases refer to each other in a cycle: {} at {}", chain.join(" -> "), location),
+            SemanticError::UnknownOutput { name } =>
+                write!(f, "'{}' is not an output of the program", name),
+            SemanticError::UnconnectedPort { subcircuit, port } =>
+                write!(f, "Input '{}' of subcircuit '{}' is not connected to any of its components", port, subcircuit),
+        }
+    }
+}
+
+/// Represents likely mistakes in a parsed program that do not stop it from being simulated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    UnusedSubcircuit { name: String, location: Location },
+    UnusedInput { name: String }, // An input of the main circuit, since subcircuits must read all of theirs
+    UnreachableGate { identifier: String, subcircuit: Option<String> },
+}
+
+/// Implements Display trait for Lint to provide human-readable warning messages
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::UnusedSubcircuit { name, location } =>
+                write!(f, "Subcircuit '{}' is never instantiated, defined at {}", name, location),
+            Lint::UnusedInput { name } =>
+                write!(f, "Input '{}' is never read", name),
+            Lint::UnreachableGate { identifier, subcircuit: Some(subcircuit) } =>
+                write!(f, "Component '{}' of subcircuit '{}' does not lead to any output", identifier, subcircuit),
+            Lint::UnreachableGate { identifier, subcircuit: None } =>
+                write!(f, "Component '{}' does not lead to any output", identifier),
+        }
+    }
+}
+
+/// A set of inputs, outputs and components that share one signal namespace
+struct Scope<'a> {
+    name: Option<&'a str>, // Subcircuit name, or None for the main circuit
+    inputs: &'a [String],
+    outputs: &'a [String],
+    output_locations: &'a HashMap<String, Location>,
+    constants: Vec<&'a str>, // Named constants, which only the main circuit has
+    components: &'a [Component],
+    aliases: &'a [Alias],
+}
+
+impl Program {
+    /// Checks every scope of the program (each subcircuit and the main circuit)
+    /// Returns all problems found rather than stopping at the first one
+    pub fn validate(&self) -> Result<(), Vec<SemanticError>> {
+        let mut errors: Vec<SemanticError> = graph::recursive_subcircuits(self).into_iter()
+            .map(|chain| SemanticError::RecursiveSubcircuit { chain })
+            .collect();
+
+        for scope in self.scopes() {
+            check_port_collisions(&scope, &mut errors);
+            check_aliases(&scope, &mut errors);
+            check_undefined_signals(&scope, &mut errors);
+            self.check_arity(&scope, &mut errors);
+            check_drivers(&scope, &mut errors);
+            check_undriven_outputs(&scope, &mut errors);
+            check_unconnected_ports(&scope, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Finds likely mistakes that validate does not treat as errors
+    /// Subcircuits that the main circuit never instantiates, directly or through other subcircuits, come first
+    /// in name order, followed by the inputs of the main circuit no component reads and then the components whose
+    /// outputs never reach a declared output, for each subcircuit in name order and then the main circuit
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut used: HashSet<&str> = HashSet::new();
+        let mut pending: Vec<&[Component]> = vec![&self.components];
+        while let Some(components) = pending.pop() {
+            for component in components {
+                let GateType::Subcircuit(name) = &component.gate_type else {
+                    continue;
+                };
+                if let Some(subcircuit) = self.subcircuits.get(name) {
+                    if used.insert(name) {
+                        pending.push(&subcircuit.components);
+                    }
+                }
+            }
+        }
+
+        let mut names: Vec<&String> = self.subcircuits.keys().filter(|name| !used.contains(name.as_str())).collect();
+        names.sort();
+        let mut lints: Vec<Lint> = names.into_iter()
+            .map(|name| Lint::UnusedSubcircuit { name: name.clone(), location: self.subcircuits[name].location })
+            .collect();
+
+        for scope in self.scopes() {
+            let read: HashSet<&str> = scope.components.iter()
+                .flat_map(|component| component.inputs.iter().filter_map(Signal::wire))
+                .chain(scope.aliases.iter().map(|alias| alias.target.as_str()))
+                .collect();
+            // An unread input of a subcircuit is an error, reported by validate as UnconnectedPort
+            if scope.name.is_none() {
+                for input in scope.inputs.iter().filter(|input| !read.contains(input.as_str())) {
+                    lints.push(Lint::UnusedInput { name: input.clone() });
+                }
+            }
+            for component in unreachable_components(&scope) {
+                lints.push(Lint::UnreachableGate {
+                    identifier: component.identifier.clone(),
+                    subcircuit: scope.name.map(str::to_string),
+                });
+            }
+        }
+        lints
+    }
+
+    /// Returns the subcircuit scopes in name order, followed by the main circuit
+    fn scopes(&self) -> Vec<Scope<'_>> {
+        let mut names: Vec<&String> = self.subcircuits.keys().collect();
+        names.sort();
+
+        let mut scopes: Vec<Scope> = names.into_iter()
+            .map(|name| {
+                let subcircuit = &self.subcircuits[name];
+                Scope {
+                    name: Some(name),
+                    inputs: &subcircuit.inputs,
+                    outputs: &subcircuit.outputs,
+                    output_locations: &subcircuit.output_locations,
+                    constants: vec![],
+                    components: &subcircuit.components,
+                    aliases: &subcircuit.aliases,
+                }
+            })
+            .collect();
+        scopes.push(Scope {
+            name: None,
+            inputs: &self.inputs,
+            outputs: &self.outputs,
+            output_locations: &self.output_locations,
+            constants: self.constants.keys().map(String::as_str).collect(),
+            components: &self.components,
+            aliases: &self.aliases,
+        });
+        scopes
+    }
+}
+
+impl Program {
+    /// Flags components whose input or output counts do not fit their gate type
+    /// NOT and BUF take exactly one input, TRI, DFF and the latches take exactly two,
+    /// HALFADDER takes two and FULLADDER three inputs and both drive two outputs, the other primitive gates
+    /// take two or more, and subcircuit instances must name a defined subcircuit and match its ports
+    fn check_arity(&self, scope: &Scope, errors: &mut Vec<SemanticError>) {
+        for component in scope.components {
+            let found = component.inputs.len();
+            let gate_type = &component.gate_type;
+            let (expected_inputs, inputs_ok, expected_outputs) = match gate_type {
+                GateType::Subcircuit(name) => match self.subcircuits.get(name) {
+                    Some(subcircuit) => (
+                        format!("exactly {}", subcircuit.inputs.len()),
+                        found == subcircuit.inputs.len(),
+                        subcircuit.outputs.len(),
+                    ),
+                    None => {
+                        errors.push(SemanticError::UnknownSubcircuit { name: name.clone(), location: component.location });
+                        continue;
+                    }
+                },
+                _ => {
+                    let min = gate_type.min_inputs();
+                    let expected = match gate_type.max_inputs() {
+                        Some(max) if max == min => format!("exactly {}", min),
+                        _ => format!("at least {}", min),
+                    };
+                    let inputs_ok = found >= min && gate_type.max_inputs().is_none_or(|max| found <= max);
+                    (expected, inputs_ok, gate_type.output_count().unwrap_or(1))
+                }
+            };
+
+            if !inputs_ok {
+                errors.push(SemanticError::BadArity {
+                    gate: component.identifier.clone(),
+                    expected: expected_inputs,
+                    found,
+                    location: component.location,
+                });
+            }
+            if component.outputs.len() != expected_outputs {
+                errors.push(SemanticError::BadOutputCount {
+                    gate: component.identifier.clone(),
+                    expected: expected_outputs,
+                    found: component.outputs.len(),
+                    location: component.location,
+                });
+            }
+        }
+    }
+}
+
+/// Returns the components of a scope in source order whose outputs do not lead to any declared output
+/// Works backwards from the outputs, marking the drivers of each live signal and then the signals those drivers read
+fn unreachable_components<'a>(scope: &Scope<'a>) -> Vec<&'a Component> {
+    let mut drivers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, component) in scope.components.iter().enumerate() {
+        for output in &component.outputs {
+            drivers.entry(output).or_default().push(index);
+        }
+    }
+    let targets: HashMap<&str, &str> = scope.aliases.iter()
+        .map(|alias| (alias.name.as_str(), alias.target.as_str()))
+        .collect();
+
+    let mut live = vec![false; scope.components.len()];
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut pending: Vec<&str> = scope.outputs.iter().map(String::as_str).collect();
+    while let Some(signal) = pending.pop() {
+        if !seen.insert(signal) {
+            continue;
+        }
+        if let Some(&target) = targets.get(signal) {
+            pending.push(target);
+        }
+        for &index in drivers.get(signal).into_iter().flatten() {
+            if !live[index] {
+                live[index] = true;
+                pending.extend(scope.components[index].inputs.iter().filter_map(Signal::wire));
+            }
+        }
+    }
+
+    scope.components.iter().zip(live).filter(|(_, live)| !live).map(|(component, _)| component).collect()
+}
+
+/// Flags names declared in both the INPUTS and the OUTPUTS section of a scope
+fn check_port_collisions(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    for output in scope.outputs.iter().filter(|output| scope.inputs.contains(output)) {
+        errors.push(SemanticError::InputOutputCollision {
+            name: output.clone(),
+            subcircuit: scope.name.map(str::to_string),
+        });
+    }
+}
+
+/// Returns the signals of a scope that have a source of their own: its inputs, its constants and component outputs
+fn sources<'a>(scope: &Scope<'a>) -> HashSet<&'a str> {
+    scope.inputs.iter()
+        .chain(scope.components.iter().flat_map(|c| c.outputs.iter()))
+        .map(String::as_str)
+        .chain(scope.constants.iter().copied())
+        .collect()
+}
+
+/// Flags aliases whose name is already taken by a signal or an earlier alias, aliases that refer to a signal
+/// that does not exist, and chains of aliases that lead back to where they started
+/// A cycle is reported once, at the alias of the cycle that is declared first
+fn check_aliases(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    let sources = sources(scope);
+    let mut targets: HashMap<&str, &str> = HashMap::new();
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (position, alias) in scope.aliases.iter().enumerate() {
+        if sources.contains(alias.name.as_str()) || targets.contains_key(alias.name.as_str()) {
+            errors.push(SemanticError::AliasCollision { name: alias.name.clone(), location: alias.location });
+            continue;
+        }
+        targets.insert(&alias.name, &alias.target);
+        positions.insert(&alias.name, position);
+    }
+
+    for (position, alias) in scope.aliases.iter().enumerate() {
+        if positions.get(alias.name.as_str()) != Some(&position) {
+            continue;
+        }
+        if !sources.contains(alias.target.as_str()) && !targets.contains_key(alias.target.as_str()) {
+            errors.push(SemanticError::UndefinedAliasTarget {
+                name: alias.name.clone(),
+                target: alias.target.clone(),
+                location: alias.location,
+            });
+            continue;
+        }
+
+        let mut chain = vec![alias.name.as_str()];
+        let mut current = alias.target.as_str();
+        while let Some(&next) = targets.get(current) {
+            if current == alias.name {
+                if chain.iter().all(|name| positions[name] >= position) {
+                    chain.push(current);
+                    errors.push(SemanticError::AliasCycle {
+                        chain: chain.into_iter().map(str::to_string).collect(),
+                        location: alias.location,
+                    });
+                }
+                break;
+            }
+            if chain.contains(&current) {
+                break;
+            }
+            chain.push(current);
+            current = next;
+        }
+    }
+}
+
+/// Flags component inputs that are neither a scope input, a constant, an alias nor driven by a component
+fn check_undefined_signals(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    let mut driven = sources(scope);
+    driven.extend(scope.aliases.iter().map(|alias| alias.name.as_str()));
+
+    for component in scope.components {
+        for wire in component.inputs.iter().filter_map(Signal::wire) {
+            if !driven.contains(wire) {
+                errors.push(SemanticError::UndefinedSignal {
+                    signal: wire.to_string(),
+                    component: component.identifier.clone(),
+                    location: component.location,
+                });
+            }
+        }
+    }
+}
+
+/// Flags signals driven by more than one component output, and inputs that a component drives
+/// A signal may have several drivers when all of them are tri-state buffers
+fn check_drivers(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    let mut drivers: HashMap<&str, Vec<Location>> = HashMap::new();
+    let mut shared: HashMap<&str, bool> = HashMap::new(); // Signal -> whether every driver is a tri-state buffer
+    let mut order: Vec<&str> = vec![];
+    for component in scope.components {
+        for output in &component.outputs {
+            if scope.inputs.contains(output) {
+                errors.push(SemanticError::DrivenInput { signal: output.clone(), location: component.location });
+                continue;
+            }
+            if scope.constants.contains(&output.as_str()) {
+                errors.push(SemanticError::DrivenConstant { signal: output.clone(), location: component.location });
+                continue;
+            }
+            let locations = drivers.entry(output).or_default();
+            if locations.is_empty() {
+                order.push(output);
+            }
+            locations.push(component.location);
+            *shared.entry(output).or_insert(true) &= matches!(component.gate_type, GateType::Tri);
+        }
+    }
+
+    for signal in order {
+        if drivers[signal].len() > 1 && !shared[signal] {
+            errors.push(SemanticError::MultipleDrivers {
+                signal: signal.to_string(),
+                locations: drivers.remove(signal).unwrap_or_default(),
+            });
+        }
+    }
+}
+
+/// Flags declared outputs that no component drives and that are neither constants nor aliases
+fn check_undriven_outputs(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    let undriven = graph::undriven_outputs(scope.inputs, scope.outputs, scope.components).into_iter()
+        .filter(|output| !scope.constants.contains(&output.as_str()))
+        .filter(|output| !scope.aliases.iter().any(|alias| &alias.name == *output));
+    for output in undriven {
+        errors.push(SemanticError::UndrivenOutput {
+            signal: output.clone(),
+            subcircuit: scope.name.map(str::to_string),
+            // Programs put together by hand may not record where their outputs were declared
+            location: scope.output_locations.get(output).copied().unwrap_or(BUILT_LOCATION),
+        });
+    }
+}
+
+/// Flags subcircuit inputs that no component reads, directly or through an alias
+/// A subcircuit's ports are its contract with every instance, so unlike an unread input of the main circuit
+/// this is an error. Outputs that nothing drives are already reported as UndrivenOutput
+fn check_unconnected_ports(scope: &Scope, errors: &mut Vec<SemanticError>) {
+    let Some(subcircuit) = scope.name else {
+        return;
+    };
+    let read: HashSet<&str> = scope.components.iter()
+        .flat_map(|component| component.inputs.iter().filter_map(Signal::wire))
+        .chain(scope.aliases.iter().map(|alias| alias.target.as_str()))
+        .collect();
+    for input in scope.inputs.iter().filter(|input| !read.contains(input.as_str())) {
+        errors.push(SemanticError::UnconnectedPort { subcircuit: subcircuit.to_string(), port: input.clone() });
+    }
+}
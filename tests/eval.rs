@@ -0,0 +1,87 @@
+use osd::lang::Program;
+use osd::sim::Simulator;
+
+/// Parses and validates source code that is expected to be correct
+fn parse(source: &str) -> Program {
+    Program::parse_validated(source).expect("source should be valid")
+}
+
+#[test]
+fn csv_of_a_xor_gate_lists_every_combination() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nXOR x IN(a, b) OUT(y)\n");
+    assert_eq!(program.truth_table().unwrap().to_csv(), "a,b,y\n0,0,0\n0,1,1\n1,0,1\n1,1,0\n");
+}
+
+#[test]
+fn refactored_xor_is_equivalent_to_the_gate() {
+    let gate = parse("INPUTS a, b\nOUTPUTS y\nXOR x IN(a, b) OUT(y)\n");
+    let nands = parse("INPUTS b, a\nOUTPUTS y\nNAND n1 IN(a, b) OUT(t)\nNAND n2 IN(a, t) OUT(u)\nNAND n3 IN(b, t) OUT(v)\nNAND n4 IN(u, v) OUT(y)\n");
+    assert!(gate.equivalent(&nands).unwrap());
+    assert!(nands.equivalent(&gate).unwrap());
+}
+
+#[test]
+fn circuits_differing_in_one_row_or_in_their_ports_are_not_equivalent() {
+    let or = parse("INPUTS a, b\nOUTPUTS y\nOR o IN(a, b) OUT(y)\n");
+    let xor = parse("INPUTS a, b\nOUTPUTS y\nXOR x IN(a, b) OUT(y)\n");
+    assert!(!or.equivalent(&xor).unwrap());
+    let renamed = parse("INPUTS a, c\nOUTPUTS y\nOR o IN(a, c) OUT(y)\n");
+    assert!(!or.equivalent(&renamed).unwrap());
+}
+
+#[test]
+fn samples_with_the_same_seed_are_identical() {
+    let program = parse("INPUTS a[24]\nOUTPUTS y\nXOR x IN(a[0:23]) OUT(y)\n");
+    let simulator = Simulator::new(&program);
+    let first = simulator.sample(32, 7).unwrap();
+    assert_eq!(first.len(), 32);
+    assert_eq!(simulator.sample(32, 7).unwrap(), first);
+    assert_ne!(simulator.sample(32, 8).unwrap(), first);
+    for (inputs, outputs) in &first {
+        let high = inputs.values().filter(|&&value| value).count();
+        assert_eq!(outputs["y"], high % 2 == 1);
+    }
+}
+
+#[test]
+fn truth_table_and_csv_columns_follow_declaration_order() {
+    let program = parse("INPUTS c, a, b\nOUTPUTS z, y\nAND g IN(a, b) OUT(y)\nOR h IN(c, a) OUT(z)\n");
+    assert_eq!((program.input_index("c"), program.input_index("a"), program.input_index("b")), (Some(0), Some(1), Some(2)));
+    assert_eq!((program.output_index("z"), program.output_index("y")), (Some(0), Some(1)));
+    assert_eq!((program.input_index("y"), program.output_index("a")), (None, None));
+
+    let table = program.truth_table().unwrap();
+    assert_eq!(table.inputs, program.inputs);
+    assert_eq!(table.outputs, program.outputs);
+    let csv = table.to_csv();
+    assert_eq!(csv.lines().next(), Some("c,a,b,z,y"));
+    // c = 0, a = 1, b = 1 is the fourth combination, and drives z = 1 and y = 1
+    let row = &table.rows[3];
+    assert_eq!(row.inputs, [false, true, true]);
+    assert!(row.outputs[program.output_index("z").unwrap()]);
+    assert!(row.outputs[program.output_index("y").unwrap()]);
+    assert_eq!(csv.lines().nth(4), Some("0,1,1,1,1"));
+}
+
+#[test]
+fn streamed_rows_match_the_eager_table() {
+    let program = parse(include_str!("../examples/full_adder.osd"));
+    let eager: Vec<(Vec<bool>, Vec<bool>)> = program.truth_table().unwrap().rows.into_iter()
+        .map(|row| (row.inputs, row.outputs))
+        .collect();
+    let streamed: Vec<(Vec<bool>, Vec<bool>)> = program.truth_table_iter()
+        .map(|row| row.map(|row| (row.inputs, row.outputs)))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(eager.len(), 8);
+    assert_eq!(streamed, eager);
+}
+
+#[test]
+fn streamed_rows_are_produced_on_demand_past_the_eager_limit() {
+    let program = parse("INPUTS a[21]\nOUTPUTS y\nAND g IN(a[0:20]) OUT(y)\n");
+    assert!(program.truth_table().is_err());
+    // The last input changes fastest, so the second row is the first one in which it is high
+    let position = program.truth_table_iter().position(|row| row.unwrap().inputs[20]);
+    assert_eq!(position, Some(1));
+}
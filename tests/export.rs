@@ -0,0 +1,94 @@
+use osd::lang::Program;
+use osd::sim::Simulator;
+
+/// A flip-flop whose output is fed back inverted, so it toggles on every rising clock edge
+const TOGGLE: &str = "INPUTS clk\nOUTPUTS q\nDFF f IN(d, clk) OUT(q)\nNOT n IN(q) OUT(d)\n";
+
+/// Parses source code that is expected to be a valid program
+fn parse(source: &str) -> Program {
+    let program: Program = source.parse().expect("source should parse");
+    program.validate().expect("program should be valid");
+    program
+}
+
+/// Returns the times at which the signal with the given VCD identifier code changes, with its new value
+fn changes(vcd: &str, code: char) -> Vec<(u64, char)> {
+    let mut time = 0;
+    let mut changes = vec![];
+    for line in vcd.lines() {
+        if let Some(stamp) = line.strip_prefix('#') {
+            time = stamp.parse().unwrap();
+        } else if line.len() == 2 && line.ends_with(code) {
+            changes.push((time, line.chars().next().unwrap()));
+        }
+    }
+    changes
+}
+
+#[test]
+fn vcd_of_a_toggling_flip_flop_alternates_on_rising_edges() {
+    let program = parse(TOGGLE);
+    let vcd = Simulator::new(&program).to_vcd(3).unwrap();
+    assert!(vcd.contains("$var wire 1 ! clk $end\n$var wire 1 \" d $end\n$var wire 1 # q $end\n"));
+    assert_eq!(changes(&vcd, '!'), [(0, '0'), (1, '1'), (2, '0'), (3, '1'), (4, '0'), (5, '1'), (6, '0')]);
+    assert_eq!(changes(&vcd, '#'), [(0, '0'), (1, '1'), (3, '0'), (5, '1')]);
+}
+
+#[test]
+fn vcd_leaves_the_simulator_untouched() {
+    let program = parse(TOGGLE);
+    let mut simulator = Simulator::new(&program);
+    simulator.set_input("clk", false).unwrap();
+    simulator.step().unwrap();
+    let first = simulator.to_vcd(2).unwrap();
+    assert_eq!(simulator.get("q"), Some(false));
+    assert_eq!(simulator.to_vcd(2).unwrap(), first);
+}
+
+#[test]
+fn verilog_of_a_xor_adder_declares_ports_wires_and_assigns() {
+    let program = parse("INPUTS a, b, c\nOUTPUTS s, co\nXOR x1 IN(a, b) OUT(t)\nXOR x2 IN(t, c) OUT(s)\nAND a1 IN(t, c) OUT(u)\nAND a2 IN(a, b) OUT(v)\nOR o1 IN(u, v) OUT(co)\n");
+    let verilog = program.to_verilog().unwrap();
+    let lines: Vec<&str> = verilog.lines().map(str::trim).collect();
+    assert_eq!(lines[..4], ["module top(a, b, c, s, co);", "input a, b, c;", "output s, co;", "wire t, u, v;"]);
+    assert!(lines.contains(&"assign t = a ^ b;"));
+    assert!(lines.contains(&"assign s = t ^ c;"));
+    assert!(lines.contains(&"assign co = u | v;"));
+    assert_eq!(lines.last(), Some(&"endmodule"));
+}
+
+#[test]
+fn verilog_puts_subcircuits_in_their_own_modules() {
+    let program = parse("SUBCIRCUIT inv2\nINPUTS a\nOUTPUTS y\nNOT n IN(a) OUT(y)\nEND\nSUBCIRCUIT half\nINPUTS a, b\nOUTPUTS s\nXOR x IN(a, b) OUT(s)\nEND\n\
+        INPUTS p, q\nOUTPUTS r\nhalf IN(p, q) OUT(t)\ninv2 IN(t) OUT(r)\n");
+    let verilog = program.to_verilog().unwrap();
+    let modules: Vec<&str> = verilog.lines().filter(|line| line.starts_with("module ")).collect();
+    assert_eq!(modules, ["module half(a, b, s);", "module inv2(a, y);", "module top(p, q, r);"]);
+    assert!(verilog.contains("half half_0 (.a(p), .b(q), .s(t));"));
+    assert!(verilog.contains("inv2 inv2_0 (.a(t), .y(r));"));
+}
+
+#[test]
+fn mermaid_of_an_and_gate_lists_nodes_then_edges() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g1 IN(a, b) OUT(y)\n");
+    let lines = [
+        "flowchart LR",
+        "    input_a((\"a\"))",
+        "    input_b((\"b\"))",
+        "    output_y(((\"y\")))",
+        "    gate_g1[\"AND<br/>g1\"]",
+        "    input_a -->|\"a\"| gate_g1",
+        "    input_b -->|\"b\"| gate_g1",
+        "    gate_g1 --> output_y",
+    ];
+    assert_eq!(program.to_mermaid(), lines.join("\n") + "\n");
+}
+
+#[test]
+fn mermaid_node_ids_escape_bus_brackets() {
+    let program = parse("INPUTS a[2]\nOUTPUTS y\nAND g1 IN(a[0], a[1]) OUT(y)\n");
+    let mermaid = program.to_mermaid();
+    assert!(mermaid.contains("    input_a_5b_0_5d_((\"a[0]\"))\n"));
+    assert!(mermaid.contains("    input_a_5b_1_5d_ -->|\"a[1]\"| gate_g1\n"));
+    assert_eq!(program.to_mermaid(), mermaid);
+}
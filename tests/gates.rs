@@ -0,0 +1,81 @@
+use osd::lang::GateType;
+use osd::logic::Logic;
+use osd::sim::{eval_gate, eval_gate_logic};
+
+/// Returns every combination of the given number of inputs, counting up from all false
+fn combinations(count: usize) -> impl Iterator<Item = Vec<bool>> {
+    (0..1u32 << count).map(move |value| (0..count).map(|i| value >> i & 1 == 1).collect())
+}
+
+/// Checks a gate against the expected output for every combination of two to four inputs
+/// The expected output is computed from the number of high inputs and the total number of inputs
+fn check_reduction(gate: GateType, expected: fn(usize, usize) -> bool) {
+    for count in 2..=4 {
+        for inputs in combinations(count) {
+            let high = inputs.iter().filter(|&&input| input).count();
+            assert_eq!(eval_gate(&gate, &inputs), [expected(high, count)], "{:?} of {:?}", gate, inputs);
+            let logic: Vec<Logic> = inputs.iter().map(|&input| Logic::from(input)).collect();
+            assert_eq!(eval_gate_logic(&gate, &logic), [Logic::from(expected(high, count))], "{:?} of {:?}", gate, inputs);
+        }
+    }
+}
+
+#[test]
+fn and_is_high_when_every_input_is() {
+    check_reduction(GateType::And, |high, count| high == count);
+}
+
+#[test]
+fn or_is_high_when_any_input_is() {
+    check_reduction(GateType::Or, |high, _| high > 0);
+}
+
+#[test]
+fn nand_is_the_inverse_of_and() {
+    check_reduction(GateType::Nand, |high, count| high != count);
+}
+
+#[test]
+fn nor_is_the_inverse_of_or() {
+    check_reduction(GateType::Nor, |high, _| high == 0);
+}
+
+#[test]
+fn xor_is_odd_parity() {
+    check_reduction(GateType::Xor, |high, _| high % 2 == 1);
+}
+
+#[test]
+fn xnor_is_even_parity() {
+    check_reduction(GateType::Xnor, |high, _| high % 2 == 0);
+}
+
+#[test]
+fn not_and_buf_take_one_input() {
+    assert_eq!(eval_gate(&GateType::Not, &[false]), [true]);
+    assert_eq!(eval_gate(&GateType::Not, &[true]), [false]);
+    assert_eq!(eval_gate(&GateType::Buf, &[false]), [false]);
+    assert_eq!(eval_gate(&GateType::Buf, &[true]), [true]);
+}
+
+#[test]
+#[should_panic(expected = "NOT takes exactly one input, got 2")]
+fn not_rejects_two_inputs() {
+    eval_gate(&GateType::Not, &[true, false]);
+}
+
+#[test]
+#[should_panic(expected = "BUF takes exactly one input, got 0")]
+fn buf_rejects_no_inputs() {
+    eval_gate(&GateType::Buf, &[]);
+}
+
+#[test]
+fn adders_drive_their_sum_and_carry() {
+    for (gate, count) in [(GateType::HalfAdder, 2), (GateType::FullAdder, 3)] {
+        for inputs in combinations(count) {
+            let high = inputs.iter().filter(|&&input| input).count();
+            assert_eq!(eval_gate(&gate, &inputs), [high % 2 == 1, high >= 2], "{:?} of {:?}", gate, inputs);
+        }
+    }
+}
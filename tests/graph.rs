@@ -0,0 +1,67 @@
+use osd::lang::{Component, Program};
+
+/// Parses and validates source code that is expected to be correct
+fn parse(source: &str) -> Program {
+    Program::parse_validated(source).expect("source should be valid")
+}
+
+#[test]
+fn signals_of_the_main_circuit_and_each_subcircuit() {
+    let program = parse("SUBCIRCUIT half\nINPUTS a, b\nOUTPUTS s\nXOR x IN(a, b) OUT(t)\nBUF u IN(t) OUT(s)\nEND\n\
+        INPUTS p, q\nOUTPUTS r, z\nCONST k = 1\nALIAS pq = p\nhalf IN(pq, q) OUT(m)\nAND g IN(m, k, 0) OUT(r)\nOR h IN(m, q) OUT(z)\n");
+    let main: Vec<String> = program.signals().into_iter().collect();
+    assert_eq!(main, ["k", "m", "p", "pq", "q", "r", "z"]);
+    let half: Vec<String> = program.subcircuits["half"].signals().into_iter().collect();
+    assert_eq!(half, ["a", "b", "s", "t"]);
+}
+
+#[test]
+fn stats_of_a_ripple_carry_adder() {
+    let program = parse(include_str!("../examples/ripple_carry_adder.osd"));
+    let stats = program.stats().unwrap();
+    assert_eq!(stats.gates, 20);
+    let counts: Vec<(&str, usize)> = stats.gate_counts.iter().map(|(gate, &count)| (gate.as_str(), count)).collect();
+    assert_eq!(counts, [("AND", 8), ("OR", 4), ("XOR", 8)]);
+    assert_eq!(stats.subcircuit_instances, 4);
+    assert_eq!(stats.signals, 17);
+    // The carry passes through an AND and an OR in each adder, after the XOR of the first one
+    assert_eq!(stats.depth, 9);
+}
+
+#[test]
+fn fanout_and_drivers_list_each_component_once() {
+    let program = parse("INPUTS a, b\nOUTPUTS y, z\nAND g1 IN(a, a) OUT(t)\nOR g2 IN(t, b) OUT(y)\nXOR g3 IN(a, t, 1) OUT(z)\n");
+    let names = |components: Vec<&Component>| -> Vec<String> {
+        components.iter().map(|component| component.identifier.clone()).collect()
+    };
+    // g1 reads a twice but is only listed once
+    assert_eq!(names(program.fanout("a")), ["g1", "g3"]);
+    assert_eq!(names(program.fanout("t")), ["g2", "g3"]);
+    assert!(program.fanout("y").is_empty());
+    assert_eq!(names(program.drivers("t")), ["g1"]);
+    assert!(program.drivers("a").is_empty());
+    assert!(program.drivers("missing").is_empty());
+
+    let fanout = program.fanout_map();
+    let drivers = program.drivers_map();
+    for signal in program.signals() {
+        assert_eq!(fanout.get(signal.as_str()).cloned().unwrap_or_default(), program.fanout(&signal));
+        assert_eq!(drivers.get(signal.as_str()).cloned().unwrap_or_default(), program.drivers(&signal));
+    }
+}
+
+#[test]
+fn drivers_of_a_signal_with_several_sources() {
+    let program: Program = "INPUTS a, b\nOUTPUTS y\nBUF g1 IN(a) OUT(y)\nBUF g2 IN(b) OUT(y)\n".parse().unwrap();
+    let drivers: Vec<&str> = program.drivers("y").iter().map(|component| component.identifier.as_str()).collect();
+    assert_eq!(drivers, ["g1", "g2"]);
+}
+
+#[test]
+fn dangling_signals_include_unread_constants() {
+    let program: Program = "INPUTS a, b\nOUTPUTS y\nCONST k = 1\nCONST c = 0\nAND g1 IN(a, c) OUT(y)\nOR g2 IN(a, w) OUT(z)\n"
+        .parse().unwrap();
+    let dangling = program.dangling_signals();
+    assert_eq!(dangling.undriven, ["w"]);
+    assert_eq!(dangling.unused, ["b", "k", "z"]);
+}
@@ -0,0 +1,51 @@
+use osd::lang::{Lexer, LexerConfig, TokenKind};
+
+/// Tokenizes source code with error recovery, returning the token kinds and the number of errors
+fn recover(source: &str) -> (Vec<TokenKind>, usize) {
+    let (tokens, errors) = Lexer::new(source.to_string()).tokenize_recovering();
+    (tokens.into_iter().map(|token| token.kind).collect(), errors.len())
+}
+
+#[test]
+fn recovery_keeps_punctuation_after_a_stray_character() {
+    let (tokens, errors) = recover("OUT(y)$;");
+    assert_eq!(errors, 1);
+    assert_eq!(tokens[tokens.len() - 2], TokenKind::Semicolon);
+
+    let (tokens, errors) = recover("AND$!b");
+    assert_eq!(errors, 1);
+    assert_eq!(tokens, vec![TokenKind::And, TokenKind::Bang, TokenKind::Identifier("b".to_string()), TokenKind::EOF]);
+
+    let (tokens, _) = recover("y $= a");
+    assert!(tokens.contains(&TokenKind::Equals));
+}
+
+#[test]
+fn recovery_reports_a_run_of_stray_characters_once() {
+    let (tokens, errors) = recover("a $$$ b");
+    assert_eq!(errors, 1);
+    assert_eq!(tokens.len(), 3);
+}
+
+#[test]
+fn multibyte_characters_advance_one_column_each() {
+    let tokens = Lexer::new("# é ü\nINPUTS a # ß\nb".to_string()).tokenize().unwrap();
+    let located: Vec<(TokenKind, usize, usize)> = tokens.into_iter()
+        .filter(|token| matches!(token.kind, TokenKind::Identifier(_)))
+        .map(|token| (token.kind, token.span.start.line, token.span.start.column))
+        .collect();
+    assert_eq!(located, vec![(TokenKind::Identifier("a".to_string()), 2, 8), (TokenKind::Identifier("b".to_string()), 3, 1)]);
+}
+
+#[test]
+fn unicode_identifiers_lex_whole_when_the_config_allows_them() {
+    let config = LexerConfig {
+        identifier_start: char::is_alphabetic,
+        identifier_continue: |c| c.is_alphanumeric() || c == '_',
+        ..LexerConfig::default()
+    };
+    let tokens = Lexer::with_config("signalé, 信号_2".to_string(), config).tokenize().unwrap();
+    assert_eq!(tokens[0].kind, TokenKind::Identifier("signalé".to_string()));
+    assert_eq!((tokens[0].span.end.column, tokens[2].span.start.column), (8, 10));
+    assert_eq!(tokens[2].kind, TokenKind::Identifier("信号_2".to_string()));
+}
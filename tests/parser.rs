@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::error::Error;
+use osd::lang::{CompileError, GateType, Lexer, LexerError, ParseError, Parser, Program, Subcircuit, TokenKind};
+use osd::validate::SemanticError;
+
+/// Lexes and parses source code into a program without validating it
+fn parse(source: &str) -> Result<Program, ParseError> {
+    let tokens = Lexer::new(source.to_string()).tokenize().expect("source should lex");
+    Parser::new(tokens).parse_program()
+}
+
+#[test]
+fn port_sections_declare_names_and_buses() {
+    let program = parse("INPUTS a, b[2]\nOUTPUTS y[2], z\nAND g IN(a, b[0]) OUT(z)\nBUF h IN(b[1]) OUT(y[0])\nBUF k IN(a) OUT(y[1])\n").unwrap();
+    assert_eq!(program.inputs, ["a", "b[0]", "b[1]"]);
+    assert_eq!(program.outputs, ["y[0]", "y[1]", "z"]);
+}
+
+#[test]
+fn blank_lines_may_precede_every_section() {
+    let program = parse("\n\nINPUTS a\n\n\nOUTPUTS y\n\nBUF b IN(a) OUT(y)\n").unwrap();
+    assert_eq!(program.outputs, ["y"]);
+}
+
+#[test]
+fn port_sections_report_keywords_in_their_own_context() {
+    let err = parse("INPUTS a\nOUTPUTS y, end\nBUF b IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::KeywordAsIdentifier { keyword: "END", .. }));
+    let err = parse("INPUTS a\nOUTPUTS y )\nBUF b IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::InvalidToken { context: "OUTPUTS section", .. }));
+}
+
+#[test]
+fn constants_cannot_reuse_port_names() {
+    for (source, reused) in [("INPUTS a\nOUTPUTS y\nCONST y = 1\n", "y"), ("INPUTS a\nOUTPUTS y\nCONST a = 0\n", "a"), ("INPUTS a\nOUTPUTS y\nCONST k = 0\nCONST k = 1\n", "k")] {
+        let err = parse(source).unwrap_err();
+        assert!(matches!(err, ParseError::DuplicateConstant { ref name, .. } if name == reused), "{}", source);
+    }
+}
+
+#[test]
+fn duplicate_component_identifiers_are_reported_with_both_locations() {
+    let err = parse("INPUTS x\nOUTPUTS y\nNOT a IN(x) OUT(p)\nNOT a IN(p) OUT(q)\nNOT b IN(q) OUT(r)\nNOT b IN(r) OUT(y)\n").unwrap_err();
+    match err {
+        ParseError::DuplicateComponent { name, first_location, second_location } => {
+            assert_eq!(name, "a");
+            assert_eq!((first_location.line, second_location.line), (3, 4));
+        }
+        err => panic!("expected a duplicate component, found {:?}", err),
+    }
+
+    let err = parse("SUBCIRCUIT s\nINPUTS a\nOUTPUTS y\nNOT g IN(a) OUT(t)\nNOT g IN(t) OUT(y)\nEND\nINPUTS x\nOUTPUTS y\ns IN(x) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::DuplicateComponent { name, .. } if name == "g"));
+}
+
+#[test]
+fn component_identifiers_only_need_to_be_unique_within_their_scope() {
+    let program = parse("SUBCIRCUIT s\nINPUTS a\nOUTPUTS y\nNOT g IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS y, z\nNOT g IN(x) OUT(y)\ns IN(x) OUT(z)\ns IN(x) OUT(w)\n").unwrap();
+    let identifiers: Vec<&str> = program.components.iter().map(|component| component.identifier.as_str()).collect();
+    assert_eq!(identifiers, ["g", "s_0", "s_1"]);
+}
+
+#[test]
+fn empty_port_sections_are_errors() {
+    let err = parse("INPUTS\nOUTPUTS y\nBUF b IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::EmptySection { section: "INPUTS", location } if location.line == 1));
+    let err = parse("INPUTS a\nOUTPUTS\nBUF b IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::EmptySection { section: "OUTPUTS", location } if location.line == 2));
+}
+
+#[test]
+fn missing_inputs_section_is_named_in_the_error() {
+    let err = parse("AND g IN(a, b) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::MissingSection { section: "INPUTS", location, .. } if location.line == 1));
+    assert!(err.to_string().starts_with("Expected INPUTS section, found And"));
+}
+
+#[test]
+fn gates_without_an_out_clause_drive_their_identifier() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g1 IN(a, b)\nNOT g2 IN(g1) OUT(n)\nOR y IN(g1, n)\n").unwrap();
+    let driven: Vec<String> = program.components.iter()
+        .map(|component| format!("{} -> {}", component.identifier, component.outputs.join(", ")))
+        .collect();
+    assert_eq!(driven, ["g1 -> g1", "g2 -> n", "y -> y"]);
+    assert!(program.validate().is_ok());
+}
+
+#[test]
+fn subcircuit_instances_and_anonymous_gates_need_an_out_clause() {
+    assert!(parse("SUBCIRCUIT s\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS o\ns IN(x)\n").is_err());
+    assert!(parse("INPUTS a, b\nOUTPUTS y\nAND IN(a, b)\n").is_err());
+}
+
+#[test]
+fn keywords_in_name_positions_are_reported_as_reserved() {
+    let err = parse("SUBCIRCUIT END\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS o\nBUF b IN(x) OUT(o)\n").unwrap_err();
+    assert!(matches!(err, ParseError::KeywordAsIdentifier { keyword: "END", location } if (location.line, location.column) == (1, 12)));
+    assert!(err.to_string().starts_with("'END' is a reserved keyword"));
+
+    let err = parse("INPUTS a\nOUTPUTS y\nBUF out IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::KeywordAsIdentifier { keyword: "OUT", .. }));
+    let err = parse("INPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(end)\n").unwrap_err();
+    assert!(matches!(err, ParseError::KeywordAsIdentifier { keyword: "END", .. }));
+    let err = parse("INPUTS in\nOUTPUTS y\nBUF b IN(a) OUT(y)\n").unwrap_err();
+    assert!(matches!(err, ParseError::KeywordAsIdentifier { keyword: "IN", .. }));
+}
+
+#[test]
+fn semicolons_and_newlines_both_end_statements() {
+    let netlist = |program: Program| -> Vec<String> {
+        program.components.iter()
+            .map(|c| format!("{:?} {} {:?} {:?}", c.gate_type, c.identifier, c.inputs, c.outputs))
+            .collect()
+    };
+    let lines = netlist(parse("INPUTS a, b, c\nOUTPUTS z\nAND g1 IN(a, b) OUT(y)\nOR g2 IN(y, c) OUT(z)\n").unwrap());
+    let one_line = netlist(parse("INPUTS a, b, c; OUTPUTS z; AND g1 IN(a, b) OUT(y); OR g2 IN(y, c) OUT(z)").unwrap());
+    let mixed = netlist(parse("INPUTS a, b, c\nOUTPUTS z;\nAND g1 IN(a, b) OUT(y); OR g2 IN(y, c) OUT(z);\n").unwrap());
+    assert_eq!(lines.len(), 2);
+    assert_eq!(one_line, lines);
+    assert_eq!(mixed, lines);
+}
+
+#[test]
+fn truncated_file_reports_where_it_ends() {
+    let cases = [
+        ("INPUTS a\nOUTPUTS y\nAND g IN(", 10),
+        ("INPUTS a\nOUTPUTS y\nAND g IN(a", 11),
+        ("INPUTS a\nOUTPUTS y\nAND g IN(a) OUT(y", 18),
+        ("INPUTS a\nOUTPUTS y\nAND", 4),
+    ];
+    for (source, column) in cases {
+        let err = parse(source).unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedEof { location, .. } if (location.line, location.column) == (3, column)), "{:?}", err);
+    }
+    let err = parse("INPUTS a\nOUTPUTS y\nAND g IN(").unwrap_err();
+    assert_eq!(err.to_string(), "Unexpected end of input, expected ParenClose at line 3, column 10");
+}
+
+#[test]
+fn signal_lists_accept_one_trailing_comma() {
+    let program = parse("INPUTS a, b,\nOUTPUTS y,\nAND g IN(a, b,) OUT(y,)\n").unwrap();
+    assert_eq!(program.inputs, ["a", "b"]);
+    assert_eq!(program.components[0].outputs, ["y"]);
+}
+
+#[test]
+fn doubled_and_leading_commas_are_errors() {
+    let cases = [
+        ("INPUTS a\nOUTPUTS y\nAND g IN(a,,b) OUT(y)\n", 12),
+        ("INPUTS a\nOUTPUTS y\nAND g IN(,a) OUT(y)\n", 10),
+        ("INPUTS a\nOUTPUTS y\nAND g IN(a) OUT(,y)\n", 17),
+        ("INPUTS a,, b\nOUTPUTS y\nAND g IN(a) OUT(y)\n", 10),
+    ];
+    for (source, column) in cases {
+        let err = parse(source).unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedComma { location } if location.column == column), "{}: {:?}", source, err);
+    }
+}
+
+#[test]
+fn anonymous_gates_are_numbered_around_user_names() {
+    let source = "INPUTS a, b\nOUTPUTS y, z\nAND IN(a, b) OUT(p)\nOR and_0 IN(a, b) OUT(q)\nAND IN(p, q) OUT(y)\nNOT IN(y) OUT(z)\n";
+    let program = parse(source).unwrap();
+    let identifiers: Vec<&str> = program.components.iter().map(|component| component.identifier.as_str()).collect();
+    assert_eq!(identifiers, ["and_1", "and_0", "and_2", "not_0"]);
+    assert!(program.validate().is_ok());
+
+    // Naming only depends on the source, so parsing it again gives the same names
+    assert_eq!(parse(source).unwrap().components, program.components);
+}
+
+/// A library of two subcircuits, one built from the other, with no main circuit
+const LIBRARY: &str = "SUBCIRCUIT half\nINPUTS a, b\nOUTPUTS s, c\nXOR x IN(a, b) OUT(s)\nAND g IN(a, b) OUT(c)\nEND\n\n\
+    SUBCIRCUIT full\nINPUTS a, b, cin\nOUTPUTS s, cout\nhalf IN(a, b) OUT(t, c1)\nhalf IN(t, cin) OUT(s, c2)\nOR o IN(c1, c2) OUT(cout)\nEND\n";
+
+/// Lexes and parses source code that contains only subcircuit definitions
+fn parse_library(source: &str) -> Result<HashMap<String, Subcircuit>, ParseError> {
+    let tokens = Lexer::new(source.to_string()).tokenize().expect("source should lex");
+    Parser::new(tokens).parse_library()
+}
+
+#[test]
+fn library_without_a_main_circuit_parses() {
+    let library = parse_library(LIBRARY).unwrap();
+    let mut names: Vec<&String> = library.keys().collect();
+    names.sort();
+    assert_eq!(names, ["full", "half"]);
+    assert_eq!(library["full"].inputs, ["a", "b", "cin"]);
+    assert_eq!(library["full"].components.len(), 3);
+}
+
+#[test]
+fn library_rejects_a_main_circuit() {
+    let err = parse_library(&format!("{}INPUTS x\nOUTPUTS y\nBUF b IN(x) OUT(y)\n", LIBRARY)).unwrap_err();
+    assert!(matches!(err, ParseError::Expected { ref one_of, .. } if one_of == &["SUBCIRCUIT", "end of input"]), "{:?}", err);
+}
+
+#[test]
+fn program_can_use_subcircuits_from_a_library() {
+    let tokens = Lexer::new("INPUTS x, y, z\nOUTPUTS s, c\nfull IN(x, y, z) OUT(s, c)\n".to_string()).tokenize().unwrap();
+    let mut parser = Parser::new(tokens);
+    parser.set_external_subcircuits(true);
+    let mut program = parser.parse_program().unwrap();
+    assert!(matches!(
+        program.validate().unwrap_err().as_slice(),
+        [SemanticError::UnknownSubcircuit { name, .. }] if name == "full"
+    ));
+
+    program.with_library(parse_library(LIBRARY).unwrap()).unwrap();
+    assert!(program.validate().is_ok());
+    let row = program.truth_table().unwrap().rows.into_iter().last().unwrap();
+    assert_eq!(row.outputs, [true, true]);
+}
+
+#[test]
+fn library_subcircuits_cannot_replace_the_program_ones() {
+    let mut program = parse("SUBCIRCUIT half\nINPUTS a, b\nOUTPUTS s\nXOR x IN(a, b) OUT(s)\nEND\nINPUTS x, y\nOUTPUTS s\nhalf IN(x, y) OUT(s)\n").unwrap();
+    let err = program.with_library(parse_library(LIBRARY).unwrap()).unwrap_err();
+    assert!(matches!(err, SemanticError::DuplicateSubcircuit { name, .. } if name == "half"));
+    assert_eq!(program.subcircuits.len(), 1);
+}
+
+#[test]
+fn content_after_the_component_list_is_an_error() {
+    let err = parse("INPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\n").unwrap_err();
+    assert!(matches!(err, ParseError::TrailingTokens { location } if (location.line, location.column) == (4, 1)), "{:?}", err);
+    let err = parse("INPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\n\n# Done\nEND\nBUF c IN(a) OUT(z)\n").unwrap_err();
+    assert!(matches!(err, ParseError::TrailingTokens { location } if location.line == 6), "{:?}", err);
+    assert!(parse("INPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\n\n# Done\n\n").is_ok());
+}
+
+#[test]
+fn inverted_connections_lower_to_not_gates() {
+    let inverted = parse("INPUTS a, b\nOUTPUTS y, z\nAND g1 IN(!a, b) OUT(y)\nOR g2 IN(a, b) OUT(!z)\n").unwrap();
+    let explicit = parse("INPUTS a, b\nOUTPUTS y, z\nNOT n IN(a) OUT(na)\nAND g1 IN(na, b) OUT(y)\nOR g2 IN(a, b) OUT(t)\nNOT m IN(t) OUT(z)\n").unwrap();
+    assert!(inverted.validate().is_ok());
+    let nots = inverted.components.iter().filter(|component| component.gate_type == GateType::Not).count();
+    assert_eq!(nots, 2);
+    assert!(inverted.components.iter().any(|component| component.identifier == "a_n_0"));
+    assert!(inverted.equivalent(&explicit).unwrap());
+
+    let rows: Vec<Vec<bool>> = inverted.truth_table().unwrap().rows.into_iter().map(|row| row.outputs).collect();
+    assert_eq!(rows, [[false, true], [true, false], [false, false], [false, false]]);
+}
+
+#[test]
+fn component_list_errors_list_every_accepted_token() {
+    let err = parse("INPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\n(\n").unwrap_err();
+    match &err {
+        ParseError::Expected { one_of, found, location } => {
+            assert_eq!(one_of[..3], ["AND", "OR", "NOT"]);
+            assert!(one_of.contains(&"subcircuit name") && one_of.contains(&"ALIAS"));
+            assert_eq!(one_of.last(), Some(&"end of input"));
+            assert_eq!(*found, TokenKind::ParenOpen);
+            assert_eq!((location.line, location.column), (4, 1));
+        }
+        err => panic!("expected a list of accepted tokens, found {:?}", err),
+    }
+    assert!(err.to_string().contains("AND, OR, NOT"), "{}", err);
+}
+
+#[test]
+fn subcircuit_component_list_accepts_end_instead_of_end_of_input() {
+    let err = parse("SUBCIRCUIT s\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nOUTPUTS z\n").unwrap_err();
+    assert!(matches!(err, ParseError::Expected { ref one_of, .. } if one_of.last() == Some(&"END")), "{:?}", err);
+}
+
+#[test]
+fn compile_errors_have_the_failed_stage_as_their_source() {
+    let lexing = Program::parse_validated("INPUTS a\nOUTPUTS y\nAND g IN(a, $) OUT(y)\n").unwrap_err();
+    let CompileError::Lexer(expected) = &lexing else { panic!("expected a lexer error, found {:?}", lexing) };
+    let source = lexing.source().and_then(|source| source.downcast_ref::<LexerError>());
+    assert_eq!(source.map(ToString::to_string), Some(expected.to_string()));
+
+    let parsing = Program::parse_validated("INPUTS a\nOUTPUTS y\nAND g IN(a OUT(y)\n").unwrap_err();
+    let CompileError::Parse(expected) = &parsing else { panic!("expected a parse error, found {:?}", parsing) };
+    let source = parsing.source().and_then(|source| source.downcast_ref::<ParseError>());
+    assert_eq!(source.map(ToString::to_string), Some(expected.to_string()));
+
+    let checking = Program::parse_validated("INPUTS a\nOUTPUTS y\nAND g IN(a, b) OUT(y)\n").unwrap_err();
+    assert!(matches!(checking, CompileError::Semantic(_)));
+    assert!(checking.source().is_none());
+}
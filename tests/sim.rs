@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use osd::lang::Program;
+use osd::logic::Logic;
+use osd::sim::{SimError, Simulator, MAX_SETTLE_TIME};
+
+/// Parses source code that is expected to be a valid program
+fn parse(source: &str) -> Program {
+    let program: Program = source.parse().expect("source should parse");
+    program.validate().expect("program should be valid");
+    program
+}
+
+/// Returns the input values numbered by the bits of the given value, the first input being the lowest bit
+fn vector(program: &Program, value: u64) -> HashMap<String, bool> {
+    program.inputs.iter().enumerate()
+        .map(|(i, input)| (input.clone(), value >> i & 1 == 1))
+        .collect()
+}
+
+/// Returns a datapath of the given number of 8-bit ripple-carry adders, each adding the same two inputs
+fn adder_datapath(copies: usize) -> String {
+    let mut source = String::from("SUBCIRCUIT fa\nINPUTS a, b, c\nOUTPUTS s, co\nXOR x1 IN(a, b) OUT(t)\nXOR x2 IN(t, c) OUT(s)\n\
+        AND a1 IN(t, c) OUT(u)\nAND a2 IN(a, b) OUT(w)\nOR o1 IN(u, w) OUT(co)\nEND\n");
+    source.push_str("SUBCIRCUIT add8\nINPUTS a[8], b[8], c\nOUTPUTS s[8], co\n");
+    for i in 0..8 {
+        let carry_in = if i == 0 { "c".to_string() } else { format!("k{}", i - 1) };
+        let carry_out = if i == 7 { "co".to_string() } else { format!("k{}", i) };
+        source.push_str(&format!("fa IN(a[{i}], b[{i}], {carry_in}) OUT(s[{i}], {carry_out})\n"));
+    }
+    source.push_str("END\nINPUTS x[8], y[8]\nOUTPUTS");
+    source.push_str(&(0..copies).map(|n| format!(" s{n}[8], c{n}")).collect::<Vec<_>>().join(","));
+    source.push('\n');
+    for n in 0..copies {
+        source.push_str(&format!("add8 IN(x[0:7], y[0:7], 0) OUT(s{n}[0:7], c{n})\n"));
+    }
+    source
+}
+
+#[test]
+fn subcircuit_cache_matches_uncached_evaluation() {
+    let program = parse(&adder_datapath(4));
+    let uncached = Simulator::new(&program);
+    let cached = Simulator::new(&program).with_subcircuit_cache();
+    for value in (0..1 << 16).step_by(251) {
+        let inputs = vector(&program, value);
+        assert_eq!(cached.evaluate(&inputs).unwrap(), uncached.evaluate(&inputs).unwrap());
+    }
+}
+
+#[test]
+fn subcircuit_cache_follows_injected_faults() {
+    let program = parse(&adder_datapath(2));
+    let mut cached = Simulator::new(&program).with_subcircuit_cache();
+    let mut faulty = Simulator::new(&program);
+    faulty.inject_fault("s0[0]", true).unwrap();
+    let inputs = vector(&program, 0);
+    let fault_free = cached.evaluate(&inputs).unwrap();
+    assert!(!fault_free["s0[0]"]);
+
+    cached.inject_fault("s0[0]", true).unwrap();
+    assert_eq!(cached.evaluate(&inputs).unwrap(), faulty.evaluate(&inputs).unwrap());
+    cached.clear_faults();
+    assert_eq!(cached.evaluate(&inputs).unwrap(), fault_free);
+}
+
+#[test]
+fn subcircuit_cache_is_used_by_evaluate_batch() {
+    let program = parse(&adder_datapath(3));
+    let vectors: Vec<HashMap<String, bool>> = (0..1 << 16).step_by(997).map(|value| vector(&program, value)).collect();
+    let cached = Simulator::new(&program).with_subcircuit_cache();
+    let expected = Simulator::new(&program).evaluate_batch(&vectors).unwrap();
+    assert_eq!(cached.evaluate_batch(&vectors).unwrap(), expected);
+}
+
+#[test]
+fn subcircuit_cache_only_saves_evaluations_within_one_call() {
+    let program = parse(&adder_datapath(4));
+    let inputs = vector(&program, 0);
+    let uncached = Simulator::new(&program);
+    uncached.evaluate(&inputs).unwrap();
+    // Four 8-bit adders, each made of eight full adders
+    assert_eq!(uncached.subcircuit_evaluations(), 36);
+
+    // With every input low, one adder and one full adder inside it answer all the others
+    let cached = Simulator::new(&program).with_subcircuit_cache();
+    cached.evaluate(&inputs).unwrap();
+    assert_eq!(cached.subcircuit_evaluations(), 2);
+    cached.evaluate(&inputs).unwrap();
+    assert_eq!(cached.subcircuit_evaluations(), 4);
+}
+
+/// Reproducible xorshift generator for the property tests
+struct Rng(u64);
+
+impl Rng {
+    /// Returns a number in 0..bound
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Returns a random acyclic program whose gates only read inputs, constants and the outputs of earlier gates
+/// Every gate output is declared as an output, and a small random subcircuit is instantiated a few times
+fn random_program(rng: &mut Rng) -> Program {
+    const GATES: [&str; 8] = ["AND", "OR", "NAND", "NOR", "XOR", "XNOR", "NOT", "BUF"];
+    let mut source = String::from("SUBCIRCUIT blk\nINPUTS a, b\nOUTPUTS y\n");
+    source.push_str(&format!("{} g1 IN(a, b) OUT(t)\n{} g2 IN(t, a) OUT(y)\nEND\n", GATES[rng.below(6)], GATES[rng.below(6)]));
+
+    let input_count = 2 + rng.below(5);
+    let mut signals: Vec<String> = (0..input_count).map(|i| format!("i{}", i)).chain(["0".into(), "1".into()]).collect();
+    let mut outputs = vec![];
+    let mut lines = vec![];
+    for n in 0..10 + rng.below(50) {
+        let output = format!("w{}", n);
+        let kind = rng.below(GATES.len() + 1);
+        let mut pick = || signals[rng.below(signals.len())].clone();
+        let line = match kind {
+            gate if gate == GATES.len() => format!("blk IN({}, {}) OUT({})", pick(), pick(), output),
+            gate if gate >= 6 => format!("{} g{} IN({}) OUT({})", GATES[gate], n, pick(), output),
+            gate => {
+                let inputs: Vec<String> = (0..2 + n % 3).map(|_| pick()).collect();
+                format!("{} g{} IN({}) OUT({})", GATES[gate], n, inputs.join(", "), output)
+            }
+        };
+        lines.push(line);
+        signals.push(output.clone());
+        outputs.push(output);
+    }
+    source.push_str(&format!("INPUTS {}\nOUTPUTS {}\n", signals[..input_count].join(", "), outputs.join(", ")));
+    source.push_str(&lines.join("\n"));
+    parse(&source)
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_evaluation_matches_serial_evaluation_on_random_programs() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..50 {
+        let program = random_program(&mut rng);
+        let parallel = Simulator::new(&program);
+        // The debug trace is only written by the serial path, so turning it on forces evaluation in order
+        let mut serial = Simulator::new(&program);
+        serial.set_debug_trace(true);
+        let vectors: Vec<HashMap<String, bool>> = (0..1 << program.inputs.len()).map(|value| vector(&program, value)).collect();
+        assert_eq!(parallel.evaluate_batch(&vectors).unwrap(), serial.evaluate_batch(&vectors).unwrap());
+    }
+}
+
+#[test]
+fn two_gate_loop_is_reported_before_evaluation() {
+    let program: Program = "INPUTS a\nOUTPUTS y\nAND g1 IN(a, q) OUT(p)\nNOT g2 IN(p) OUT(q)\nBUF g3 IN(q) OUT(y)\n".parse().unwrap();
+    let simulator = Simulator::new(&program);
+    // No input values are given, so the loop must be found before any gate is evaluated
+    match simulator.evaluate(&HashMap::new()) {
+        Err(SimError::CombinationalLoop { mut signals }) => {
+            signals.sort();
+            assert_eq!(signals, ["p", "q"]);
+        }
+        result => panic!("expected a combinational loop, found {:?}", result),
+    }
+    assert!(matches!(simulator.check_loops(), Err(SimError::CombinationalLoop { .. })));
+}
+
+#[test]
+fn loop_inside_a_subcircuit_is_reported() {
+    let program: Program = "SUBCIRCUIT ring\nINPUTS a\nOUTPUTS y\nNOR g1 IN(a, y) OUT(t)\nNOT g2 IN(t) OUT(y)\nEND\nINPUTS x\nOUTPUTS o\nring IN(x) OUT(o)\n".parse().unwrap();
+    let err = Simulator::new(&program).evaluate(&vector(&program, 0)).unwrap_err();
+    assert!(matches!(err, SimError::CombinationalLoop { signals } if signals.contains(&"t".to_string()) && signals.contains(&"y".to_string())));
+}
+
+#[test]
+fn ring_oscillator_does_not_converge() {
+    let program = parse("INPUTS en\nOUTPUTS z\nNAND g1 IN(en, z) OUT(x)\nNOT g2 IN(x) OUT(w)\nNOT g3 IN(w) OUT(z)\n");
+    let mut simulator = Simulator::new(&program).with_max_iterations(8);
+    simulator.set_input("en", false).unwrap();
+    simulator.step().unwrap();
+    assert_eq!(simulator.get("z"), Some(true));
+    simulator.set_input("en", true).unwrap();
+    assert!(matches!(simulator.step(), Err(SimError::DidNotConverge { after: 8 })));
+}
+
+#[test]
+fn cross_coupled_nor_latch_converges_and_holds() {
+    let program = parse("INPUTS s, r\nOUTPUTS q, qn\nNOR g1 IN(r, qn) OUT(q)\nNOR g2 IN(s, q) OUT(qn)\n");
+    let mut simulator = Simulator::new(&program).with_max_iterations(8);
+    simulator.set_input("s", true).unwrap();
+    simulator.set_input("r", false).unwrap();
+    simulator.step().unwrap();
+    assert_eq!((simulator.get("q"), simulator.get("qn")), (Some(true), Some(false)));
+    simulator.set_input("s", false).unwrap();
+    simulator.step().unwrap();
+    assert_eq!((simulator.get("q"), simulator.get("qn")), (Some(true), Some(false)));
+    simulator.set_input("r", true).unwrap();
+    simulator.step().unwrap();
+    assert_eq!((simulator.get("q"), simulator.get("qn")), (Some(false), Some(true)));
+}
+
+#[test]
+fn batch_evaluation_matches_evaluating_each_vector_on_random_programs() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for _ in 0..20 {
+        let program = random_program(&mut rng);
+        let simulator = Simulator::new(&program);
+        let vectors: Vec<HashMap<String, bool>> = (0..1 << program.inputs.len()).map(|value| vector(&program, value)).collect();
+        let one_by_one: Vec<HashMap<String, bool>> = vectors.iter().map(|inputs| simulator.evaluate(inputs).unwrap()).collect();
+        assert_eq!(simulator.evaluate_batch(&vectors).unwrap(), one_by_one);
+    }
+}
+
+#[test]
+fn batch_evaluation_stops_at_the_first_failing_vector() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g IN(a, b) OUT(y)\n");
+    let mut vectors = vec![vector(&program, 3), vector(&program, 1)];
+    vectors[1].remove("b");
+    let err = Simulator::new(&program).evaluate_batch(&vectors).unwrap_err();
+    assert!(matches!(err, SimError::MissingInput(name) if name == "b"));
+}
+
+/// Records every value a signal takes while the simulator runs
+fn record(simulator: &mut Simulator, signal: &str) -> Arc<Mutex<Vec<bool>>> {
+    let changes = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&changes);
+    simulator.watch(signal, Box::new(move |value| sink.lock().unwrap().push(value == Logic::High)));
+    changes
+}
+
+#[test]
+fn evaluation_order_runs_drivers_before_readers() {
+    let program = parse("SUBCIRCUIT neg\nINPUTS p\nOUTPUTS q\nNOT n IN(p) OUT(q)\nEND\nINPUTS a\nOUTPUTS y\n\
+        AND g3 IN(t2, a) OUT(y)\nneg IN(t1) OUT(t2)\nBUF g1 IN(a) OUT(t1)\n");
+    let simulator = Simulator::new(&program);
+    let order: Vec<&str> = simulator.evaluation_order().iter().map(|component| component.identifier.as_str()).collect();
+    let instance = program.components[1].identifier.as_str();
+    assert_eq!(order, ["g1", instance, "g3"]);
+
+    let looped: Program = "INPUTS a\nOUTPUTS y\nAND g IN(a, t) OUT(y)\nBUF b IN(y) OUT(t)\n".parse().unwrap();
+    assert!(Simulator::new(&looped).evaluation_order().is_empty());
+}
+
+#[test]
+fn watch_reports_each_change_of_incremental_simulation_once() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g IN(a, b) OUT(y)\n");
+    let mut simulator = Simulator::new(&program);
+    simulator.step().unwrap();
+    let a = record(&mut simulator, "a");
+    let y = record(&mut simulator, "y");
+    simulator.set_input("a", true).unwrap();
+    simulator.step().unwrap();
+    simulator.set_input("b", true).unwrap();
+    simulator.step().unwrap();
+    // Setting an input to the value it already has is not a change
+    simulator.set_input("b", true).unwrap();
+    simulator.step().unwrap();
+    simulator.set_input("a", false).unwrap();
+    simulator.step().unwrap();
+    assert_eq!(*a.lock().unwrap(), [true, false]);
+    assert_eq!(*y.lock().unwrap(), [true, false]);
+}
+
+#[test]
+fn watch_reports_each_change_of_a_flip_flop_once_per_tick() {
+    let program = parse("INPUTS clk\nOUTPUTS q\nDFF f IN(d, clk) OUT(q)\nNOT n IN(q) OUT(d)\n");
+    let mut simulator = Simulator::new(&program);
+    simulator.tick().unwrap();
+    let q = record(&mut simulator, "q");
+    let d = record(&mut simulator, "d");
+    for clock in [true, true, false, true] {
+        simulator.set_input("clk", clock).unwrap();
+        simulator.tick().unwrap();
+    }
+    // Only the two rising edges toggle the flip-flop
+    assert_eq!(*q.lock().unwrap(), [true, false]);
+    assert_eq!(*d.lock().unwrap(), [false, true]);
+}
+
+#[test]
+fn watch_reports_every_signal_once_per_evaluate() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nCONST k = 1\nAND g IN(a, b) OUT(t)\nXOR h IN(t, k) OUT(y)\n");
+    let mut simulator = Simulator::new(&program);
+    let watched: Vec<(&str, Arc<Mutex<Vec<bool>>>)> = ["a", "b", "k", "t", "y"].into_iter()
+        .map(|signal| (signal, record(&mut simulator, signal)))
+        .collect();
+    let inputs = HashMap::from([("a".to_string(), true), ("b".to_string(), false)]);
+    simulator.evaluate(&inputs).unwrap();
+    // Signals are reported again by the next call even though their values did not change
+    simulator.evaluate(&inputs).unwrap();
+    let expected = HashMap::from([("a", true), ("b", false), ("k", true), ("t", false), ("y", true)]);
+    for (signal, changes) in watched {
+        assert_eq!(*changes.lock().unwrap(), [expected[signal]; 2], "{}", signal);
+    }
+}
+
+#[test]
+fn settle_time_of_a_chain_is_the_sum_of_its_delays() {
+    let program = parse("INPUTS a\nOUTPUTS y\nBUF b1 IN(a) OUT(t1)\nBUF b2 IN(t1) OUT(t2)\nBUF b3 IN(t2) OUT(t3)\nBUF b4 IN(t3) OUT(y)\n");
+    let mut simulator = Simulator::new(&program);
+    simulator.set_input("a", false).unwrap();
+    simulator.run_until_stable().unwrap();
+    simulator.set_input("a", true).unwrap();
+    assert_eq!(simulator.run_until_stable().unwrap(), 4);
+    assert_eq!(simulator.get("y"), Some(true));
+
+    simulator.set_delay("b3", 5).unwrap();
+    simulator.set_input("a", false).unwrap();
+    assert_eq!(simulator.run_until_stable().unwrap(), 8);
+    assert!(matches!(simulator.set_delay("b9", 1), Err(SimError::UnknownComponent(name)) if name == "b9"));
+}
+
+#[test]
+fn slow_inverter_causes_a_glitch() {
+    // y = a AND NOT a is always low once settled, but the inverter lags behind a rising a
+    let program = parse("INPUTS a\nOUTPUTS y\nNOT n IN(a) OUT(na)\nAND g IN(a, na) OUT(y)\n");
+    let mut simulator = Simulator::new(&program);
+    simulator.set_input("a", false).unwrap();
+    simulator.run_until_stable().unwrap();
+    simulator.set_delay("n", 3).unwrap();
+    let changes = record(&mut simulator, "y");
+    simulator.set_input("a", true).unwrap();
+    assert_eq!(simulator.run_until_stable().unwrap(), 4);
+    assert_eq!(*changes.lock().unwrap(), [true, false]);
+    assert_eq!(simulator.get("y"), Some(false));
+}
+
+#[test]
+fn ring_oscillator_does_not_stabilize() {
+    let program = parse("INPUTS en\nOUTPUTS z\nNAND g1 IN(en, z) OUT(x)\nNOT g2 IN(x) OUT(w)\nNOT g3 IN(w) OUT(z)\n");
+    let mut simulator = Simulator::new(&program).with_max_iterations(100);
+    // Long delays make it run past MAX_SETTLE_TIME after a few oscillations
+    for gate in ["g1", "g2", "g3"] {
+        simulator.set_delay(gate, MAX_SETTLE_TIME / 10).unwrap();
+    }
+    simulator.set_input("en", true).unwrap();
+    assert!(matches!(simulator.run_until_stable(), Err(SimError::DidNotStabilize { limit: MAX_SETTLE_TIME })));
+}
+
+#[test]
+fn zero_delay_loop_does_not_stabilize() {
+    let program = parse("INPUTS a\nOUTPUTS y\nAND g IN(a, q) OUT(y)\nNOT n IN(y) OUT(q)\n");
+    let mut simulator = Simulator::new(&program).with_max_iterations(100);
+    simulator.set_delay("g", 0).unwrap();
+    simulator.set_delay("n", 0).unwrap();
+    simulator.set_input("a", true).unwrap();
+    assert!(matches!(simulator.run_until_stable(), Err(SimError::DidNotStabilize { .. })));
+}
+
+#[test]
+fn zero_delay_chain_settles_at_once() {
+    let program = parse("INPUTS a\nOUTPUTS y\nNOT n1 IN(a) OUT(t)\nNOT n2 IN(t) OUT(y)\n");
+    let mut simulator = Simulator::new(&program);
+    simulator.set_delay("n1", 0).unwrap();
+    simulator.set_delay("n2", 0).unwrap();
+    simulator.set_input("a", true).unwrap();
+    assert_eq!(simulator.run_until_stable().unwrap(), 0);
+    assert_eq!(simulator.get("y"), Some(true));
+}
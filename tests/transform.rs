@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use osd::lang::{GateType, Lexer, LexerConfig, Parser, Program, Signal};
+use osd::sim::SimError;
+
+/// Parses and validates source code that is expected to be correct
+fn parse(source: &str) -> Program {
+    Program::parse_validated(source).expect("source should be valid")
+}
+
+#[test]
+fn folding_replaces_gates_with_constant_inputs() {
+    let program = parse("INPUTS a, b\nOUTPUTS y, z\nAND g1 IN(a, 0) OUT(t)\nOR g2 IN(t, b) OUT(y)\nOR g3 IN(a, 0) OUT(z)\n");
+    let folded = program.fold_constants();
+    assert!(folded.validate().is_ok());
+    assert!(program.equivalent(&folded).unwrap());
+    assert!(!folded.components.iter().any(|component| component.identifier == "g1"));
+    let g3 = folded.components.iter().find(|component| component.identifier == "g3").unwrap();
+    assert_eq!(g3.gate_type, GateType::Buf);
+}
+
+#[test]
+fn folding_keeps_gates_that_were_unread_before_folding() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g IN(a, b) OUT(y)\nXOR spare IN(a, b) OUT(unused)\n");
+    let folded = program.fold_constants();
+    assert!(folded.components.iter().any(|component| component.identifier == "spare"));
+}
+
+#[test]
+fn folding_keeps_subcircuit_inputs_connected() {
+    let program = parse("SUBCIRCUIT masked\nINPUTS a, b\nOUTPUTS y\nAND m IN(b, 0) OUT(t)\nOR o IN(a, t) OUT(y)\nEND\nINPUTS x, z\nOUTPUTS q\nmasked IN(x, z) OUT(q)\n");
+    let folded = program.fold_constants();
+    assert!(folded.validate().is_ok());
+    assert!(program.equivalent(&folded).unwrap());
+}
+
+#[test]
+fn flattening_rejects_names_containing_the_hierarchy_separator() {
+    let config = LexerConfig {
+        identifier_continue: |c| c.is_ascii_alphanumeric() || c == '_' || c == '.',
+        ..LexerConfig::default()
+    };
+    let source = "SUBCIRCUIT pass\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS o, q\npass IN(x) OUT(o)\nBUF pass_0.b IN(x) OUT(q)\n";
+    let tokens = Lexer::with_config(source.to_string(), config).tokenize().unwrap();
+    let program = Parser::new(tokens).parse_program().unwrap();
+    assert!(matches!(program.flatten(), Err(SimError::SeparatorInName(name)) if name == "pass_0.b"));
+}
+
+/// Returns the gate type and inputs of the component driving a signal
+fn driver(program: &Program, signal: &str) -> (GateType, Vec<Signal>) {
+    let component = program.components.iter()
+        .find(|component| component.outputs.iter().any(|output| output == signal))
+        .expect("signal should have a driver");
+    (component.gate_type.clone(), component.inputs.clone())
+}
+
+#[test]
+fn simplifying_removes_an_even_chain_of_inverters() {
+    let program = parse("INPUTS a, b\nOUTPUTS y, z\nNOT n1 IN(a) OUT(t1)\nNOT n2 IN(t1) OUT(t2)\nNOT n3 IN(t2) OUT(t3)\n\
+        NOT n4 IN(t3) OUT(y)\nAND g IN(t2, b) OUT(z)\n");
+    let simplified = program.simplify_inverters();
+    assert!(program.equivalent(&simplified).unwrap());
+    assert!(!simplified.components.iter().any(|component| component.gate_type == GateType::Not));
+    assert_eq!(driver(&simplified, "y"), (GateType::Buf, vec![Signal::Wire("a".to_string())]));
+    assert_eq!(driver(&simplified, "z").1, [Signal::Wire("a".to_string()), Signal::Wire("b".to_string())]);
+}
+
+#[test]
+fn simplifying_leaves_one_inverter_of_an_odd_chain() {
+    let program = parse("INPUTS a\nOUTPUTS y\nNOT n1 IN(a) OUT(t1)\nNOT n2 IN(t1) OUT(t2)\nNOT n3 IN(t2) OUT(y)\n");
+    let simplified = program.simplify_inverters();
+    assert!(program.equivalent(&simplified).unwrap());
+    assert_eq!(simplified.components.len(), 1);
+    assert_eq!(driver(&simplified, "y"), (GateType::Not, vec![Signal::Wire("a".to_string())]));
+}
+
+#[test]
+fn simplifying_keeps_intermediate_signals_that_are_outputs() {
+    let program = parse("INPUTS a\nOUTPUTS t1, y\nNOT n1 IN(a) OUT(t1)\nNOT n2 IN(t1) OUT(y)\n");
+    let simplified = program.simplify_inverters();
+    assert!(program.equivalent(&simplified).unwrap());
+    assert_eq!(driver(&simplified, "t1"), (GateType::Not, vec![Signal::Wire("a".to_string())]));
+    assert_eq!(driver(&simplified, "y"), (GateType::Buf, vec![Signal::Wire("a".to_string())]));
+}
+
+#[test]
+fn minimizing_renames_internal_signals_around_existing_names() {
+    // n0 to n2 are already ports, and the internal wire n3 is itself renamed
+    let program = parse("INPUTS a, n0\nOUTPUTS y, n2\nCONST n1 = 1\nAND g1 IN(a, n0) OUT(t)\nOR g2 IN(t, n1) OUT(n3)\n\
+        XOR g3 IN(n3, a) OUT(u)\nNAND g4 IN(u, t) OUT(y)\nBUF g5 IN(t) OUT(n2)\n");
+    let (minimized, renames) = program.minimize_signals();
+    let expected = HashMap::from([("t", "n3"), ("n3", "n4"), ("u", "n5")]);
+    assert_eq!(renames, expected.iter().map(|(old, new)| (old.to_string(), new.to_string())).collect());
+    assert_eq!(minimized.inputs, program.inputs);
+    assert_eq!(minimized.outputs, program.outputs);
+    assert_eq!(minimized.constants, program.constants);
+    let signals: Vec<String> = minimized.signals().into_iter().collect();
+    assert_eq!(signals, ["a", "n0", "n1", "n2", "n3", "n4", "n5", "y"]);
+    assert!(minimized.validate().is_ok());
+    assert!(program.equivalent(&minimized).unwrap());
+}
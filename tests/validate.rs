@@ -0,0 +1,126 @@
+use osd::lang::Program;
+use osd::sim::SimError;
+use osd::validate::{Lint, SemanticError};
+
+/// Parses source code that is expected to be well formed, without validating it
+fn parse(source: &str) -> Program {
+    source.parse().expect("source should parse")
+}
+
+#[test]
+fn unread_subcircuit_input_is_an_unconnected_port() {
+    let program = parse("SUBCIRCUIT half\nINPUTS a, b, spare\nOUTPUTS y\nAND g IN(a, b) OUT(y)\nEND\nINPUTS x, z\nOUTPUTS o\nhalf IN(x, z, z) OUT(o)\n");
+    let errors = program.validate().unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [SemanticError::UnconnectedPort { subcircuit, port }] if subcircuit == "half" && port == "spare"
+    ));
+    assert!(!program.lint().iter().any(|lint| matches!(lint, Lint::UnusedInput { .. })));
+}
+
+#[test]
+fn subcircuit_input_read_through_an_alias_is_connected() {
+    let program = parse("SUBCIRCUIT half\nINPUTS a, b\nOUTPUTS y\nALIAS k = b\nAND g IN(a, k) OUT(y)\nEND\nINPUTS x\nOUTPUTS o\nhalf IN(x, x) OUT(o)\n");
+    assert!(program.validate().is_ok());
+}
+
+#[test]
+fn unread_main_input_is_only_a_lint() {
+    let program = parse("INPUTS a, unused\nOUTPUTS y\nBUF b IN(a) OUT(y)\n");
+    assert!(program.validate().is_ok());
+    assert_eq!(program.lint(), vec![Lint::UnusedInput { name: "unused".to_string() }]);
+}
+
+#[test]
+fn undriven_output_points_at_its_declaration() {
+    let program = parse("SUBCIRCUIT pass\nINPUTS a\nOUTPUTS y, spare\nBUF b IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS o, p[2]\nBUF b IN(x) OUT(o)\n");
+    let errors = program.validate().unwrap_err();
+    let undriven: Vec<(&str, Option<&str>, usize, usize)> = errors.iter()
+        .filter_map(|error| match error {
+            SemanticError::UndrivenOutput { signal, subcircuit, location } =>
+                Some((signal.as_str(), subcircuit.as_deref(), location.line, location.column)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(undriven, vec![("spare", Some("pass"), 3, 12), ("p[0]", None, 7, 12), ("p[1]", None, 7, 12)]);
+    assert_eq!(errors[0].location().map(|location| location.line), Some(3));
+}
+
+#[test]
+fn self_instantiating_subcircuit_is_recursive() {
+    let program = parse("SUBCIRCUIT loop\nINPUTS a\nOUTPUTS y\nloop IN(a) OUT(y)\nEND\nINPUTS x\nOUTPUTS o\nloop IN(x) OUT(o)\n");
+    let errors = program.validate().unwrap_err();
+    assert!(matches!(&errors[0], SemanticError::RecursiveSubcircuit { chain } if chain == &["loop", "loop"]));
+}
+
+#[test]
+fn mutually_instantiating_subcircuits_are_recursive() {
+    let program = parse("SUBCIRCUIT ping\nINPUTS a\nOUTPUTS y\npong IN(a) OUT(y)\nEND\nSUBCIRCUIT pong\nINPUTS a\nOUTPUTS y\nping IN(a) OUT(y)\nEND\n\
+        INPUTS x\nOUTPUTS o\nping IN(x) OUT(o)\n");
+    let errors = program.validate().unwrap_err();
+    let chains: Vec<&Vec<String>> = errors.iter()
+        .filter_map(|error| match error {
+            SemanticError::RecursiveSubcircuit { chain } => Some(chain),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(chains, [&["ping", "pong", "ping"]]);
+    assert!(matches!(program.flatten(), Err(SimError::RecursiveSubcircuit { chain }) if chain == ["ping", "pong", "ping"]));
+}
+
+#[test]
+fn name_declared_as_input_and_output_collides() {
+    let errors = parse("INPUTS a\nOUTPUTS a\n").validate().unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [SemanticError::InputOutputCollision { name, subcircuit: None }] if name == "a"
+    ), "{:?}", errors);
+    assert!(parse("INPUTS a\nOUTPUTS b\nBUF g IN(a) OUT(b)\n").validate().is_ok());
+}
+
+#[test]
+fn subcircuit_port_declared_as_input_and_output_collides() {
+    let program = parse("SUBCIRCUIT wire\nINPUTS a\nOUTPUTS a\nEND\nINPUTS x\nOUTPUTS y\nwire IN(x) OUT(y)\n");
+    let errors = program.validate().unwrap_err();
+    assert!(errors.iter().any(|error| matches!(
+        error,
+        SemanticError::InputOutputCollision { name, subcircuit: Some(subcircuit) } if name == "a" && subcircuit == "wire"
+    )), "{:?}", errors);
+}
+
+#[test]
+fn defined_but_never_instantiated_subcircuit_is_a_lint() {
+    let program = parse("SUBCIRCUIT used\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\nSUBCIRCUIT adder\nINPUTS a, b\nOUTPUTS s\nXOR x IN(a, b) OUT(s)\nEND\n\
+        INPUTS x\nOUTPUTS o\nused IN(x) OUT(o)\n");
+    assert!(program.validate().is_ok());
+    assert!(matches!(
+        program.lint().as_slice(),
+        [Lint::UnusedSubcircuit { name, location }] if name == "adder" && location.line == 6
+    ), "{:?}", program.lint());
+}
+
+#[test]
+fn subcircuit_only_used_by_an_unused_subcircuit_is_flagged_too() {
+    let program = parse("SUBCIRCUIT inner\nINPUTS a\nOUTPUTS y\nBUF b IN(a) OUT(y)\nEND\nSUBCIRCUIT outer\nINPUTS a\nOUTPUTS y\ninner IN(a) OUT(y)\nEND\n\
+        INPUTS x\nOUTPUTS o\nBUF b IN(x) OUT(o)\n");
+    let unused: Vec<String> = program.lint().into_iter()
+        .filter_map(|lint| match lint {
+            Lint::UnusedSubcircuit { name, .. } => Some(name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(unused, ["inner", "outer"]);
+}
+
+#[test]
+fn gates_with_no_path_to_an_output_are_unreachable() {
+    let program = parse("INPUTS a, b\nOUTPUTS y\nAND g IN(a, b) OUT(y)\nOR dead IN(a, b) OUT(t)\nNOT deader IN(t) OUT(u)\n");
+    assert!(program.validate().is_ok());
+    let unreachable: Vec<(String, Option<String>)> = program.lint().into_iter()
+        .filter_map(|lint| match lint {
+            Lint::UnreachableGate { identifier, subcircuit } => Some((identifier, subcircuit)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(unreachable, [("dead".to_string(), None), ("deader".to_string(), None)]);
+}